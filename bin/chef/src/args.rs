@@ -2,6 +2,8 @@ use std::net::{IpAddr, SocketAddr};
 
 use clap::Parser;
 
+use crate::service::{VerificationConfig, ZoneDefaults};
+
 #[derive(Parser)]
 #[clap(about, version)]
 pub(super) struct Args {
@@ -34,4 +36,12 @@ pub(super) struct Args {
   pub(super) redis_addr: IpAddr,
   #[arg(long, env = "CHEF_REDIS_PORT", default_value = "6379")]
   pub(super) redis_port: u16,
+  /// Hours deleted zones can be restored before they are purged, zones are
+  /// purged right away if unset
+  #[arg(long, env = "CHEF_ZONE_GRACE_PERIOD")]
+  pub(super) zone_grace_period: Option<u64>,
+  #[command(flatten)]
+  pub(super) zone_defaults: ZoneDefaults,
+  #[command(flatten)]
+  pub(super) verification: VerificationConfig,
 }
@@ -2,12 +2,14 @@ use std::sync::Arc;
 
 use session::{SessionContext, SessionStore};
 
-use crate::service::{RecordService, ZoneService};
+use crate::service::{RecordService, VerificationService, ViewService, ZoneService};
 
 #[derive(Clone)]
 pub(crate) struct Context {
   pub(crate) zone_service: Arc<ZoneService>,
   pub(crate) record_service: Arc<RecordService>,
+  pub(crate) view_service: Arc<ViewService>,
+  pub(crate) verification_service: Arc<VerificationService>,
   pub(crate) session_store: SessionStore,
 }
 
@@ -0,0 +1,284 @@
+use std::fmt::{Display, Formatter};
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use sea_orm::{DbErr, SqlErr, TransactionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::error;
+
+/// Answered as RFC 7807 problem details, clients are meant to match on `code`
+/// which stays the same across releases.
+#[derive(Debug)]
+pub(crate) struct ApiError {
+  status: StatusCode,
+  code: &'static str,
+  detail: String,
+  errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FieldError {
+  /// Dotted path into the request body, e.g. `health_check.port`
+  field: String,
+  code: &'static str,
+  message: String,
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+  r#type: &'static str,
+  title: &'static str,
+  status: u16,
+  code: &'static str,
+  detail: &'a str,
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  errors: &'a [FieldError],
+}
+
+impl ApiError {
+  fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+    Self {
+      status,
+      code,
+      detail: detail.into(),
+      errors: Vec::new(),
+    }
+  }
+
+  pub(crate) fn not_found(code: &'static str, detail: impl Into<String>) -> Self {
+    Self::new(StatusCode::NOT_FOUND, code, detail)
+  }
+
+  pub(crate) fn forbidden(code: &'static str, detail: impl Into<String>) -> Self {
+    Self::new(StatusCode::FORBIDDEN, code, detail)
+  }
+
+  pub(crate) fn conflict(code: &'static str, detail: impl Into<String>) -> Self {
+    Self::new(StatusCode::CONFLICT, code, detail)
+  }
+
+  /// Something the request depends on, e.g. a resolver, failed.
+  pub(crate) fn bad_gateway(code: &'static str, detail: impl Into<String>) -> Self {
+    Self::new(StatusCode::BAD_GATEWAY, code, detail)
+  }
+
+  pub(crate) fn internal() -> Self {
+    Self::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "internal_error",
+      "unexpected error, try again later",
+    )
+  }
+
+  /// Fails validation of a single field.
+  pub(crate) fn field(
+    field: impl Into<String>,
+    code: &'static str,
+    message: impl Into<String>,
+  ) -> Self {
+    let mut errors = FieldErrors::default();
+    errors.add(field, code, message);
+    errors.into_error()
+  }
+
+  /// Code of the first field error, or of the error itself.
+  #[cfg(test)]
+  pub(crate) fn code(&self) -> &'static str {
+    self.errors.first().map_or(self.code, |error| error.code)
+  }
+
+  /// Locates the error inside a larger request, e.g. a line of a zone file.
+  pub(crate) fn within(mut self, location: &str) -> Self {
+    if self.errors.is_empty() {
+      self.detail = format!("{location}: {}", self.detail);
+    }
+    for error in &mut self.errors {
+      error.field = format!("{location}.{}", error.field);
+    }
+    self
+  }
+}
+
+impl Display for ApiError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} ({}): {}", self.status, self.code, self.detail)
+  }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    let problem = Problem {
+      r#type: "about:blank",
+      title: self.status.canonical_reason().unwrap_or_default(),
+      status: self.status.as_u16(),
+      code: self.code,
+      detail: &self.detail,
+      errors: &self.errors,
+    };
+
+    let mut response = (self.status, axum::Json(problem)).into_response();
+    response.headers_mut().insert(
+      header::CONTENT_TYPE,
+      HeaderValue::from_static("application/problem+json"),
+    );
+    response
+  }
+}
+
+/// Constraint violations are the client's fault, anything else is logged and
+/// hidden behind a 500.
+impl From<DbErr> for ApiError {
+  fn from(err: DbErr) -> Self {
+    match err.sql_err() {
+      Some(SqlErr::UniqueConstraintViolation(_)) => {
+        Self::conflict("already_exists", "a resource with the same name exists")
+      }
+      Some(SqlErr::ForeignKeyConstraintViolation(_)) => Self::conflict(
+        "still_referenced",
+        "the resource is referenced by or references another one",
+      ),
+      _ => {
+        error!("Unexpected database error: {}", err);
+        Self::internal()
+      }
+    }
+  }
+}
+
+impl From<TransactionError<ApiError>> for ApiError {
+  fn from(err: TransactionError<ApiError>) -> Self {
+    match err {
+      TransactionError::Connection(err) => err.into(),
+      TransactionError::Transaction(err) => err,
+    }
+  }
+}
+
+impl From<anyhow::Error> for ApiError {
+  fn from(err: anyhow::Error) -> Self {
+    let err = match err.downcast::<ApiError>() {
+      Ok(err) => return err,
+      Err(err) => err,
+    };
+    let err = match err.downcast::<TransactionError<ApiError>>() {
+      Ok(err) => return err.into(),
+      Err(err) => err,
+    };
+    let err = match err.downcast::<TransactionError<DbErr>>() {
+      Ok(TransactionError::Connection(err) | TransactionError::Transaction(err)) => {
+        return err.into()
+      }
+      Err(err) => err,
+    };
+    match err.downcast::<DbErr>() {
+      Ok(err) => err.into(),
+      Err(err) => {
+        error!("Unable to handle request: {}", err);
+        Self::internal()
+      }
+    }
+  }
+}
+
+/// Collects the validation errors of a request body.
+#[derive(Default)]
+pub(crate) struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+  pub(crate) fn add(
+    &mut self,
+    field: impl Into<String>,
+    code: &'static str,
+    message: impl Into<String>,
+  ) {
+    self.0.push(FieldError {
+      field: field.into(),
+      code,
+      message: message.into(),
+    });
+  }
+
+  fn into_error(self) -> ApiError {
+    ApiError {
+      errors: self.0,
+      ..ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "validation_failed",
+        "the request body is invalid",
+      )
+    }
+  }
+
+  /// Fails with 422 if any error was added.
+  pub(crate) fn finish(self) -> Result<(), ApiError> {
+    if self.0.is_empty() {
+      Ok(())
+    } else {
+      Err(self.into_error())
+    }
+  }
+}
+
+/// Like `axum::Json`, but rejects bodies with problem details naming the
+/// offending field.
+pub(crate) struct Json<T>(pub(crate) T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Json<T> {
+  type Rejection = ApiError;
+
+  async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+    let json = req
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|content_type| content_type.to_str().ok())
+      .and_then(|content_type| content_type.split(';').next())
+      .map(|mime| mime.trim().to_ascii_lowercase())
+      .is_some_and(|mime| {
+        mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+      });
+    if !json {
+      return Err(ApiError::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "unsupported_media_type",
+        "expected a body of type application/json",
+      ));
+    }
+
+    let body = Bytes::from_request(req, state)
+      .await
+      .map_err(|err| ApiError::new(err.status(), "unreadable_body", err.body_text()))?;
+
+    let mut deserializer = serde_json::Deserializer::from_slice(&body);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+      if err.inner().is_data() {
+        ApiError::field(
+          err.path().to_string(),
+          "invalid_value",
+          err.inner().to_string(),
+        )
+      } else {
+        malformed(err.into_inner())
+      }
+    })?;
+    deserializer.end().map_err(malformed)?;
+
+    Ok(Self(value))
+  }
+}
+
+fn malformed(err: serde_json::Error) -> ApiError {
+  ApiError::new(StatusCode::BAD_REQUEST, "malformed_body", err.to_string())
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+  fn into_response(self) -> Response {
+    axum::Json(self.0).into_response()
+  }
+}
@@ -1,5 +1,6 @@
 use std::future::IntoFuture;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bb8_redis::bb8::Pool;
 use bb8_redis::RedisConnectionManager;
@@ -19,10 +20,11 @@ use utils::shutdown_signal;
 use crate::args::Args;
 use crate::ctx::Context;
 use crate::routes::router;
-use crate::service::{RecordService, ZoneService};
+use crate::service::{RecordService, VerificationService, ViewService, ZoneService};
 
 mod args;
 mod ctx;
+mod error;
 mod routes;
 mod service;
 
@@ -70,14 +72,26 @@ async fn main() -> anyhow::Result<()> {
     Pool::builder().build(manager).await?
   };
 
-  let zone_service = Arc::new(ZoneService::new(db.clone()));
-  let record_service = Arc::new(RecordService::new(db));
+  let zone_service = Arc::new(ZoneService::new(
+    db.clone(),
+    args
+      .zone_grace_period
+      .map(|hours| Duration::from_secs(hours * 3600)),
+    args.zone_defaults,
+  ));
+  tokio::spawn(zone_service.clone().run_purge());
+  let record_service = Arc::new(RecordService::new(db.clone()));
+  let view_service = Arc::new(ViewService::new(db.clone()));
+  let verification_service = Arc::new(VerificationService::new(db, args.verification));
+  tokio::spawn(verification_service.clone().run_recheck());
   let session_store = SessionStore::new(redis_pool);
 
   let router = router()
     .with_state(Context {
       zone_service,
       record_service,
+      view_service,
+      verification_service,
       session_store,
     })
     .layer(TraceLayer::new_for_http())
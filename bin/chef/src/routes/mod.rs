@@ -1,19 +1,26 @@
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::Router;
 
 use entity::prelude::{RecordA, RecordAaaa, RecordCname, RecordMx, RecordNs, RecordTxt};
 
 use crate::ctx::Context;
 use crate::routes::record::{
-  create_record, delete_record, get_record, list_records, modify_record,
+  apply_changeset, create_any_record, create_record, delete_any_record, delete_record,
+  get_any_record, get_record, list_any_records, list_records, modify_any_record, modify_record,
+  replace_records,
+};
+use crate::routes::view::{create_view, delete_view, get_view, list_views, modify_view};
+use crate::routes::zone::{
+  create_zone, delete_zone, export_zone, get_zone, import_zone, list_zones, modify_zone,
+  restore_zone, verify_zone,
 };
-use crate::routes::zone::{create_zone, delete_zone, get_zone, list_zones};
 use crate::service::{
   RecordARequest, RecordAaaaRequest, RecordCnameRequest, RecordMxRequest, RecordNsRequest,
   RecordTxtRequest,
 };
 
 mod record;
+mod view;
 mod zone;
 
 pub(super) fn router() -> Router<Context> {
@@ -21,8 +28,34 @@ pub(super) fn router() -> Router<Context> {
     .route("/api/dns/v1/zone", get(list_zones).post(create_zone))
     .route(
       "/api/dns/v1/zone/:zone_id",
-      get(get_zone).delete(delete_zone),
+      get(get_zone).delete(delete_zone).put(modify_zone),
+    )
+    .route("/api/dns/v1/zone/:zone_id/restore", post(restore_zone))
+    .route("/api/dns/v1/zone/:zone_id/verify", post(verify_zone))
+    .route("/api/dns/v1/zone/:zone_id/import", post(import_zone))
+    .route("/api/dns/v1/zone/:zone_id/export", get(export_zone))
+    .route(
+      "/api/dns/v1/zone/:zone_id/view",
+      get(list_views).post(create_view),
+    )
+    .route(
+      "/api/dns/v1/view/:view_id",
+      get(get_view).delete(delete_view).put(modify_view),
+    )
+    .route(
+      "/api/dns/v1/zone/:zone_id/record",
+      get(list_any_records)
+        .post(create_any_record)
+        .put(replace_records),
+    )
+    .route("/api/dns/v1/zone/:zone_id/changeset", post(apply_changeset))
+    .route(
+      "/api/dns/v1/zone/:zone_id/record/:record_id",
+      get(get_any_record)
+        .delete(delete_any_record)
+        .put(modify_any_record),
     )
+    // per type aliases of the routes above
     .route(
       "/api/dns/v1/zone/:zone_id/record/a",
       get(list_records::<RecordA>).post(create_record::<RecordARequest, _>),
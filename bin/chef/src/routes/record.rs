@@ -1,9 +1,7 @@
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
-use axum::Json;
 use sea_orm::{ActiveModelBehavior, ActiveModelTrait, EntityTrait, PrimaryKeyTrait, Related};
 use serde::{Deserialize, Serialize};
-use tracing::error;
 use uuid::Uuid;
 
 use entity::prelude::Record;
@@ -11,7 +9,11 @@ use entity::record;
 use session::{Session, ROLE_DNS};
 
 use crate::ctx::Context;
-use crate::service::RecordRequestTrait;
+use crate::error::{ApiError, Json};
+use crate::service::{
+  AnyRecord, AnyRecordRequest, ChangeResult, Changeset, DesiredRecord, RecordCommonReq,
+  RecordRequestTrait, ZoneDiff,
+};
 
 #[derive(Serialize)]
 pub(crate) struct RecordResponse<E: EntityTrait>
@@ -24,6 +26,14 @@ where
   specific: E::Model,
 }
 
+#[derive(Serialize)]
+pub(crate) struct AnyRecordResponse {
+  #[serde(flatten)]
+  common: record::Model,
+  #[serde(flatten)]
+  specific: AnyRecord,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct RecordRequest<S> {
   #[serde(flatten)]
@@ -33,27 +43,24 @@ pub(crate) struct RecordRequest<S> {
 }
 
 #[derive(Deserialize)]
-pub(crate) struct RecordCommonReq {
-  name: String,
-  ttl: Option<u32>,
+pub(crate) struct WriteParams {
+  /// Changes the ttl of the other records in the RRset instead of failing
+  #[serde(default)]
+  harmonize_ttl: bool,
 }
 
 pub(crate) async fn list_records<E: EntityTrait>(
   State(ctx): State<Context>,
   Path(zone_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
-) -> Result<Json<Vec<RecordResponse<E>>>, StatusCode>
+) -> Result<Json<Vec<RecordResponse<E>>>, ApiError>
 where
   Record: Related<E>,
 {
   let records = ctx
     .record_service
     .list::<E>(session.user_id, zone_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to list records: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?
+    .await?
     .into_iter()
     .map(|(common, specific)| RecordResponse { common, specific })
     .collect();
@@ -65,21 +72,15 @@ pub(crate) async fn get_record<E: EntityTrait>(
   State(ctx): State<Context>,
   Path(record_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
-) -> Result<Json<RecordResponse<E>>, StatusCode>
+) -> Result<Json<RecordResponse<E>>, ApiError>
 where
   Record: Related<E>,
   <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType: From<Uuid>,
 {
-  let record = ctx
+  let (common, specific) = ctx
     .record_service
     .by_id::<E>(session.user_id, record_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to get record: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-  let (common, specific) = record.ok_or(StatusCode::NOT_FOUND)?;
+    .await?;
 
   Ok(Json(RecordResponse { common, specific }))
 }
@@ -91,8 +92,9 @@ pub(crate) async fn create_record<
   State(ctx): State<Context>,
   Path(zone_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
+  Query(params): Query<WriteParams>,
   Json(req): Json<RecordRequest<R>>,
-) -> Result<Json<RecordResponse<<A as ActiveModelTrait>::Entity>>, StatusCode>
+) -> Result<Json<RecordResponse<<A as ActiveModelTrait>::Entity>>, ApiError>
 where
   Record: Related<A::Entity>,
   <<A::Entity as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType: From<Uuid>,
@@ -103,18 +105,15 @@ where
     .create(
       session.user_id,
       zone_id,
-      req.common.name,
-      req.common.ttl,
+      req.common,
       req.specific,
+      params.harmonize_ttl,
     )
-    .await
-    .map_err(|err| {
-      error!("Unable to create record: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
 
   Ok(Json(RecordResponse { common, specific }))
 }
+
 pub(crate) async fn modify_record<
   R: RecordRequestTrait<A> + Send + 'static,
   A: ActiveModelTrait + ActiveModelBehavior + Send,
@@ -122,8 +121,9 @@ pub(crate) async fn modify_record<
   State(ctx): State<Context>,
   Path(record_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
+  Query(params): Query<WriteParams>,
   Json(req): Json<RecordRequest<R>>,
-) -> Result<Json<RecordResponse<<A as ActiveModelTrait>::Entity>>, StatusCode>
+) -> Result<Json<RecordResponse<<A as ActiveModelTrait>::Entity>>, ApiError>
 where
   Record: Related<A::Entity>,
   <<A::Entity as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType: From<Uuid>,
@@ -134,15 +134,11 @@ where
     .modify(
       session.user_id,
       record_id,
-      req.common.name,
-      req.common.ttl,
+      req.common,
       req.specific,
+      params.harmonize_ttl,
     )
-    .await
-    .map_err(|err| {
-      error!("Unable to modify record: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
 
   Ok(Json(RecordResponse { common, specific }))
 }
@@ -151,23 +147,138 @@ pub(crate) async fn delete_record<E: EntityTrait>(
   State(ctx): State<Context>,
   Path(record_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
-) -> Result<StatusCode, StatusCode>
+) -> Result<StatusCode, ApiError>
 where
   Record: Related<E>,
   <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType: From<Uuid>,
 {
-  let found = ctx
+  ctx
     .record_service
     .delete::<E>(session.user_id, record_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to delete record: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-  if found {
-    Ok(StatusCode::NO_CONTENT)
-  } else {
-    Err(StatusCode::NOT_FOUND)
-  }
+    .await?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_any_records(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<Vec<AnyRecordResponse>>, ApiError> {
+  let records = ctx
+    .record_service
+    .list_any(session.user_id, zone_id)
+    .await?
+    .into_iter()
+    .map(|(common, specific)| AnyRecordResponse { common, specific })
+    .collect();
+
+  Ok(Json(records))
+}
+
+pub(crate) async fn get_any_record(
+  State(ctx): State<Context>,
+  Path((zone_id, record_id)): Path<(Uuid, Uuid)>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<AnyRecordResponse>, ApiError> {
+  let (common, specific) = ctx
+    .record_service
+    .by_id_any(session.user_id, zone_id, record_id)
+    .await?;
+
+  Ok(Json(AnyRecordResponse { common, specific }))
+}
+
+pub(crate) async fn create_any_record(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<WriteParams>,
+  Json(req): Json<RecordRequest<AnyRecordRequest>>,
+) -> Result<Json<AnyRecordResponse>, ApiError> {
+  let (common, specific) = ctx
+    .record_service
+    .create_any(
+      session.user_id,
+      zone_id,
+      req.common,
+      req.specific,
+      params.harmonize_ttl,
+    )
+    .await?;
+
+  Ok(Json(AnyRecordResponse { common, specific }))
+}
+
+pub(crate) async fn modify_any_record(
+  State(ctx): State<Context>,
+  Path((zone_id, record_id)): Path<(Uuid, Uuid)>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<WriteParams>,
+  Json(req): Json<RecordRequest<AnyRecordRequest>>,
+) -> Result<Json<AnyRecordResponse>, ApiError> {
+  let (common, specific) = ctx
+    .record_service
+    .modify_any(
+      session.user_id,
+      zone_id,
+      record_id,
+      req.common,
+      req.specific,
+      params.harmonize_ttl,
+    )
+    .await?;
+
+  Ok(Json(AnyRecordResponse { common, specific }))
+}
+
+pub(crate) async fn delete_any_record(
+  State(ctx): State<Context>,
+  Path((zone_id, record_id)): Path<(Uuid, Uuid)>,
+  session: Session<ROLE_DNS>,
+) -> Result<StatusCode, ApiError> {
+  ctx
+    .record_service
+    .delete_any(session.user_id, zone_id, record_id)
+    .await?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn apply_changeset(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<WriteParams>,
+  Json(changeset): Json<Changeset>,
+) -> Result<Json<Vec<ChangeResult>>, ApiError> {
+  let results = ctx
+    .record_service
+    .apply_changeset(session.user_id, zone_id, changeset, params.harmonize_ttl)
+    .await?;
+
+  Ok(Json(results))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReplaceParams {
+  /// Only answers what would change
+  #[serde(default)]
+  dry_run: bool,
+}
+
+/// Takes the complete desired set of records of the zone.
+pub(crate) async fn replace_records(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<ReplaceParams>,
+  Json(records): Json<Vec<DesiredRecord>>,
+) -> Result<Json<ZoneDiff>, ApiError> {
+  let diff = ctx
+    .record_service
+    .replace(session.user_id, zone_id, records, params.dry_run)
+    .await?;
+
+  Ok(Json(diff))
 }
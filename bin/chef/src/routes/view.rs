@@ -0,0 +1,75 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use ipnet::IpNet;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use entity::view;
+use session::{Session, ROLE_DNS};
+
+use crate::ctx::Context;
+use crate::error::{ApiError, Json};
+
+#[derive(Deserialize)]
+pub(crate) struct ViewRequest {
+  name: String,
+  networks: Vec<IpNet>,
+}
+
+pub(crate) async fn list_views(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<Vec<view::Model>>, ApiError> {
+  let views = ctx.view_service.list(session.user_id, zone_id).await?;
+
+  Ok(Json(views))
+}
+
+pub(crate) async fn get_view(
+  State(ctx): State<Context>,
+  Path(view_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<view::Model>, ApiError> {
+  let view = ctx.view_service.by_id(session.user_id, view_id).await?;
+
+  Ok(Json(view))
+}
+
+pub(crate) async fn create_view(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Json(req): Json<ViewRequest>,
+) -> Result<Json<view::Model>, ApiError> {
+  let view = ctx
+    .view_service
+    .create(session.user_id, zone_id, req.name, req.networks)
+    .await?;
+
+  Ok(Json(view))
+}
+
+pub(crate) async fn modify_view(
+  State(ctx): State<Context>,
+  Path(view_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Json(req): Json<ViewRequest>,
+) -> Result<Json<view::Model>, ApiError> {
+  let view = ctx
+    .view_service
+    .modify(session.user_id, view_id, req.name, req.networks)
+    .await?;
+
+  Ok(Json(view))
+}
+
+pub(crate) async fn delete_view(
+  State(ctx): State<Context>,
+  Path(view_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<StatusCode, ApiError> {
+  ctx.view_service.delete(session.user_id, view_id).await?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
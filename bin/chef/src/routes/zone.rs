@@ -1,32 +1,42 @@
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::Json;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
 use serde::Deserialize;
-use tracing::error;
 use uuid::Uuid;
 
 use entity::zone;
 use session::{Session, ROLE_DNS};
 
 use crate::ctx::Context;
+use crate::error::{ApiError, Json};
+use crate::service::{ImportMode, ImportReport, ZoneSettings};
 
 #[derive(Deserialize)]
 pub(crate) struct CreateZoneRequest {
   name: String,
+  /// Address of the server the zone is transferred from, if it is mastered
+  /// elsewhere
+  primary_server: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListZonesParams {
+  /// Lists the zones deleted but not yet purged instead
+  #[serde(default)]
+  deleted: bool,
 }
 
 pub(crate) async fn list_zones(
   State(ctx): State<Context>,
   session: Session<ROLE_DNS>,
-) -> Result<Json<Vec<zone::Model>>, StatusCode> {
+  Query(params): Query<ListZonesParams>,
+) -> Result<Json<Vec<zone::Model>>, ApiError> {
   let zones = ctx
     .zone_service
-    .list(session.user_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to list zones: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .list(session.user_id, params.deleted)
+    .await?;
 
   Ok(Json(zones))
 }
@@ -35,17 +45,8 @@ pub(crate) async fn get_zone(
   State(ctx): State<Context>,
   Path(zone_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
-) -> Result<Json<zone::Model>, StatusCode> {
-  let zone = ctx
-    .zone_service
-    .by_id(session.user_id, zone_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to get zone: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-  let zone = zone.ok_or(StatusCode::NOT_FOUND)?;
+) -> Result<Json<zone::Model>, ApiError> {
+  let zone = ctx.zone_service.by_id(session.user_id, zone_id).await?;
 
   Ok(Json(zone))
 }
@@ -54,15 +55,36 @@ pub(crate) async fn create_zone(
   State(ctx): State<Context>,
   session: Session<ROLE_DNS>,
   Json(req): Json<CreateZoneRequest>,
-) -> Result<Json<zone::Model>, StatusCode> {
+) -> Result<Json<zone::Model>, ApiError> {
+  // either an ip address or an ip address with port
+  if let Some(primary_server) = &req.primary_server {
+    if primary_server.parse::<SocketAddr>().is_err() && primary_server.parse::<IpAddr>().is_err() {
+      return Err(ApiError::field(
+        "primary_server",
+        "invalid_address",
+        "expected an ip address, optionally with a port",
+      ));
+    }
+  }
+
   let zone = ctx
     .zone_service
-    .create(session.user_id, req.name)
-    .await
-    .map_err(|err| {
-      error!("Unable to create zone: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .create(session.user_id, req.name, req.primary_server)
+    .await?;
+
+  Ok(Json(zone))
+}
+
+pub(crate) async fn modify_zone(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Json(req): Json<ZoneSettings>,
+) -> Result<Json<zone::Model>, ApiError> {
+  let zone = ctx
+    .zone_service
+    .modify(session.user_id, zone_id, req)
+    .await?;
 
   Ok(Json(zone))
 }
@@ -71,19 +93,91 @@ pub(crate) async fn delete_zone(
   State(ctx): State<Context>,
   Path(zone_id): Path<Uuid>,
   session: Session<ROLE_DNS>,
-) -> Result<StatusCode, StatusCode> {
-  let found = ctx
+) -> Result<StatusCode, ApiError> {
+  ctx.zone_service.delete(session.user_id, zone_id).await?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn restore_zone(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<zone::Model>, ApiError> {
+  let zone = ctx.zone_service.restore(session.user_id, zone_id).await?;
+
+  Ok(Json(zone))
+}
+
+/// Checks the zone is the owner's, see `VerificationService::check`.
+pub(crate) async fn verify_zone(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+) -> Result<Json<zone::Model>, ApiError> {
+  let zone = ctx
+    .verification_service
+    .verify(session.user_id, zone_id)
+    .await?;
+
+  Ok(Json(zone))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImportParams {
+  #[serde(default)]
+  mode: ImportMode,
+  #[serde(default)]
+  harmonize_ttl: bool,
+}
+
+/// Takes an RFC 1035 master file as body.
+pub(crate) async fn import_zone(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<ImportParams>,
+  content: String,
+) -> Result<Json<ImportReport>, ApiError> {
+  let report = ctx
+    .record_service
+    .import(
+      session.user_id,
+      zone_id,
+      content,
+      params.mode,
+      params.harmonize_ttl,
+    )
+    .await?;
+
+  Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ExportParams {
+  /// Exports what clients of this view are answered with
+  view: Option<Uuid>,
+}
+
+pub(crate) async fn export_zone(
+  State(ctx): State<Context>,
+  Path(zone_id): Path<Uuid>,
+  session: Session<ROLE_DNS>,
+  Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+  let (name, content) = ctx
     .zone_service
-    .delete(session.user_id, zone_id)
-    .await
-    .map_err(|err| {
-      error!("Unable to delete zone: {}", err);
-      StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-  if found {
-    Ok(StatusCode::NO_CONTENT)
-  } else {
-    Err(StatusCode::NOT_FOUND)
-  }
+    .export(session.user_id, zone_id, params.view)
+    .await?;
+
+  Ok((
+    [
+      (header::CONTENT_TYPE, "text/dns".to_string()),
+      (
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{name}.zone\""),
+      ),
+    ],
+    content,
+  ))
 }
@@ -0,0 +1,96 @@
+use sea_orm::{ConnectionTrait, EntityTrait, QuerySelect};
+use uuid::Uuid;
+
+use entity::prelude::{Record, View, Zone};
+use entity::{record, view, zone};
+
+use crate::error::ApiError;
+
+/// Fails with 404 if the zone does not exist or is deleted and with 403 if it
+/// belongs to someone else.
+pub(crate) async fn zone_access<C: ConnectionTrait>(
+  db: &C,
+  user_id: Uuid,
+  zone_id: Uuid,
+) -> Result<zone::Model, ApiError> {
+  check_zone(Zone::find_by_id(zone_id).one(db).await?, user_id)
+}
+
+/// Like `zone_access`, but locks the zone until the transaction ends, so
+/// records are written by one transaction at a time and the rules across
+/// them hold.
+pub(crate) async fn zone_write_access<C: ConnectionTrait>(
+  db: &C,
+  user_id: Uuid,
+  zone_id: Uuid,
+) -> Result<zone::Model, ApiError> {
+  check_zone(
+    Zone::find_by_id(zone_id).lock_exclusive().one(db).await?,
+    user_id,
+  )
+}
+
+fn check_zone(zone: Option<zone::Model>, user_id: Uuid) -> Result<zone::Model, ApiError> {
+  let zone = zone.ok_or_else(|| ApiError::not_found("zone_not_found", "no such zone"))?;
+
+  if zone.owner != user_id {
+    return Err(ApiError::forbidden(
+      "zone_forbidden",
+      "the zone belongs to someone else",
+    ));
+  }
+  if zone.deleted.is_some() {
+    return Err(ApiError::not_found(
+      "zone_deleted",
+      "the zone has been deleted, restore it first",
+    ));
+  }
+
+  Ok(zone)
+}
+
+pub(crate) async fn record_access<C: ConnectionTrait>(
+  db: &C,
+  user_id: Uuid,
+  record_id: Uuid,
+) -> Result<(record::Model, zone::Model), ApiError> {
+  let record = Record::find_by_id(record_id)
+    .one(db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("record_not_found", "no such record"))?;
+
+  let zone = zone_access(db, user_id, record.zone_id).await?;
+
+  Ok((record, zone))
+}
+
+/// Like `record_access`, but locks the zone of the record.
+pub(crate) async fn record_write_access<C: ConnectionTrait>(
+  db: &C,
+  user_id: Uuid,
+  record_id: Uuid,
+) -> Result<(record::Model, zone::Model), ApiError> {
+  let record = Record::find_by_id(record_id)
+    .one(db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("record_not_found", "no such record"))?;
+
+  let zone = zone_write_access(db, user_id, record.zone_id).await?;
+
+  Ok((record, zone))
+}
+
+pub(crate) async fn view_access<C: ConnectionTrait>(
+  db: &C,
+  user_id: Uuid,
+  view_id: Uuid,
+) -> Result<view::Model, ApiError> {
+  let view = View::find_by_id(view_id)
+    .one(db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("view_not_found", "no such view"))?;
+
+  zone_access(db, user_id, view.zone_id).await?;
+
+  Ok(view)
+}
@@ -1,5 +1,14 @@
+pub(crate) use access::*;
+pub(crate) use name::*;
 pub(crate) use record::*;
+pub(crate) use verification::*;
+pub(crate) use view::*;
 pub(crate) use zone::*;
 
+mod access;
+mod name;
 mod record;
+mod verification;
+mod view;
 mod zone;
+mod zone_file;
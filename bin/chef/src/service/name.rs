@@ -0,0 +1,281 @@
+// see RFC 1035 2.3.4, 255 octets on the wire are 253 characters without the
+// trailing dot
+const MAX_NAME_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Why a name was rejected, reported as field error.
+pub(crate) struct NameError {
+  pub(crate) code: &'static str,
+  pub(crate) message: String,
+}
+
+impl NameError {
+  fn new(code: &'static str, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+/// Internationalized names are converted to lower case punycode.
+fn to_ascii(name: &str) -> Result<String, NameError> {
+  idna::domain_to_ascii(name)
+    .map_err(|_| NameError::new("invalid_name", "not a valid internationalized domain name"))
+}
+
+/// Letters, digits and inner hyphens as per RFC 1123 2.1, underscores for
+/// service labels like `_dmarc` and a leading `*` for wildcards if allowed.
+fn check_labels(name: &str, underscores: bool, wildcard: bool) -> Result<(), NameError> {
+  for (i, label) in name.split('.').enumerate() {
+    if label.is_empty() {
+      return Err(NameError::new("empty_label", "labels must not be empty"));
+    }
+    if label.len() > MAX_LABEL_LENGTH {
+      return Err(NameError::new(
+        "label_too_long",
+        format!("labels must not exceed {MAX_LABEL_LENGTH} characters"),
+      ));
+    }
+    if wildcard && i == 0 && label == "*" {
+      continue;
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+      return Err(NameError::new(
+        "invalid_hyphen",
+        "labels must not start or end with a hyphen",
+      ));
+    }
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || (underscores && c == '_');
+    if !label.chars().all(valid) {
+      return Err(NameError::new(
+        "invalid_character",
+        "only letters, digits and hyphens are allowed",
+      ));
+    }
+  }
+
+  Ok(())
+}
+
+/// Relative names count with the zone appended.
+fn check_length(length: usize) -> Result<(), NameError> {
+  if length > MAX_NAME_LENGTH {
+    return Err(NameError::new(
+      "name_too_long",
+      format!("names must not exceed {MAX_NAME_LENGTH} characters including the zone"),
+    ));
+  }
+
+  Ok(())
+}
+
+/// Normalizes a zone name, which is stored without the trailing dot.
+pub(crate) fn zone_name(name: &str) -> Result<String, NameError> {
+  let name = to_ascii(name.strip_suffix('.').unwrap_or(name))?;
+  check_labels(&name, false, false)?;
+  check_length(name.len())?;
+  if !name.contains('.') {
+    return Err(NameError::new(
+      "top_level_domain",
+      "top level domains can not be managed",
+    ));
+  }
+
+  Ok(name)
+}
+
+/// Normalizes the name of a record to be relative to the zone, `@` for the
+/// apex. Names relative to `@` and absolute names inside the zone are
+/// accepted too.
+pub(crate) fn record_name(name: &str, zone: &str) -> Result<String, NameError> {
+  if name == "@" {
+    return Ok(name.to_string());
+  }
+
+  let name = to_ascii(name.strip_suffix(".@").unwrap_or(name))?;
+  let name = match name.strip_suffix('.') {
+    Some(absolute) if absolute == zone => return Ok("@".to_string()),
+    Some(absolute) => absolute
+      .strip_suffix(zone)
+      .and_then(|relative| relative.strip_suffix('.'))
+      .ok_or_else(|| NameError::new("outside_zone", format!("the name is not inside {zone}")))?
+      .to_string(),
+    None => name,
+  };
+
+  check_labels(&name, true, true)?;
+  check_length(name.len() + 1 + zone.len())?;
+
+  Ok(name)
+}
+
+/// Normalizes a name records point to. Names ending in `@` are relative to the
+/// zone, e.g. `mail.@`, everything else is absolute and stored with the
+/// trailing dot.
+pub(crate) fn target_name(name: &str, zone: &str) -> Result<String, NameError> {
+  if name == "@" {
+    return Ok(name.to_string());
+  }
+
+  if let Some(relative) = name.strip_suffix(".@") {
+    let relative = to_ascii(relative)?;
+    check_labels(&relative, true, false)?;
+    check_length(relative.len() + 1 + zone.len())?;
+    return Ok(format!("{relative}.@"));
+  }
+
+  let name = to_ascii(name.strip_suffix('.').unwrap_or(name))?;
+  check_labels(&name, true, false)?;
+  check_length(name.len())?;
+
+  Ok(format!("{name}."))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn code<T>(result: Result<T, NameError>) -> &'static str {
+    match result {
+      Ok(_) => "ok",
+      Err(err) => err.code,
+    }
+  }
+
+  fn long(labels: usize) -> String {
+    vec!["a".repeat(MAX_LABEL_LENGTH); labels].join(".")
+  }
+
+  #[test]
+  fn zone_names() {
+    assert_eq!(
+      zone_name("Example.ORG.").ok(),
+      Some("example.org".to_string())
+    );
+    assert_eq!(
+      zone_name("bücher.example").ok(),
+      Some("xn--bcher-kva.example".to_string())
+    );
+    assert_eq!(code(zone_name("org")), "top_level_domain");
+    assert_eq!(code(zone_name("org.")), "top_level_domain");
+    assert_eq!(code(zone_name("example..org")), "empty_label");
+    assert_eq!(code(zone_name("")), "empty_label");
+    assert_eq!(code(zone_name("_dmarc.example.org")), "invalid_character");
+    assert_eq!(code(zone_name("*.example.org")), "invalid_character");
+    assert_eq!(code(zone_name("-a.example.org")), "invalid_hyphen");
+    assert_eq!(code(zone_name("a-.example.org")), "invalid_hyphen");
+    assert_eq!(code(zone_name("a-b.example.org")), "ok");
+  }
+
+  #[test]
+  fn zone_name_lengths() {
+    let label = "a".repeat(MAX_LABEL_LENGTH);
+    assert_eq!(code(zone_name(&format!("{label}.org"))), "ok");
+    assert_eq!(code(zone_name(&format!("a{label}.org"))), "label_too_long");
+
+    // 4 * 63 + 3 dots = 255
+    let name = long(4);
+    assert_eq!(code(zone_name(&name[2..])), "ok");
+    assert_eq!(code(zone_name(&name[1..])), "name_too_long");
+    assert_eq!(code(zone_name(&format!("{}.", &name[2..]))), "ok");
+  }
+
+  #[test]
+  fn record_names() {
+    let zone = "example.org";
+
+    assert_eq!(record_name("@", zone).ok().as_deref(), Some("@"));
+    assert_eq!(record_name("example.org.", zone).ok().as_deref(), Some("@"));
+    assert_eq!(record_name("WWW", zone).ok().as_deref(), Some("www"));
+    assert_eq!(record_name("www.@", zone).ok().as_deref(), Some("www"));
+    assert_eq!(
+      record_name("www.example.org.", zone).ok().as_deref(),
+      Some("www")
+    );
+    assert_eq!(
+      record_name("a.b.example.org.", zone).ok().as_deref(),
+      Some("a.b")
+    );
+    assert_eq!(record_name("_dmarc", zone).ok().as_deref(), Some("_dmarc"));
+    assert_eq!(record_name("*", zone).ok().as_deref(), Some("*"));
+    assert_eq!(record_name("*.sub", zone).ok().as_deref(), Some("*.sub"));
+    assert_eq!(
+      record_name("bücher", zone).ok().as_deref(),
+      Some("xn--bcher-kva")
+    );
+    // relative names are not checked for the zone
+    assert_eq!(
+      record_name("www.example.org", zone).ok().as_deref(),
+      Some("www.example.org")
+    );
+  }
+
+  #[test]
+  fn invalid_record_names() {
+    let zone = "example.org";
+
+    assert_eq!(code(record_name("www.example.net.", zone)), "outside_zone");
+    assert_eq!(code(record_name("wwwexample.org.", zone)), "outside_zone");
+    assert_eq!(code(record_name("org.", zone)), "outside_zone");
+    assert_eq!(code(record_name("sub.*", zone)), "invalid_character");
+    assert_eq!(code(record_name("**", zone)), "invalid_character");
+    assert_eq!(code(record_name("a..b", zone)), "empty_label");
+    assert_eq!(code(record_name(".@", zone)), "empty_label");
+    assert_eq!(code(record_name("a b", zone)), "invalid_character");
+    assert_eq!(code(record_name("xn--a", zone)), "invalid_name");
+  }
+
+  #[test]
+  fn record_name_lengths() {
+    let zone = "example.org";
+    // with ".example.org" appended, 253 - 12 characters are left
+    let name = long(4);
+    assert_eq!(code(record_name(&name[14..], zone)), "ok");
+    assert_eq!(code(record_name(&name[13..], zone)), "name_too_long");
+    assert_eq!(
+      code(record_name(&format!("{}.example.org.", &name[14..]), zone)),
+      "ok"
+    );
+  }
+
+  #[test]
+  fn target_names() {
+    let zone = "example.org";
+
+    assert_eq!(target_name("@", zone).ok().as_deref(), Some("@"));
+    assert_eq!(target_name("mail.@", zone).ok().as_deref(), Some("mail.@"));
+    assert_eq!(
+      target_name("Mail.Example.NET", zone).ok().as_deref(),
+      Some("mail.example.net.")
+    );
+    assert_eq!(
+      target_name("mail.example.net.", zone).ok().as_deref(),
+      Some("mail.example.net.")
+    );
+    assert_eq!(
+      target_name("_sip._tcp.example.net.", zone).ok().as_deref(),
+      Some("_sip._tcp.example.net.")
+    );
+    assert_eq!(
+      target_name("bücher.@", zone).ok().as_deref(),
+      Some("xn--bcher-kva.@")
+    );
+    assert_eq!(
+      code(target_name("*.example.net.", zone)),
+      "invalid_character"
+    );
+    assert_eq!(code(target_name("*.@", zone)), "invalid_character");
+    assert_eq!(code(target_name(".@", zone)), "empty_label");
+    assert_eq!(code(target_name("a..b.", zone)), "empty_label");
+
+    let name = long(4);
+    assert_eq!(code(target_name(&name[2..], zone)), "ok");
+    assert_eq!(code(target_name(&name[1..], zone)), "name_too_long");
+    assert_eq!(code(target_name(&format!("{}.@", &name[14..]), zone)), "ok");
+    assert_eq!(
+      code(target_name(&format!("{}.@", &name[13..]), zone)),
+      "name_too_long"
+    );
+  }
+}
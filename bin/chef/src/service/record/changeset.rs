@@ -0,0 +1,146 @@
+use sea_orm::{ConnectionTrait, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use entity::{record, zone};
+
+use crate::error::ApiError;
+use crate::service::record::rrset::RRsets;
+use crate::service::record::{insert_any, modify_record, remove_any, touch_zone, zone_record};
+use crate::service::{
+  zone_write_access, AnyRecord, AnyRecordRequest, RecordCommonReq, RecordService,
+};
+
+// keeps a single transaction from locking the zone for too long
+const MAX_CHANGES: usize = 1000;
+
+/// Operation of a changeset, tagged by `"op"`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum Change {
+  Create {
+    #[serde(flatten)]
+    common: RecordCommonReq,
+    #[serde(flatten)]
+    specific: AnyRecordRequest,
+  },
+  /// Records keep their type, they have to be deleted and created anew instead
+  Modify {
+    id: Uuid,
+    #[serde(flatten)]
+    common: RecordCommonReq,
+    #[serde(flatten)]
+    specific: AnyRecordRequest,
+  },
+  Delete {
+    id: Uuid,
+  },
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Changeset {
+  operations: Vec<Change>,
+}
+
+/// Result of an operation, at the same position as in the changeset.
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum ChangeResult {
+  Create {
+    #[serde(flatten)]
+    common: record::Model,
+    #[serde(flatten)]
+    specific: AnyRecord,
+  },
+  Modify {
+    #[serde(flatten)]
+    common: record::Model,
+    #[serde(flatten)]
+    specific: AnyRecord,
+  },
+  Delete {
+    id: Uuid,
+  },
+}
+
+async fn apply<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  change: Change,
+  harmonize_ttl: bool,
+) -> Result<ChangeResult, ApiError> {
+  let result = match change {
+    Change::Create { common, specific } => {
+      let (common, specific) =
+        insert_any(db, zone, rrsets, common, specific, harmonize_ttl).await?;
+      ChangeResult::Create { common, specific }
+    }
+    Change::Modify {
+      id,
+      common,
+      specific,
+    } => {
+      let (common, specific) =
+        modify_record(db, zone, rrsets, id, common, specific, harmonize_ttl).await?;
+      ChangeResult::Modify { common, specific }
+    }
+    Change::Delete { id } => {
+      let current = zone_record(db, zone, id).await?;
+      remove_any(db, id, &current).await?;
+      rrsets.removed(id);
+      ChangeResult::Delete { id }
+    }
+  };
+
+  Ok(result)
+}
+
+impl RecordService {
+  /// Applies the operations in order in a single transaction, the first one
+  /// failing rolls back all of them. Rules like the ones of RRsets only have
+  /// to hold once all operations before are applied.
+  pub(crate) async fn apply_changeset(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    changeset: Changeset,
+    harmonize_ttl: bool,
+  ) -> anyhow::Result<Vec<ChangeResult>> {
+    if changeset.operations.len() > MAX_CHANGES {
+      return Err(
+        ApiError::field(
+          "operations",
+          "too_many_operations",
+          format!("at most {MAX_CHANGES} operations are applied at once"),
+        )
+        .into(),
+      );
+    }
+
+    let results = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let mut rrsets = RRsets::load(tx, zone.id).await?;
+
+          let mut results = Vec::with_capacity(changeset.operations.len());
+          for (i, change) in changeset.operations.into_iter().enumerate() {
+            let result = apply(tx, &zone, &mut rrsets, change, harmonize_ttl)
+              .await
+              .map_err(|err| err.within(&format!("operations[{i}]")))?;
+            results.push(result);
+          }
+
+          touch_zone(tx, zone.id).await?;
+
+          Ok::<_, ApiError>(results)
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(results)
+  }
+}
@@ -0,0 +1,200 @@
+use hickory_proto::rr::RecordType;
+use sea_orm::{ActiveModelTrait, ActiveValue, ConnectionTrait, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use entity::record;
+
+use crate::error::ApiError;
+use crate::service::record::rrset::RRsets;
+use crate::service::record::{
+  delete_zone_records, insert_any, touch_zone, validate_any, zone_records,
+};
+use crate::service::zone_file::{parse, Entry, EntryData, SkippedLine};
+use crate::service::{
+  zone_write_access, AnyRecordRequest, RecordARequest, RecordAaaaRequest, RecordCnameRequest,
+  RecordCommonReq, RecordMxRequest, RecordNsRequest, RecordService, RecordTxtRequest,
+};
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImportMode {
+  /// Adds the records of the file to the ones of the zone
+  #[default]
+  Merge,
+  /// Deletes every record of the zone beforehand
+  Replace,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ImportReport {
+  created: usize,
+  /// Records of the file the zone already had with another ttl
+  modified: usize,
+  /// Records of the file the zone already had
+  unchanged: usize,
+  deleted: u64,
+  skipped: Vec<SkippedLine>,
+}
+
+/// Targets inside the zone are stored relative to it, e.g. `mail.@`.
+fn relative_target(target: String, zone: &str) -> String {
+  let target = target.to_ascii_lowercase();
+  match target
+    .strip_suffix('.')
+    .and_then(|name| name.strip_suffix(zone))
+  {
+    Some("") => "@".to_string(),
+    Some(prefix) if prefix.ends_with('.') => format!("{prefix}@"),
+    _ => target,
+  }
+}
+
+fn into_request(entry: Entry, zone: &str) -> (RecordCommonReq, AnyRecordRequest) {
+  let common = RecordCommonReq {
+    name: entry.name,
+    ttl: entry.ttl,
+    view_id: None,
+    country: None,
+    continent: None,
+    weight: None,
+    health_check: None,
+  };
+
+  let specific = match entry.data {
+    EntryData::A(addr) => AnyRecordRequest::A(RecordARequest { addr }),
+    EntryData::Aaaa(addr) => AnyRecordRequest::Aaaa(RecordAaaaRequest { addr }),
+    EntryData::Cname(target) => AnyRecordRequest::Cname(RecordCnameRequest {
+      target: relative_target(target, zone),
+    }),
+    EntryData::Mx(preference, exchange) => AnyRecordRequest::Mx(RecordMxRequest {
+      preference,
+      exchange: relative_target(exchange, zone),
+    }),
+    EntryData::Ns(target) => AnyRecordRequest::Ns(RecordNsRequest {
+      target: relative_target(target, zone),
+    }),
+    EntryData::Txt(content) => AnyRecordRequest::Txt(RecordTxtRequest { content }),
+  };
+
+  (common, specific)
+}
+
+/// Gives a record the file already has the ttl of the file, keeping its
+/// weight and health check.
+async fn update_ttl<C: ConnectionTrait>(
+  db: &C,
+  rrsets: &mut RRsets,
+  record: &record::Model,
+  record_type: RecordType,
+  mut common: RecordCommonReq,
+  harmonize_ttl: bool,
+) -> Result<record::Model, ApiError> {
+  common.weight = record.weight.map(|weight| weight as u32);
+  rrsets
+    .check(db, Some(record.id), record_type, &common, harmonize_ttl)
+    .await?;
+
+  let record = record::ActiveModel {
+    id: ActiveValue::Unchanged(record.id),
+    ttl: ActiveValue::Set(common.ttl.map(|ttl| ttl as i32)),
+    updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+    ..Default::default()
+  }
+  .update(db)
+  .await?;
+  rrsets.written(&record, record_type);
+
+  Ok(record)
+}
+
+impl RecordService {
+  /// Imports a master file in a single transaction, records the zone already
+  /// has with the same data are left alone. Any invalid record fails the
+  /// whole import.
+  pub(crate) async fn import(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    content: String,
+    mode: ImportMode,
+    harmonize_ttl: bool,
+  ) -> anyhow::Result<ImportReport> {
+    let report = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let (entries, skipped) = parse(&content, &zone.name)?;
+
+          let deleted = match mode {
+            ImportMode::Merge => 0,
+            ImportMode::Replace => delete_zone_records(tx, zone_id).await?,
+          };
+
+          let mut records = zone_records(tx, zone_id).await?;
+          let mut rrsets = RRsets::new(&records);
+          let mut created = 0;
+          let mut modified = 0;
+          let mut unchanged = 0;
+
+          for entry in entries {
+            let location = format!("line {}", entry.line);
+            let (mut common, mut req) = into_request(entry, &zone.name);
+
+            // normalized first to be compared with the existing records
+            validate_any(&zone, &mut common, &mut req).map_err(|err| err.within(&location))?;
+            let existing = records.iter_mut().find(|(record, specific)| {
+              record.name == common.name
+                && record.view_id.is_none()
+                && record.country.is_none()
+                && record.continent.is_none()
+                && req.matches(specific)
+            });
+            if let Some((record, _)) = existing {
+              let ttl = common.ttl.map(|ttl| ttl as i32);
+              if record.ttl == ttl {
+                unchanged += 1;
+              } else {
+                *record = update_ttl(
+                  tx,
+                  &mut rrsets,
+                  record,
+                  req.record_type(),
+                  common,
+                  harmonize_ttl,
+                )
+                .await
+                .map_err(|err| err.within(&location))?;
+                modified += 1;
+              }
+              continue;
+            }
+
+            let record = insert_any(tx, &zone, &mut rrsets, common, req, harmonize_ttl)
+              .await
+              .map_err(|err| err.within(&location))?;
+            records.push(record);
+            created += 1;
+          }
+
+          if deleted > 0 || created > 0 || modified > 0 {
+            touch_zone(tx, zone.id).await?;
+          }
+
+          Ok::<_, ApiError>(ImportReport {
+            created,
+            modified,
+            unchanged,
+            deleted,
+            skipped,
+          })
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(report)
+  }
+}
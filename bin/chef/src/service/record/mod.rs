@@ -1,17 +1,38 @@
 use std::sync::Arc;
 
+use sea_orm::sea_query::Query;
 use sea_orm::{
-  ActiveModelBehavior, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr,
-  EntityTrait, PaginatorTrait, QueryFilter, Related, Select, TransactionTrait,
+  ActiveModelBehavior, ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait,
+  DatabaseConnection, DbErr, EntityTrait, IntoActiveModel, QueryFilter, Related, Select, SqlErr,
+  TransactionTrait,
 };
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use entity::prelude::{Record, Zone};
-use entity::{record, zone};
+pub(crate) use changeset::*;
+use entity::prelude::{
+  Record, RecordA, RecordAaaa, RecordCname, RecordHealthCheck, RecordMx, RecordNs, RecordTxt, Zone,
+};
+use entity::{
+  record, record_a, record_aaaa, record_cname, record_health_check, record_mx, record_ns,
+  record_txt, zone,
+};
+pub(crate) use import::*;
 pub(crate) use model::*;
+pub(crate) use replace::*;
+
+use crate::error::{ApiError, FieldErrors};
+use crate::service::record::rrset::RRsets;
+use crate::service::{record_access, record_write_access, zone_access, zone_write_access};
 
+mod changeset;
+mod import;
 mod model;
+mod replace;
+mod rrset;
+
+// same as the default of the column
+const DEFAULT_CHECK_INTERVAL: i32 = 30;
 
 fn map_entry<E: EntityTrait>(
   (common, specific): (record::Model, Option<E::Model>),
@@ -19,6 +40,537 @@ fn map_entry<E: EntityTrait>(
   (common, specific.unwrap())
 }
 
+fn validate<A: ActiveModelTrait, R: RecordRequestTrait<A>>(
+  zone: &zone::Model,
+  common: &mut RecordCommonReq,
+  req: &mut R,
+) -> Result<(), ApiError> {
+  let mut errors = FieldErrors::default();
+  common.normalize(&zone.name, R::balanced(), &mut errors);
+  req.normalize(&zone.name, &mut errors);
+  errors.finish()
+}
+
+fn validate_any(
+  zone: &zone::Model,
+  common: &mut RecordCommonReq,
+  req: &mut AnyRecordRequest,
+) -> Result<(), ApiError> {
+  match req {
+    AnyRecordRequest::A(req) => validate(zone, common, req),
+    AnyRecordRequest::Aaaa(req) => validate(zone, common, req),
+    AnyRecordRequest::Cname(req) => validate(zone, common, req),
+    AnyRecordRequest::Mx(req) => validate(zone, common, req),
+    AnyRecordRequest::Ns(req) => validate(zone, common, req),
+    AnyRecordRequest::Txt(req) => validate(zone, common, req),
+  }
+}
+
+fn record_not_found() -> ApiError {
+  ApiError::not_found("record_not_found", "no such record")
+}
+
+/// The only foreign key besides the zone, which is checked beforehand.
+fn unknown_view(err: DbErr) -> ApiError {
+  match err.sql_err() {
+    Some(SqlErr::ForeignKeyConstraintViolation(_)) => {
+      ApiError::field("view_id", "unknown_view", "the zone has no view of that id")
+    }
+    _ => err.into(),
+  }
+}
+
+/// Health checks start over as healthy whenever what they probe changes, a
+/// new interval keeps the state.
+async fn replace_health_check<C: ConnectionTrait>(
+  db: &C,
+  record_id: Uuid,
+  health_check: Option<HealthCheckReq>,
+) -> Result<(), DbErr> {
+  let current = RecordHealthCheck::find_by_id(record_id).one(db).await?;
+  let (current, health_check) = match (current, health_check) {
+    (None, None) => return Ok(()),
+    (Some(_), None) => {
+      RecordHealthCheck::delete_by_id(record_id).exec(db).await?;
+      return Ok(());
+    }
+    (current, Some(health_check)) => (current, health_check),
+  };
+
+  let protocol = health_check.protocol.as_str().to_string();
+  let port = health_check.port as i32;
+  let check_interval = health_check
+    .interval
+    .map_or(DEFAULT_CHECK_INTERVAL, |interval| interval as i32);
+
+  match current {
+    Some(current)
+      if current.protocol == protocol
+        && current.port == port
+        && current.path == health_check.path =>
+    {
+      if current.check_interval != check_interval {
+        record_health_check::ActiveModel {
+          id: ActiveValue::Unchanged(record_id),
+          check_interval: ActiveValue::Set(check_interval),
+          ..Default::default()
+        }
+        .update(db)
+        .await?;
+      }
+    }
+    Some(_) => {
+      record_health_check::ActiveModel {
+        id: ActiveValue::Unchanged(record_id),
+        protocol: ActiveValue::Set(protocol),
+        port: ActiveValue::Set(port),
+        path: ActiveValue::Set(health_check.path),
+        check_interval: ActiveValue::Set(check_interval),
+        healthy: ActiveValue::Set(true),
+        failures: ActiveValue::Set(0),
+        checked: ActiveValue::Set(None),
+      }
+      .update(db)
+      .await?;
+    }
+    None => {
+      record_health_check::ActiveModel {
+        id: ActiveValue::Set(record_id),
+        protocol: ActiveValue::Set(protocol),
+        port: ActiveValue::Set(port),
+        path: ActiveValue::Set(health_check.path),
+        check_interval: ActiveValue::Set(check_interval),
+        healthy: ActiveValue::NotSet,
+        failures: ActiveValue::NotSet,
+        checked: ActiveValue::NotSet,
+      }
+      .insert(db)
+      .await?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Validates and inserts a record into the zone, part of a larger transaction.
+async fn insert<C, A, R>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  mut common: RecordCommonReq,
+  mut req: R,
+  harmonize_ttl: bool,
+) -> Result<(record::Model, <A::Entity as EntityTrait>::Model), ApiError>
+where
+  C: ConnectionTrait,
+  A: ActiveModelTrait + ActiveModelBehavior + Send,
+  R: RecordRequestTrait<A>,
+  <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+{
+  validate(zone, &mut common, &mut req)?;
+  rrsets
+    .check(db, None, R::record_type(), &common, harmonize_ttl)
+    .await?;
+
+  let record = record::ActiveModel {
+    id: ActiveValue::NotSet,
+    created: ActiveValue::NotSet,
+    updated: ActiveValue::NotSet,
+    name: ActiveValue::Set(common.name),
+    zone_id: ActiveValue::Set(zone.id),
+    ttl: ActiveValue::Set(common.ttl.map(|x| x as i32)),
+    view_id: ActiveValue::Set(common.view_id),
+    country: ActiveValue::Set(common.country.map(|x| x.to_ascii_uppercase())),
+    continent: ActiveValue::Set(common.continent.map(|x| x.to_ascii_uppercase())),
+    weight: ActiveValue::Set(common.weight.map(|x| x as i32)),
+  };
+
+  let record = record.insert(db).await.map_err(unknown_view)?;
+  rrsets.written(&record, R::record_type());
+  replace_health_check(db, record.id, common.health_check).await?;
+
+  let specific = req
+    .into_active_model(ActiveValue::Set(record.id))
+    .insert(db)
+    .await?;
+
+  Ok((record, specific))
+}
+
+/// Validates and updates a record of the zone, part of a larger transaction.
+async fn update<C, A, R>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  record_id: Uuid,
+  mut common: RecordCommonReq,
+  mut req: R,
+  harmonize_ttl: bool,
+) -> Result<(record::Model, <A::Entity as EntityTrait>::Model), ApiError>
+where
+  C: ConnectionTrait,
+  A: ActiveModelTrait + ActiveModelBehavior + Send,
+  R: RecordRequestTrait<A>,
+  <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+{
+  validate(zone, &mut common, &mut req)?;
+  rrsets
+    .check(
+      db,
+      Some(record_id),
+      R::record_type(),
+      &common,
+      harmonize_ttl,
+    )
+    .await?;
+
+  let now = OffsetDateTime::now_utc();
+
+  let record = record::ActiveModel {
+    id: ActiveValue::Unchanged(record_id),
+    created: ActiveValue::NotSet,
+    updated: ActiveValue::Set(now),
+    name: ActiveValue::Set(common.name),
+    zone_id: ActiveValue::NotSet,
+    ttl: ActiveValue::Set(common.ttl.map(|x| x as i32)),
+    view_id: ActiveValue::Set(common.view_id),
+    country: ActiveValue::Set(common.country.map(|x| x.to_ascii_uppercase())),
+    continent: ActiveValue::Set(common.continent.map(|x| x.to_ascii_uppercase())),
+    weight: ActiveValue::Set(common.weight.map(|x| x as i32)),
+  };
+
+  let record = record.update(db).await.map_err(unknown_view)?;
+  rrsets.written(&record, R::record_type());
+  replace_health_check(db, record.id, common.health_check).await?;
+
+  // the type of a record can not be changed
+  let specific = req
+    .into_active_model(ActiveValue::Unchanged(record.id))
+    .update(db)
+    .await
+    .map_err(|err| match err {
+      DbErr::RecordNotUpdated => record_not_found(),
+      err => err.into(),
+    })?;
+
+  Ok((record, specific))
+}
+
+async fn insert_any<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  common: RecordCommonReq,
+  req: AnyRecordRequest,
+  harmonize_ttl: bool,
+) -> Result<(record::Model, AnyRecord), ApiError> {
+  let result = match req {
+    AnyRecordRequest::A(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::A(specific))
+    }
+    AnyRecordRequest::Aaaa(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Aaaa(specific))
+    }
+    AnyRecordRequest::Cname(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Cname(specific))
+    }
+    AnyRecordRequest::Mx(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Mx(specific))
+    }
+    AnyRecordRequest::Ns(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Ns(specific))
+    }
+    AnyRecordRequest::Txt(req) => {
+      let (common, specific) = insert(db, zone, rrsets, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Txt(specific))
+    }
+  };
+
+  Ok(result)
+}
+
+/// Updates a record of any type, part of a larger transaction. Fails with 404
+/// if the record is of another type.
+async fn update_any<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  record_id: Uuid,
+  common: RecordCommonReq,
+  req: AnyRecordRequest,
+  harmonize_ttl: bool,
+) -> Result<(record::Model, AnyRecord), ApiError> {
+  let result = match req {
+    AnyRecordRequest::A(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::A(specific))
+    }
+    AnyRecordRequest::Aaaa(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Aaaa(specific))
+    }
+    AnyRecordRequest::Cname(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Cname(specific))
+    }
+    AnyRecordRequest::Mx(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Mx(specific))
+    }
+    AnyRecordRequest::Ns(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Ns(specific))
+    }
+    AnyRecordRequest::Txt(req) => {
+      let (common, specific) =
+        update(db, zone, rrsets, record_id, common, req, harmonize_ttl).await?;
+      (common, AnyRecord::Txt(specific))
+    }
+  };
+
+  Ok(result)
+}
+
+/// maid derives the serial from the latest change, deletions leave no record
+/// behind to take it from.
+async fn touch_zone<C: ConnectionTrait>(db: &C, zone_id: Uuid) -> Result<(), DbErr> {
+  zone::ActiveModel {
+    id: ActiveValue::Unchanged(zone_id),
+    updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+    ..Default::default()
+  }
+  .update(db)
+  .await?;
+
+  Ok(())
+}
+
+/// Record of the zone, whatever its type is.
+async fn zone_record<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+  record_id: Uuid,
+) -> Result<AnyRecord, ApiError> {
+  Record::find_by_id(record_id)
+    .one(db)
+    .await?
+    .filter(|record| record.zone_id == zone.id)
+    .ok_or_else(record_not_found)?;
+
+  find_any(db, record_id).await?.ok_or_else(record_not_found)
+}
+
+/// Updates a record of the zone, part of a larger transaction. A record keeps
+/// its type, it has to be deleted and created anew instead.
+async fn modify_record<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+  rrsets: &mut RRsets,
+  record_id: Uuid,
+  common: RecordCommonReq,
+  req: AnyRecordRequest,
+  harmonize_ttl: bool,
+) -> Result<(record::Model, AnyRecord), ApiError> {
+  let current = zone_record(db, zone, record_id).await?;
+  if current.record_type() != req.record_type() {
+    return Err(ApiError::field(
+      "type",
+      "type_changed",
+      format!("the record is of type {}", current.record_type()),
+    ));
+  }
+
+  update_any(db, zone, rrsets, record_id, common, req, harmonize_ttl).await
+}
+
+/// Deletes a record of the given type along with its health check, part of a
+/// larger transaction.
+async fn remove<C: ConnectionTrait, E: EntityTrait>(db: &C, record_id: Uuid) -> Result<(), ApiError>
+where
+  <<E as EntityTrait>::PrimaryKey as sea_orm::PrimaryKeyTrait>::ValueType: From<Uuid>,
+{
+  let result = E::delete_by_id(record_id).exec(db).await?;
+
+  if result.rows_affected == 0 {
+    return Err(record_not_found());
+  }
+
+  RecordHealthCheck::delete_by_id(record_id).exec(db).await?;
+  Record::delete_by_id(record_id).exec(db).await?;
+
+  Ok(())
+}
+
+async fn remove_any<C: ConnectionTrait>(
+  db: &C,
+  record_id: Uuid,
+  specific: &AnyRecord,
+) -> Result<(), ApiError> {
+  match specific {
+    AnyRecord::A(_) => remove::<_, RecordA>(db, record_id).await,
+    AnyRecord::Aaaa(_) => remove::<_, RecordAaaa>(db, record_id).await,
+    AnyRecord::Cname(_) => remove::<_, RecordCname>(db, record_id).await,
+    AnyRecord::Mx(_) => remove::<_, RecordMx>(db, record_id).await,
+    AnyRecord::Ns(_) => remove::<_, RecordNs>(db, record_id).await,
+    AnyRecord::Txt(_) => remove::<_, RecordTxt>(db, record_id).await,
+  }
+}
+
+/// Type specific part of a record, whatever its type is.
+async fn find_any<C: ConnectionTrait>(db: &C, record_id: Uuid) -> Result<Option<AnyRecord>, DbErr> {
+  let specific = if let Some(specific) = RecordA::find_by_id(record_id).one(db).await? {
+    AnyRecord::A(specific)
+  } else if let Some(specific) = RecordAaaa::find_by_id(record_id).one(db).await? {
+    AnyRecord::Aaaa(specific)
+  } else if let Some(specific) = RecordCname::find_by_id(record_id).one(db).await? {
+    AnyRecord::Cname(specific)
+  } else if let Some(specific) = RecordMx::find_by_id(record_id).one(db).await? {
+    AnyRecord::Mx(specific)
+  } else if let Some(specific) = RecordNs::find_by_id(record_id).one(db).await? {
+    AnyRecord::Ns(specific)
+  } else if let Some(specific) = RecordTxt::find_by_id(record_id).one(db).await? {
+    AnyRecord::Txt(specific)
+  } else {
+    return Ok(None);
+  };
+
+  Ok(Some(specific))
+}
+
+/// Records of all types in the zone, sorted by name and type.
+pub(crate) async fn zone_records<C: ConnectionTrait>(
+  db: &C,
+  zone_id: Uuid,
+) -> Result<Vec<(record::Model, AnyRecord)>, DbErr> {
+  async fn of_type<C: ConnectionTrait, E: EntityTrait>(
+    db: &C,
+    zone_id: Uuid,
+  ) -> Result<Vec<(record::Model, E::Model)>, DbErr>
+  where
+    record::Entity: Related<E>,
+  {
+    let records = Record::find()
+      .filter(record::Column::ZoneId.eq(zone_id))
+      .inner_join(E::default())
+      .select_also(E::default())
+      .all(db)
+      .await?
+      .into_iter()
+      .map(map_entry::<E>)
+      .collect();
+
+    Ok(records)
+  }
+
+  let mut records = Vec::new();
+  records.extend(
+    of_type::<_, RecordA>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::A(specific))),
+  );
+  records.extend(
+    of_type::<_, RecordAaaa>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::Aaaa(specific))),
+  );
+  records.extend(
+    of_type::<_, RecordCname>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::Cname(specific))),
+  );
+  records.extend(
+    of_type::<_, RecordMx>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::Mx(specific))),
+  );
+  records.extend(
+    of_type::<_, RecordNs>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::Ns(specific))),
+  );
+  records.extend(
+    of_type::<_, RecordTxt>(db, zone_id)
+      .await?
+      .into_iter()
+      .map(|(common, specific)| (common, AnyRecord::Txt(specific))),
+  );
+
+  records.sort_by(|(a, a_specific), (b, b_specific)| {
+    a.name.cmp(&b.name).then_with(|| {
+      a_specific
+        .record_type()
+        .to_string()
+        .cmp(&b_specific.record_type().to_string())
+    })
+  });
+
+  Ok(records)
+}
+
+/// Removes every record of the zone along with its type specific rows and
+/// health check, returns how many there were.
+pub(crate) async fn delete_zone_records<C: ConnectionTrait>(
+  db: &C,
+  zone_id: Uuid,
+) -> Result<u64, DbErr> {
+  let records = Query::select()
+    .column(record::Column::Id)
+    .from(Record)
+    .and_where(record::Column::ZoneId.eq(zone_id))
+    .to_owned();
+
+  RecordA::delete_many()
+    .filter(record_a::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordAaaa::delete_many()
+    .filter(record_aaaa::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordCname::delete_many()
+    .filter(record_cname::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordMx::delete_many()
+    .filter(record_mx::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordNs::delete_many()
+    .filter(record_ns::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordTxt::delete_many()
+    .filter(record_txt::Column::Id.in_subquery(records.clone()))
+    .exec(db)
+    .await?;
+  RecordHealthCheck::delete_many()
+    .filter(record_health_check::Column::Id.in_subquery(records))
+    .exec(db)
+    .await?;
+
+  let result = Record::delete_many()
+    .filter(record::Column::ZoneId.eq(zone_id))
+    .exec(db)
+    .await?;
+
+  Ok(result.rows_affected)
+}
+
 #[derive(Clone)]
 pub(crate) struct RecordService {
   db: Arc<DatabaseConnection>,
@@ -46,6 +598,8 @@ impl RecordService {
       )
     }
 
+    zone_access(self.db.as_ref(), user_id, zone_id).await?;
+
     let zones = call(user_id, zone_id)
       .inner_join(E::default())
       .select_also(E::default())
@@ -62,7 +616,7 @@ impl RecordService {
     &self,
     user_id: Uuid,
     record_id: Uuid,
-  ) -> anyhow::Result<Option<(record::Model, E::Model)>>
+  ) -> anyhow::Result<(record::Model, E::Model)>
   where
     record::Entity: Related<E>,
     <<E as EntityTrait>::PrimaryKey as sea_orm::PrimaryKeyTrait>::ValueType: From<Uuid>,
@@ -74,14 +628,18 @@ impl RecordService {
         .filter(zone::Column::Owner.eq(user_id))
     }
 
-    let zones = call(user_id, record_id)
+    record_access(self.db.as_ref(), user_id, record_id).await?;
+
+    // records of another type are not found either
+    let record = call(user_id, record_id)
       .inner_join(E::default())
       .select_also(E::default())
       .one(self.db.as_ref())
       .await?
-      .map(map_entry::<E>);
+      .map(map_entry::<E>)
+      .ok_or_else(record_not_found)?;
 
-    Ok(zones)
+    Ok(record)
   }
 
   pub(crate) async fn create<
@@ -91,9 +649,9 @@ impl RecordService {
     &self,
     user_id: Uuid,
     zone_id: Uuid,
-    name: String,
-    ttl: Option<u32>,
+    common: RecordCommonReq,
     req: R,
+    harmonize_ttl: bool,
   ) -> anyhow::Result<(
     record::Model,
     <<A as ActiveModelTrait>::Entity as EntityTrait>::Model,
@@ -105,42 +663,13 @@ impl RecordService {
       .db
       .transaction(|tx| {
         Box::pin(async move {
-          // validate access
-          let access = Zone::find_by_id(zone_id)
-            .filter(zone::Column::Owner.eq(user_id))
-            .count(tx)
-            .await?
-            == 1;
-
-          // TODO: error handling
-          assert!(access);
-
-          let record = record::ActiveModel {
-            id: ActiveValue::NotSet,
-            created: ActiveValue::NotSet,
-            updated: ActiveValue::NotSet,
-            name: ActiveValue::Set(name),
-            zone_id: ActiveValue::Set(zone_id),
-            ttl: ActiveValue::Set(ttl.map(|x| x as i32)),
-          };
-
-          let record = record.insert(tx).await?;
-
-          let specific = req
-            .into_active_model(ActiveValue::Set(record.id))
-            .insert(tx)
-            .await?;
-
-          Ok::<
-            (
-              record::Model,
-              <<A as ActiveModelTrait>::Entity as EntityTrait>::Model,
-            ),
-            DbErr,
-          >((record, specific))
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let mut rrsets = RRsets::load(tx, zone.id).await?;
+          insert(tx, &zone, &mut rrsets, common, req, harmonize_ttl).await
         })
       })
-      .await?;
+      .await
+      .map_err(ApiError::from)?;
 
     Ok(result)
   }
@@ -152,9 +681,9 @@ impl RecordService {
     &self,
     user_id: Uuid,
     record_id: Uuid,
-    name: String,
-    ttl: Option<u32>,
+    common: RecordCommonReq,
     req: R,
+    harmonize_ttl: bool,
   ) -> anyhow::Result<(
     record::Model,
     <<A as ActiveModelTrait>::Entity as EntityTrait>::Model,
@@ -166,45 +695,22 @@ impl RecordService {
       .db
       .transaction(|tx| {
         Box::pin(async move {
-          // validate access
-          let access = Record::find_by_id(record_id)
-            .inner_join(zone::Entity)
-            .filter(zone::Column::Owner.eq(user_id))
-            .count(tx)
-            .await?
-            == 1;
-
-          // TODO: error handling
-          assert!(access);
-
-          let now = OffsetDateTime::now_utc();
-
-          let record = record::ActiveModel {
-            id: ActiveValue::Unchanged(record_id),
-            created: ActiveValue::NotSet,
-            updated: ActiveValue::Set(now),
-            name: ActiveValue::Set(name),
-            zone_id: ActiveValue::NotSet,
-            ttl: ActiveValue::Set(ttl.map(|x| x as i32)),
-          };
-
-          let record = record.update(tx).await?;
-
-          let specific = req
-            .into_active_model(ActiveValue::Unchanged(record.id))
-            .update(tx)
-            .await?;
-
-          Ok::<
-            (
-              record::Model,
-              <<A as ActiveModelTrait>::Entity as EntityTrait>::Model,
-            ),
-            DbErr,
-          >((record, specific))
+          let (_, zone) = record_write_access(tx, user_id, record_id).await?;
+          let mut rrsets = RRsets::load(tx, zone.id).await?;
+          update(
+            tx,
+            &zone,
+            &mut rrsets,
+            record_id,
+            common,
+            req,
+            harmonize_ttl,
+          )
+          .await
         })
       })
-      .await?;
+      .await
+      .map_err(ApiError::from)?;
 
     Ok(result)
   }
@@ -213,39 +719,136 @@ impl RecordService {
     &self,
     user_id: Uuid,
     record_id: Uuid,
-  ) -> anyhow::Result<bool>
+  ) -> anyhow::Result<()>
   where
     <<E as EntityTrait>::PrimaryKey as sea_orm::PrimaryKeyTrait>::ValueType: From<Uuid>,
   {
-    let result = self
+    self
       .db
       .transaction(|tx| {
         Box::pin(async move {
-          // validate access
-          let access = Record::find_by_id(record_id)
-            .filter(zone::Column::Owner.eq(user_id))
-            .count(tx)
-            .await?
-            == 1;
+          let (record, _) = record_write_access(tx, user_id, record_id).await?;
+          remove::<_, E>(tx, record_id).await?;
+          touch_zone(tx, record.zone_id).await?;
+          Ok::<_, ApiError>(())
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(())
+  }
+
+  /// Records of all types, sorted by name and type.
+  pub(crate) async fn list_any(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+  ) -> anyhow::Result<Vec<(record::Model, AnyRecord)>> {
+    zone_access(self.db.as_ref(), user_id, zone_id).await?;
+
+    let records = zone_records(self.db.as_ref(), zone_id).await?;
+
+    Ok(records)
+  }
+
+  pub(crate) async fn by_id_any(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    record_id: Uuid,
+  ) -> anyhow::Result<(record::Model, AnyRecord)> {
+    let db = self.db.as_ref();
+
+    let (record, _) = record_access(db, user_id, record_id).await?;
+    if record.zone_id != zone_id {
+      return Err(record_not_found().into());
+    }
 
-          if !access {
-            return Ok::<bool, DbErr>(false);
-          }
+    let specific = find_any(db, record_id)
+      .await?
+      .ok_or_else(record_not_found)?;
 
-          // delete
-          let result = E::delete_by_id(record_id).exec(tx).await?;
+    Ok((record, specific))
+  }
 
-          if result.rows_affected == 0 {
-            return Ok::<bool, DbErr>(false);
-          }
+  pub(crate) async fn create_any(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    common: RecordCommonReq,
+    req: AnyRecordRequest,
+    harmonize_ttl: bool,
+  ) -> anyhow::Result<(record::Model, AnyRecord)> {
+    let result = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let mut rrsets = RRsets::load(tx, zone.id).await?;
+          insert_any(tx, &zone, &mut rrsets, common, req, harmonize_ttl).await
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
 
-          let result = Record::delete_by_id(record_id).exec(tx).await?;
+    Ok(result)
+  }
 
-          Ok::<bool, DbErr>(result.rows_affected == 1)
+  /// A record keeps its type, it has to be deleted and created anew instead.
+  pub(crate) async fn modify_any(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    record_id: Uuid,
+    common: RecordCommonReq,
+    req: AnyRecordRequest,
+    harmonize_ttl: bool,
+  ) -> anyhow::Result<(record::Model, AnyRecord)> {
+    let result = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let mut rrsets = RRsets::load(tx, zone.id).await?;
+          modify_record(
+            tx,
+            &zone,
+            &mut rrsets,
+            record_id,
+            common,
+            req,
+            harmonize_ttl,
+          )
+          .await
         })
       })
-      .await?;
+      .await
+      .map_err(ApiError::from)?;
 
     Ok(result)
   }
+
+  pub(crate) async fn delete_any(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    record_id: Uuid,
+  ) -> anyhow::Result<()> {
+    self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_write_access(tx, user_id, zone_id).await?;
+          let specific = zone_record(tx, &zone, record_id).await?;
+          remove_any(tx, record_id, &specific).await?;
+          touch_zone(tx, zone.id).await?;
+          Ok::<_, ApiError>(())
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(())
+  }
 }
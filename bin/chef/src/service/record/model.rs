@@ -1,44 +1,291 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 
+use hickory_proto::rr::{Name, RData, RecordType};
 use sea_orm::{ActiveModelTrait, ActiveValue};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use entity::{record_a, record_aaaa, record_cname, record_mx, record_ns, record_txt};
+use entity::{
+  record_a, record_aaaa, record_cname, record_mx, record_ns, record_txt, EntityError, IntoRecord,
+};
+
+use crate::error::FieldErrors;
+use crate::service::{record_name, target_name, NameError};
+
+// GeoNames continent codes, as used by MaxMind databases
+const CONTINENTS: &[&str] = &["AF", "AN", "AS", "EU", "NA", "OC", "SA"];
+pub(crate) const MIN_TTL: u32 = 30;
+pub(crate) const MAX_TTL: u32 = 604800;
+// maid splits longer content into strings of 255 bytes, see RFC 1035 3.3.14
+const MAX_TXT_LENGTH: usize = 2048;
+
+/// Replaces the name with its normalized form or reports why it is invalid.
+pub(crate) fn normalize_name(
+  name: &mut String,
+  field: &str,
+  zone: &str,
+  normalize: fn(&str, &str) -> Result<String, NameError>,
+  errors: &mut FieldErrors,
+) {
+  match normalize(name, zone) {
+    Ok(normalized) => *name = normalized,
+    Err(err) => errors.add(field, err.code, err.message),
+  }
+}
 
 pub(crate) trait RecordRequestTrait<A: ActiveModelTrait> {
   fn into_active_model(self, id: ActiveValue<Uuid>) -> A;
+
+  fn record_type() -> RecordType;
+
+  /// Validates the type specific fields, names are normalized in place.
+  fn normalize(&mut self, _zone: &str, _errors: &mut FieldErrors) {}
+
+  /// Whether records of this type can be weighted and health checked.
+  fn balanced() -> bool {
+    false
+  }
 }
 
 #[derive(Deserialize)]
-pub(crate) struct RecordARequest {
-  addr: Ipv4Addr,
+pub(crate) struct RecordCommonReq {
+  pub(crate) name: String,
+  pub(crate) ttl: Option<u32>,
+  pub(crate) view_id: Option<Uuid>,
+  pub(crate) country: Option<String>,
+  pub(crate) continent: Option<String>,
+  pub(crate) weight: Option<u32>,
+  pub(crate) health_check: Option<HealthCheckReq>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HealthCheckProtocol {
+  Http,
+  Tcp,
+}
+
+impl HealthCheckProtocol {
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      Self::Http => "http",
+      Self::Tcp => "tcp",
+    }
+  }
 }
 
 #[derive(Deserialize)]
+pub(crate) struct HealthCheckReq {
+  pub(crate) protocol: HealthCheckProtocol,
+  pub(crate) port: u16,
+  pub(crate) path: Option<String>,
+  pub(crate) interval: Option<u32>,
+}
+
+impl RecordCommonReq {
+  /// Country codes have to be ISO 3166 alpha-2, continents one of
+  /// `CONTINENTS`. Weights and health checks only make sense for address
+  /// records, see `RecordRequestTrait::balanced`.
+  pub(crate) fn normalize(&mut self, zone: &str, balanced: bool, errors: &mut FieldErrors) {
+    normalize_name(&mut self.name, "name", zone, record_name, errors);
+
+    if self
+      .ttl
+      .is_some_and(|ttl| !(MIN_TTL..=MAX_TTL).contains(&ttl))
+    {
+      errors.add(
+        "ttl",
+        "out_of_range",
+        format!("must be between {MIN_TTL} and {MAX_TTL} seconds"),
+      );
+    }
+
+    if let Some(country) = &self.country {
+      if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+        errors.add(
+          "country",
+          "invalid_country",
+          "expected an ISO 3166 alpha-2 country code",
+        );
+      }
+    }
+    if let Some(continent) = &self.continent {
+      if !CONTINENTS.contains(&continent.to_ascii_uppercase().as_str()) {
+        errors.add(
+          "continent",
+          "invalid_continent",
+          format!("expected one of {}", CONTINENTS.join(", ")),
+        );
+      }
+    }
+
+    if !balanced {
+      if self.weight.is_some() {
+        errors.add(
+          "weight",
+          "not_balanced",
+          "only address records can be weighted",
+        );
+      }
+      if self.health_check.is_some() {
+        errors.add(
+          "health_check",
+          "not_balanced",
+          "only address records can be health checked",
+        );
+      }
+      return;
+    }
+
+    if self.weight.is_some_and(|weight| weight > i32::MAX as u32) {
+      errors.add(
+        "weight",
+        "out_of_range",
+        format!("must not exceed {}", i32::MAX),
+      );
+    }
+
+    if let Some(check) = &self.health_check {
+      if check.port == 0 {
+        errors.add("health_check.port", "out_of_range", "must not be 0");
+      }
+      if check
+        .interval
+        .is_some_and(|interval| !(1..=86400).contains(&interval))
+      {
+        errors.add(
+          "health_check.interval",
+          "out_of_range",
+          "must be between 1 and 86400 seconds",
+        );
+      }
+      match (check.protocol, &check.path) {
+        (HealthCheckProtocol::Http, Some(path)) if !path.starts_with('/') || path.len() > 255 => {
+          errors.add(
+            "health_check.path",
+            "invalid_path",
+            "expected an absolute path of at most 255 characters",
+          );
+        }
+        (HealthCheckProtocol::Tcp, Some(_)) => {
+          errors.add(
+            "health_check.path",
+            "invalid_path",
+            "tcp health checks have no path",
+          );
+        }
+        _ => {}
+      }
+    }
+  }
+}
+
+/// Type specific part of a record of any type, tagged by `"type"`.
+#[derive(Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "UPPERCASE")]
+pub(crate) enum AnyRecordRequest {
+  A(RecordARequest),
+  Aaaa(RecordAaaaRequest),
+  Cname(RecordCnameRequest),
+  Mx(RecordMxRequest),
+  Ns(RecordNsRequest),
+  Txt(RecordTxtRequest),
+}
+
+impl AnyRecordRequest {
+  pub(crate) fn record_type(&self) -> RecordType {
+    match self {
+      Self::A(_) => RecordType::A,
+      Self::Aaaa(_) => RecordType::AAAA,
+      Self::Cname(_) => RecordType::CNAME,
+      Self::Mx(_) => RecordType::MX,
+      Self::Ns(_) => RecordType::NS,
+      Self::Txt(_) => RecordType::TXT,
+    }
+  }
+
+  /// Whether an existing record holds the same data, names have to be
+  /// normalized already.
+  pub(crate) fn matches(&self, record: &AnyRecord) -> bool {
+    match (self, record) {
+      (Self::A(req), AnyRecord::A(record)) => record.addr.parse().ok() == Some(req.addr),
+      (Self::Aaaa(req), AnyRecord::Aaaa(record)) => record.addr.parse().ok() == Some(req.addr),
+      (Self::Cname(req), AnyRecord::Cname(record)) => req.target == record.target,
+      (Self::Mx(req), AnyRecord::Mx(record)) => {
+        record.preference == req.preference as i32 && req.exchange == record.exchange
+      }
+      (Self::Ns(req), AnyRecord::Ns(record)) => req.target == record.target,
+      (Self::Txt(req), AnyRecord::Txt(record)) => req.content == record.content,
+      _ => false,
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "UPPERCASE")]
+pub(crate) enum AnyRecord {
+  A(record_a::Model),
+  Aaaa(record_aaaa::Model),
+  Cname(record_cname::Model),
+  Mx(record_mx::Model),
+  Ns(record_ns::Model),
+  Txt(record_txt::Model),
+}
+
+impl AnyRecord {
+  pub(crate) fn record_type(&self) -> RecordType {
+    match self {
+      Self::A(_) => RecordType::A,
+      Self::Aaaa(_) => RecordType::AAAA,
+      Self::Cname(_) => RecordType::CNAME,
+      Self::Mx(_) => RecordType::MX,
+      Self::Ns(_) => RecordType::NS,
+      Self::Txt(_) => RecordType::TXT,
+    }
+  }
+
+  /// The record as maid answers it.
+  pub(crate) fn into_rdata(self, origin: &Name) -> Result<RData, EntityError> {
+    match self {
+      Self::A(record) => record.into_record(origin),
+      Self::Aaaa(record) => record.into_record(origin),
+      Self::Cname(record) => record.into_record(origin),
+      Self::Mx(record) => record.into_record(origin),
+      Self::Ns(record) => record.into_record(origin),
+      Self::Txt(record) => record.into_record(origin),
+    }
+  }
+}
+
+#[derive(Deserialize, PartialEq)]
+pub(crate) struct RecordARequest {
+  pub(crate) addr: Ipv4Addr,
+}
+
+#[derive(Deserialize, PartialEq)]
 pub(crate) struct RecordAaaaRequest {
-  addr: Ipv6Addr,
+  pub(crate) addr: Ipv6Addr,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 pub(crate) struct RecordCnameRequest {
-  target: String,
+  pub(crate) target: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 pub(crate) struct RecordMxRequest {
-  preference: u16,
-  exchange: String,
+  pub(crate) preference: u16,
+  pub(crate) exchange: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 pub(crate) struct RecordNsRequest {
-  target: String,
+  pub(crate) target: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 pub(crate) struct RecordTxtRequest {
-  content: String,
+  pub(crate) content: String,
 }
 
 impl RecordRequestTrait<record_a::ActiveModel> for RecordARequest {
@@ -48,6 +295,14 @@ impl RecordRequestTrait<record_a::ActiveModel> for RecordARequest {
       addr: ActiveValue::Set(self.addr.to_string()),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::A
+  }
+
+  fn balanced() -> bool {
+    true
+  }
 }
 
 impl RecordRequestTrait<record_aaaa::ActiveModel> for RecordAaaaRequest {
@@ -57,6 +312,14 @@ impl RecordRequestTrait<record_aaaa::ActiveModel> for RecordAaaaRequest {
       addr: ActiveValue::Set(self.addr.to_string()),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::AAAA
+  }
+
+  fn balanced() -> bool {
+    true
+  }
 }
 
 impl RecordRequestTrait<record_cname::ActiveModel> for RecordCnameRequest {
@@ -66,6 +329,18 @@ impl RecordRequestTrait<record_cname::ActiveModel> for RecordCnameRequest {
       target: ActiveValue::Set(self.target),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::CNAME
+  }
+
+  fn normalize(&mut self, zone: &str, errors: &mut FieldErrors) {
+    normalize_name(&mut self.target, "target", zone, target_name, errors);
+  }
+
+  fn balanced() -> bool {
+    true
+  }
 }
 
 impl RecordRequestTrait<record_mx::ActiveModel> for RecordMxRequest {
@@ -76,6 +351,14 @@ impl RecordRequestTrait<record_mx::ActiveModel> for RecordMxRequest {
       exchange: ActiveValue::Set(self.exchange),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::MX
+  }
+
+  fn normalize(&mut self, zone: &str, errors: &mut FieldErrors) {
+    normalize_name(&mut self.exchange, "exchange", zone, target_name, errors);
+  }
 }
 
 impl RecordRequestTrait<record_ns::ActiveModel> for RecordNsRequest {
@@ -85,6 +368,14 @@ impl RecordRequestTrait<record_ns::ActiveModel> for RecordNsRequest {
       target: ActiveValue::Set(self.target),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::NS
+  }
+
+  fn normalize(&mut self, zone: &str, errors: &mut FieldErrors) {
+    normalize_name(&mut self.target, "target", zone, target_name, errors);
+  }
 }
 
 impl RecordRequestTrait<record_txt::ActiveModel> for RecordTxtRequest {
@@ -94,4 +385,18 @@ impl RecordRequestTrait<record_txt::ActiveModel> for RecordTxtRequest {
       content: ActiveValue::Set(self.content),
     }
   }
+
+  fn record_type() -> RecordType {
+    RecordType::TXT
+  }
+
+  fn normalize(&mut self, _zone: &str, errors: &mut FieldErrors) {
+    if self.content.len() > MAX_TXT_LENGTH {
+      errors.add(
+        "content",
+        "too_long",
+        format!("must not exceed {MAX_TXT_LENGTH} bytes"),
+      );
+    }
+  }
 }
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use hickory_proto::rr::RecordType;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use entity::prelude::RecordHealthCheck;
+use entity::{record, record_health_check};
+
+use crate::error::ApiError;
+use crate::service::record::rrset::{same_answer, RRsets};
+use crate::service::record::DEFAULT_CHECK_INTERVAL;
+use crate::service::record::{
+  insert_any, remove_any, touch_zone, update_any, validate_any, zone_records,
+};
+use crate::service::{
+  zone_write_access, AnyRecord, AnyRecordRequest, HealthCheckReq, RecordCommonReq, RecordService,
+};
+
+/// Record of the desired state of a zone.
+#[derive(Deserialize)]
+pub(crate) struct DesiredRecord {
+  #[serde(flatten)]
+  common: RecordCommonReq,
+  #[serde(flatten)]
+  specific: AnyRecordRequest,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DiffRecord {
+  #[serde(flatten)]
+  common: record::Model,
+  #[serde(flatten)]
+  specific: AnyRecord,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ModifiedRecord {
+  before: DiffRecord,
+  after: DiffRecord,
+}
+
+/// Changes needed to reach the desired state, applied unless it is a dry run.
+#[derive(Serialize)]
+pub(crate) struct ZoneDiff {
+  dry_run: bool,
+  created: Vec<DiffRecord>,
+  /// Records only differing in ttl, weight or health check keep their id
+  modified: Vec<ModifiedRecord>,
+  deleted: Vec<DiffRecord>,
+  unchanged: usize,
+}
+
+fn same_health_check(
+  current: Option<&record_health_check::Model>,
+  desired: &Option<HealthCheckReq>,
+) -> bool {
+  match (current, desired) {
+    (None, None) => true,
+    (Some(current), Some(desired)) => {
+      current.protocol == desired.protocol.as_str()
+        && current.port == desired.port as i32
+        && current.path == desired.path
+        && current.check_interval
+          == desired
+            .interval
+            .map_or(DEFAULT_CHECK_INTERVAL, |interval| interval as i32)
+    }
+    _ => false,
+  }
+}
+
+/// Records of an RRset share their ttl, the same record must not be given
+/// twice.
+fn check_desired(records: &[DesiredRecord]) -> Result<(), ApiError> {
+  type RRset<'a> = (
+    &'a str,
+    RecordType,
+    Option<Uuid>,
+    Option<String>,
+    Option<String>,
+  );
+  let mut rrsets: HashMap<RRset<'_>, Vec<&DesiredRecord>> = HashMap::new();
+
+  for (i, record) in records.iter().enumerate() {
+    let common = &record.common;
+    let key = (
+      common.name.as_str(),
+      record.specific.record_type(),
+      common.view_id,
+      common.country.as_ref().map(|x| x.to_ascii_uppercase()),
+      common.continent.as_ref().map(|x| x.to_ascii_uppercase()),
+    );
+    let rrset = rrsets.entry(key).or_default();
+
+    if rrset.iter().any(|other| other.specific == record.specific) {
+      return Err(
+        ApiError::field(
+          "name",
+          "duplicate_record",
+          format!("{} is given twice", common.name),
+        )
+        .within(&format!("[{i}]")),
+      );
+    }
+    if rrset.iter().any(|other| other.common.ttl != common.ttl) {
+      return Err(
+        ApiError::conflict(
+          "ttl_mismatch",
+          format!(
+            "the other {} records of {} have a different ttl",
+            record.specific.record_type(),
+            common.name
+          ),
+        )
+        .within(&format!("[{i}]")),
+      );
+    }
+    rrset.push(record);
+  }
+
+  Ok(())
+}
+
+impl RecordService {
+  /// Makes the records of the zone match the desired ones. Records with the
+  /// same name, type, data, view and location are kept, the others deleted
+  /// or created. The changes are applied in a single transaction, a dry run
+  /// rolls it back.
+  pub(crate) async fn replace(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    mut desired: Vec<DesiredRecord>,
+    dry_run: bool,
+  ) -> anyhow::Result<ZoneDiff> {
+    let tx = self.db.begin().await?;
+    let zone = zone_write_access(&tx, user_id, zone_id).await?;
+
+    for (i, record) in desired.iter_mut().enumerate() {
+      validate_any(&zone, &mut record.common, &mut record.specific)
+        .map_err(|err| err.within(&format!("[{i}]")))?;
+    }
+    check_desired(&desired)?;
+
+    let current = zone_records(&tx, zone_id).await?;
+    let mut rrsets = RRsets::new(&current);
+    let health_checks: HashMap<Uuid, record_health_check::Model> = RecordHealthCheck::find()
+      .filter(record_health_check::Column::Id.is_in(current.iter().map(|(record, _)| record.id)))
+      .all(&tx)
+      .await?
+      .into_iter()
+      .map(|check| (check.id, check))
+      .collect();
+
+    // matched records are taken out, whatever is left over is deleted
+    let mut current: Vec<_> = current.into_iter().map(Some).collect();
+    let mut modify = Vec::new();
+    let mut create = Vec::new();
+    let mut unchanged = 0;
+    for (i, record) in desired.into_iter().enumerate() {
+      let matching = current.iter_mut().find(|entry| {
+        entry.as_ref().is_some_and(|(existing, specific)| {
+          existing.name == record.common.name
+            && same_answer(existing, &record.common)
+            && record.specific.matches(specific)
+        })
+      });
+
+      match matching.and_then(Option::take) {
+        Some((existing, specific)) => {
+          let same = existing.ttl == record.common.ttl.map(|ttl| ttl as i32)
+            && existing.weight == record.common.weight.map(|weight| weight as i32)
+            && same_health_check(health_checks.get(&existing.id), &record.common.health_check);
+          if same {
+            unchanged += 1;
+          } else {
+            modify.push((i, existing, specific, record));
+          }
+        }
+        None => create.push((i, record)),
+      }
+    }
+
+    // deleted first, so records of the desired state do not conflict with
+    // the ones they replace
+    let mut deleted = Vec::new();
+    for (common, specific) in current.into_iter().flatten() {
+      remove_any(&tx, common.id, &specific).await?;
+      rrsets.removed(common.id);
+      deleted.push(DiffRecord { common, specific });
+    }
+
+    // the ttls of the desired RRsets are consistent, so differing ones are
+    // only left over until the whole RRset is written
+    let mut modified = Vec::new();
+    for (i, common, specific, record) in modify {
+      let (after, after_specific) = update_any(
+        &tx,
+        &zone,
+        &mut rrsets,
+        common.id,
+        record.common,
+        record.specific,
+        true,
+      )
+      .await
+      .map_err(|err| err.within(&format!("[{i}]")))?;
+      modified.push(ModifiedRecord {
+        before: DiffRecord { common, specific },
+        after: DiffRecord {
+          common: after,
+          specific: after_specific,
+        },
+      });
+    }
+
+    let mut created = Vec::new();
+    for (i, record) in create {
+      let (common, specific) = insert_any(
+        &tx,
+        &zone,
+        &mut rrsets,
+        record.common,
+        record.specific,
+        true,
+      )
+      .await
+      .map_err(|err| err.within(&format!("[{i}]")))?;
+      created.push(DiffRecord { common, specific });
+    }
+
+    let changed = !(created.is_empty() && modified.is_empty() && deleted.is_empty());
+    if dry_run {
+      tx.rollback().await?;
+    } else {
+      if changed {
+        touch_zone(&tx, zone.id).await?;
+      }
+      tx.commit().await?;
+    }
+
+    Ok(ZoneDiff {
+      dry_run,
+      created,
+      modified,
+      deleted,
+      unchanged,
+    })
+  }
+}
@@ -0,0 +1,450 @@
+use hickory_proto::rr::RecordType;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use entity::prelude::Record;
+use entity::record;
+
+use crate::error::ApiError;
+use crate::service::record::zone_records;
+use crate::service::{AnyRecord, RecordCommonReq};
+
+/// Whether `name` is `ancestor` or below it, both relative to the zone.
+fn is_at_or_below(name: &str, ancestor: &str) -> bool {
+  name.eq_ignore_ascii_case(ancestor)
+    || (name.len() > ancestor.len()
+      && name.as_bytes()[name.len() - ancestor.len() - 1] == b'.'
+      && name[name.len() - ancestor.len()..].eq_ignore_ascii_case(ancestor))
+}
+
+/// Views and locations answer instead of each other, so only records sharing
+/// both form an RRset.
+pub(super) fn same_answer(record: &record::Model, common: &RecordCommonReq) -> bool {
+  let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+    (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+    (None, None) => true,
+    _ => false,
+  };
+
+  record.view_id == common.view_id
+    && same(&record.country, &common.country)
+    && same(&record.continent, &common.continent)
+}
+
+/// Names and types of the records of a zone as they are within the current
+/// transaction, loaded once and kept up to date with every write.
+pub(super) struct RRsets {
+  records: Vec<(record::Model, RecordType)>,
+}
+
+impl RRsets {
+  pub(super) fn new(records: &[(record::Model, AnyRecord)]) -> Self {
+    Self {
+      records: records
+        .iter()
+        .map(|(record, specific)| (record.clone(), specific.record_type()))
+        .collect(),
+    }
+  }
+
+  pub(super) async fn load<C: ConnectionTrait>(db: &C, zone_id: Uuid) -> Result<Self, DbErr> {
+    Ok(Self::new(&zone_records(db, zone_id).await?))
+  }
+
+  /// Takes note of a record that was inserted or updated.
+  pub(super) fn written(&mut self, record: &record::Model, record_type: RecordType) {
+    self.removed(record.id);
+    self.records.push((record.clone(), record_type));
+  }
+
+  pub(super) fn removed(&mut self, record_id: Uuid) {
+    self.records.retain(|(record, _)| record.id != record_id);
+  }
+
+  /// Enforces the rules of RFC 1034 and RFC 2181 on the record about to be
+  /// written (`record_id` if it exists already). TTLs differing within the
+  /// RRset are either rejected or changed to the record's one.
+  pub(super) async fn check<C: ConnectionTrait>(
+    &mut self,
+    db: &C,
+    record_id: Option<Uuid>,
+    record_type: RecordType,
+    common: &RecordCommonReq,
+    harmonize_ttl: bool,
+  ) -> Result<(), ApiError> {
+    let name = common.name.as_str();
+    let records: Vec<_> = self
+      .records
+      .iter()
+      .filter(|(record, _)| Some(record.id) != record_id)
+      .collect();
+
+    // see RFC 1034 3.6.2 and RFC 2181 10.1
+    if record_type == RecordType::CNAME {
+      if name == "@" {
+        return Err(ApiError::field(
+          "name",
+          "cname_at_apex",
+          "the apex of a zone can not be an alias",
+        ));
+      }
+      if let Some((_, other)) = records.iter().find(|(record, other)| {
+        record.name.eq_ignore_ascii_case(name) && *other != RecordType::CNAME
+      }) {
+        return Err(ApiError::conflict(
+          "cname_conflict",
+          format!("{name} already has {other} records, an alias can not have others"),
+        ));
+      }
+      // alternatives are picked by weight, without any all would be answered
+      let alternatives: Vec<_> = records
+        .iter()
+        .filter(|(record, other)| {
+          record.name.eq_ignore_ascii_case(name)
+            && *other == RecordType::CNAME
+            && same_answer(record, common)
+        })
+        .collect();
+      if !alternatives.is_empty()
+        && common.weight.is_none()
+        && alternatives
+          .iter()
+          .all(|(record, _)| record.weight.is_none())
+      {
+        return Err(ApiError::conflict(
+          "cname_conflict",
+          format!("{name} already is an alias, weight them to answer with either"),
+        ));
+      }
+    } else if records
+      .iter()
+      .any(|(record, other)| record.name.eq_ignore_ascii_case(name) && *other == RecordType::CNAME)
+    {
+      return Err(ApiError::conflict(
+        "cname_conflict",
+        format!("{name} is an alias, it can not have other records"),
+      ));
+    }
+
+    // a delegation only holds NS records, below it only glue addresses, see
+    // RFC 1034 4.2.1
+    if record_type == RecordType::NS && name != "@" {
+      if let Some((record, other)) = records.iter().find(|(record, other)| {
+        is_at_or_below(&record.name, name)
+          && if record.name.eq_ignore_ascii_case(name) {
+            *other != RecordType::NS
+          } else {
+            !matches!(other, RecordType::A | RecordType::AAAA)
+          }
+      }) {
+        return Err(ApiError::conflict(
+          "delegation_conflict",
+          format!(
+            "{} has {} records, which would be hidden by delegating {}",
+            record.name, other, name
+          ),
+        ));
+      }
+    }
+
+    let glue = matches!(record_type, RecordType::A | RecordType::AAAA);
+    if let Some((record, _)) = records.iter().find(|(record, other)| {
+      let at = record.name.eq_ignore_ascii_case(name);
+      *other == RecordType::NS
+        && record.name != "@"
+        && is_at_or_below(name, &record.name)
+        && !(at && record_type == RecordType::NS)
+        && (at || !glue)
+    }) {
+      return Err(ApiError::conflict(
+        "delegation_conflict",
+        format!(
+          "{} is delegated, only glue addresses can be added below it",
+          record.name
+        ),
+      ));
+    }
+
+    // see RFC 2181 5.2
+    let ttl = common.ttl.map(|ttl| ttl as i32);
+    let differing: Vec<Uuid> = records
+      .iter()
+      .filter(|(record, other)| {
+        record.name.eq_ignore_ascii_case(name)
+          && *other == record_type
+          && same_answer(record, common)
+          && record.ttl != ttl
+      })
+      .map(|(record, _)| record.id)
+      .collect();
+
+    if differing.is_empty() {
+      return Ok(());
+    }
+    if !harmonize_ttl {
+      return Err(ApiError::conflict(
+        "ttl_mismatch",
+        format!("the other {record_type} records of {name} have a different ttl, harmonize them"),
+      ));
+    }
+
+    let now = OffsetDateTime::now_utc();
+    Record::update_many()
+      .col_expr(record::Column::Ttl, Expr::value(ttl))
+      .col_expr(record::Column::Updated, Expr::value(now))
+      .filter(record::Column::Id.is_in(differing.clone()))
+      .exec(db)
+      .await?;
+
+    for (record, _) in &mut self.records {
+      if differing.contains(&record.id) {
+        record.ttl = ttl;
+        record.updated = now;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use sea_orm::DatabaseConnection;
+
+  use entity::{record_a, record_cname, record_ns, record_txt};
+
+  use super::*;
+
+  fn record(name: &str, record_type: RecordType) -> (record::Model, AnyRecord) {
+    let id = Uuid::new_v4();
+    let now = OffsetDateTime::now_utc();
+    let common = record::Model {
+      id,
+      created: now,
+      updated: now,
+      name: name.to_string(),
+      zone_id: Uuid::nil(),
+      ttl: Some(300),
+      view_id: None,
+      country: None,
+      continent: None,
+      weight: None,
+    };
+    let specific = match record_type {
+      RecordType::A => AnyRecord::A(record_a::Model {
+        id,
+        addr: "192.0.2.1".to_string(),
+      }),
+      RecordType::CNAME => AnyRecord::Cname(record_cname::Model {
+        id,
+        target: "www.example.net.".to_string(),
+      }),
+      RecordType::NS => AnyRecord::Ns(record_ns::Model {
+        id,
+        target: "ns.example.net.".to_string(),
+      }),
+      _ => AnyRecord::Txt(record_txt::Model {
+        id,
+        content: "text".to_string(),
+      }),
+    };
+
+    (common, specific)
+  }
+
+  fn weighted(mut record: (record::Model, AnyRecord)) -> (record::Model, AnyRecord) {
+    record.0.weight = Some(1);
+    record
+  }
+
+  fn common(name: &str) -> RecordCommonReq {
+    RecordCommonReq {
+      name: name.to_string(),
+      ttl: Some(300),
+      view_id: None,
+      country: None,
+      continent: None,
+      weight: None,
+      health_check: None,
+    }
+  }
+
+  // code of the error writing a record with `common` would fail with
+  async fn check_common(
+    records: &[(record::Model, AnyRecord)],
+    record_type: RecordType,
+    common: RecordCommonReq,
+  ) -> &'static str {
+    // every check failing or passing here is done before touching the database
+    let db = DatabaseConnection::Disconnected;
+    match RRsets::new(records)
+      .check(&db, None, record_type, &common, false)
+      .await
+    {
+      Ok(()) => "ok",
+      Err(err) => err.code(),
+    }
+  }
+
+  async fn check(
+    records: &[(record::Model, AnyRecord)],
+    name: &str,
+    record_type: RecordType,
+  ) -> &'static str {
+    check_common(records, record_type, common(name)).await
+  }
+
+  #[test]
+  fn at_or_below() {
+    assert!(is_at_or_below("sub", "sub"));
+    assert!(is_at_or_below("Sub", "sUB"));
+    assert!(is_at_or_below("ns.sub", "sub"));
+    assert!(is_at_or_below("a.b.sub", "sub"));
+    assert!(!is_at_or_below("nssub", "sub"));
+    assert!(!is_at_or_below("sub", "ns.sub"));
+    assert!(!is_at_or_below("sub.other", "sub"));
+  }
+
+  #[tokio::test]
+  async fn cname_exclusivity() {
+    let a = [record("www", RecordType::A)];
+    let cname = [record("www", RecordType::CNAME)];
+
+    assert_eq!(check(&[], "@", RecordType::CNAME).await, "cname_at_apex");
+    assert_eq!(check(&a, "www", RecordType::CNAME).await, "cname_conflict");
+    assert_eq!(check(&a, "WWW", RecordType::CNAME).await, "cname_conflict");
+    assert_eq!(check(&cname, "www", RecordType::A).await, "cname_conflict");
+    assert_eq!(
+      check(&cname, "www", RecordType::TXT).await,
+      "cname_conflict"
+    );
+    assert_eq!(check(&a, "mail", RecordType::CNAME).await, "ok");
+    assert_eq!(check(&cname, "mail", RecordType::A).await, "ok");
+  }
+
+  #[tokio::test]
+  async fn weighted_cname_alternatives() {
+    let cname = [record("www", RecordType::CNAME)];
+    let weighted_cname = [weighted(record("www", RecordType::CNAME))];
+
+    assert_eq!(
+      check(&cname, "www", RecordType::CNAME).await,
+      "cname_conflict"
+    );
+
+    let mut with_weight = common("www");
+    with_weight.weight = Some(1);
+    assert_eq!(
+      check_common(&cname, RecordType::CNAME, with_weight).await,
+      "ok"
+    );
+    assert_eq!(check(&weighted_cname, "www", RecordType::CNAME).await, "ok");
+
+    // answers of other views are not alternatives
+    let mut other_view = common("www");
+    other_view.view_id = Some(Uuid::new_v4());
+    assert_eq!(
+      check_common(&cname, RecordType::CNAME, other_view).await,
+      "ok"
+    );
+  }
+
+  #[tokio::test]
+  async fn delegation() {
+    let a = [record("sub", RecordType::A)];
+    let txt_below = [record("x.sub", RecordType::TXT)];
+    let glue = [
+      record("ns.sub", RecordType::A),
+      record("ns.deep.sub", RecordType::A),
+    ];
+    let ns = [record("sub", RecordType::NS)];
+
+    // NS below an existing name hides it
+    assert_eq!(
+      check(&a, "sub", RecordType::NS).await,
+      "delegation_conflict"
+    );
+    assert_eq!(
+      check(&txt_below, "sub", RecordType::NS).await,
+      "delegation_conflict"
+    );
+    assert_eq!(check(&glue, "sub", RecordType::NS).await, "ok");
+    assert_eq!(check(&ns, "sub", RecordType::NS).await, "ok");
+    assert_eq!(check(&a, "nssub", RecordType::NS).await, "ok");
+    // the apex is no delegation
+    assert_eq!(check(&a, "@", RecordType::NS).await, "ok");
+  }
+
+  #[tokio::test]
+  async fn below_delegation() {
+    let ns = [record("sub", RecordType::NS)];
+    let apex = [record("@", RecordType::NS)];
+
+    // glue
+    assert_eq!(check(&ns, "ns.sub", RecordType::A).await, "ok");
+    assert_eq!(check(&ns, "ns.sub", RecordType::AAAA).await, "ok");
+    assert_eq!(
+      check(&ns, "x.sub", RecordType::TXT).await,
+      "delegation_conflict"
+    );
+    assert_eq!(
+      check(&ns, "deep.sub", RecordType::NS).await,
+      "delegation_conflict"
+    );
+    assert_eq!(
+      check(&ns, "sub", RecordType::A).await,
+      "delegation_conflict"
+    );
+    assert_eq!(
+      check(&ns, "x.sub", RecordType::CNAME).await,
+      "delegation_conflict"
+    );
+    assert_eq!(check(&ns, "subway", RecordType::TXT).await, "ok");
+    assert_eq!(check(&apex, "www", RecordType::TXT).await, "ok");
+  }
+
+  #[tokio::test]
+  async fn ttls() {
+    let a = [record("www", RecordType::A)];
+
+    assert_eq!(check(&a, "www", RecordType::A).await, "ok");
+    assert_eq!(check(&a, "mail", RecordType::A).await, "ok");
+    assert_eq!(check(&a, "www", RecordType::TXT).await, "ok");
+
+    let mut longer = common("www");
+    longer.ttl = Some(600);
+    assert_eq!(
+      check_common(&a, RecordType::A, longer).await,
+      "ttl_mismatch"
+    );
+
+    let mut default = common("www");
+    default.ttl = None;
+    assert_eq!(
+      check_common(&a, RecordType::A, default).await,
+      "ttl_mismatch"
+    );
+
+    let mut other_country = common("www");
+    other_country.ttl = Some(600);
+    other_country.country = Some("de".to_string());
+    assert_eq!(check_common(&a, RecordType::A, other_country).await, "ok");
+
+    // a record does not conflict with its former self
+    let mut rrsets = RRsets::new(&a);
+    let mut changed = common("www");
+    changed.ttl = Some(600);
+    let result = rrsets
+      .check(
+        &DatabaseConnection::Disconnected,
+        Some(a[0].0.id),
+        RecordType::A,
+        &changed,
+        false,
+      )
+      .await;
+    assert!(result.is_ok());
+  }
+}
@@ -0,0 +1,402 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use clap::Args;
+use hickory_client::client::AsyncClient;
+use hickory_client::udp::UdpClientStream;
+use hickory_proto::op::{Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+use hickory_proto::xfer::{DnsHandle, DnsRequestOptions, DnsResponse, FirstAnswer};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+  ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+  DbErr, EntityTrait, QueryFilter, TransactionError, TransactionTrait,
+};
+use time::OffsetDateTime;
+use tokio::net::UdpSocket;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use entity::prelude::Zone;
+use entity::zone;
+
+use crate::error::ApiError;
+use crate::service::zone_access;
+
+/// Label below the zone the token is published at as TXT record.
+const CHALLENGE_LABEL: &str = "_rabauken-challenge";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+// how often zones due for a recheck are looked for
+const RECHECK_POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Args, Clone)]
+pub(crate) struct VerificationConfig {
+  /// Recursive resolver delegations and challenges are looked up with
+  #[arg(
+    long = "verification-resolver",
+    env = "CHEF_VERIFICATION_RESOLVER",
+    default_value = "9.9.9.9:53"
+  )]
+  resolver: SocketAddr,
+  /// Name servers zones are delegated to, a delegation to any of them
+  /// verifies a zone
+  #[arg(
+    long = "nameserver",
+    env = "CHEF_NAMESERVERS",
+    value_delimiter = ',',
+    default_value = "ns.dns.dresden.zone."
+  )]
+  nameservers: Vec<Name>,
+  /// Hours after which verified zones are checked again
+  #[arg(
+    long = "verification-interval",
+    env = "CHEF_VERIFICATION_INTERVAL",
+    default_value_t = 24
+  )]
+  interval: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Method {
+  Delegation,
+  Txt,
+}
+
+impl Method {
+  fn as_str(self) -> &'static str {
+    match self {
+      Self::Delegation => "delegation",
+      Self::Txt => "txt",
+    }
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct VerificationService {
+  db: Arc<DatabaseConnection>,
+  config: VerificationConfig,
+}
+
+impl VerificationService {
+  pub(crate) fn new(db: Arc<DatabaseConnection>, config: VerificationConfig) -> Self {
+    Self { db, config }
+  }
+
+  /// Checks the zone right away, fails with 409 if it can not be verified.
+  pub(crate) async fn verify(&self, user_id: Uuid, zone_id: Uuid) -> anyhow::Result<zone::Model> {
+    let zone = zone_access(self.db.as_ref(), user_id, zone_id).await?;
+    let claimed = verified_namesake(self.db.as_ref(), &zone).await?;
+    let taken = claimed.is_some();
+
+    let method = self.check(&zone, claimed.is_none()).await.map_err(|err| {
+      warn!("Unable to check zone {}: {:#}", zone.name, err);
+      ApiError::bad_gateway(
+        "lookup_failed",
+        format!("unable to look up {}, try again later", zone.name),
+      )
+    })?;
+
+    let zone = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          // only the one in control of the name can publish the token, the
+          // zone verified before is not served anymore
+          if let (Some(_), Some(claimed)) = (method, claimed) {
+            info!(
+              "Zone {} of {} passed the challenge, zone {} is no longer verified",
+              zone.name, zone.owner, claimed.id
+            );
+            save(tx, claimed, None).await?;
+          }
+          save(tx, zone, method).await
+        })
+      })
+      .await
+      .map_err(|err| match err {
+        TransactionError::Connection(err) | TransactionError::Transaction(err) => {
+          ApiError::from(err)
+        }
+      })?;
+
+    if !zone.verified && taken {
+      return Err(
+        ApiError::conflict(
+          "verification_failed",
+          format!(
+            "{} is verified for another zone, only the token as TXT record at {}.{} proves control",
+            zone.name, CHALLENGE_LABEL, zone.name,
+          ),
+        )
+        .into(),
+      );
+    }
+
+    if !zone.verified {
+      return Err(
+        ApiError::conflict(
+          "verification_failed",
+          format!(
+            "{} is neither delegated to {} nor has {}.{} the token as TXT record",
+            zone.name,
+            self
+              .config
+              .nameservers
+              .iter()
+              .map(Name::to_ascii)
+              .collect::<Vec<_>>()
+              .join(", "),
+            CHALLENGE_LABEL,
+            zone.name,
+          ),
+        )
+        .into(),
+      );
+    }
+
+    Ok(zone)
+  }
+
+  /// Keeps checking verified zones, those no longer passing are not served
+  /// anymore. Lookups failing leave the zone as it is.
+  pub(crate) async fn run_recheck(self: Arc<Self>) {
+    let mut interval = interval(RECHECK_POLL_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      if let Err(err) = self.recheck_due().await {
+        error!("Unable to recheck verified zones: {}", err);
+      }
+    }
+  }
+
+  async fn recheck_due(&self) -> anyhow::Result<()> {
+    let due = OffsetDateTime::now_utc() - Duration::from_secs(self.config.interval * 3600);
+    let zones = Zone::find()
+      .filter(zone::Column::Verified.eq(true))
+      .filter(zone::Column::Deleted.is_null())
+      .filter(
+        Condition::any()
+          .add(zone::Column::VerificationChecked.is_null())
+          .add(zone::Column::VerificationChecked.lt(due)),
+      )
+      .all(self.db.as_ref())
+      .await?;
+
+    for zone in zones {
+      let method = match self.check(&zone, true).await {
+        Ok(method) => method,
+        Err(err) => {
+          warn!("Unable to recheck zone {}: {:#}", zone.name, err);
+          continue;
+        }
+      };
+      if method.is_none() {
+        info!(
+          "Zone {} failed its recheck, it is no longer verified",
+          zone.name
+        );
+      }
+
+      let name = zone.name.clone();
+      if let Err(err) = save(self.db.as_ref(), zone, method).await {
+        error!("Unable to save recheck of zone {}: {}", name, err);
+      }
+    }
+
+    Ok(())
+  }
+
+  /// How the zone is proven to be the owner's, if it is. Fails if neither
+  /// check got an answer. A delegation to us does not tell which of several
+  /// owners of a name it is meant for, so only the challenge counts unless
+  /// `by_delegation`.
+  async fn check(&self, zone: &zone::Model, by_delegation: bool) -> anyhow::Result<Option<Method>> {
+    let mut origin = Name::from_ascii(&zone.name)?;
+    origin.set_fqdn(true);
+
+    if !by_delegation {
+      let found = self.challenge(&origin, &zone.verification_token).await?;
+      return Ok(found.then_some(Method::Txt));
+    }
+
+    let delegation = self.delegation(&origin).await;
+    if let Ok(nameservers) = &delegation {
+      if nameservers
+        .iter()
+        .any(|nameserver| self.config.nameservers.contains(nameserver))
+      {
+        return Ok(Some(Method::Delegation));
+      }
+    }
+
+    let challenge = self.challenge(&origin, &zone.verification_token).await;
+    match (delegation, challenge) {
+      (_, Ok(true)) => Ok(Some(Method::Txt)),
+      (Ok(_), Ok(false)) => Ok(None),
+      (Err(err), _) | (_, Err(err)) => Err(err),
+    }
+  }
+
+  /// Name servers the parent zone delegates the zone to, asked directly as a
+  /// resolver would follow the delegation.
+  async fn delegation(&self, origin: &Name) -> anyhow::Result<Vec<Name>> {
+    // the closest enclosing zone cut, e.g. for zones below public suffixes
+    let mut parent = origin.base_name();
+    let parent_servers = loop {
+      let response = self
+        .query(self.config.resolver, parent.clone(), RecordType::NS, true)
+        .await?;
+      let servers = ns_names(&response, &parent);
+      if !servers.is_empty() {
+        break servers;
+      }
+      if parent.is_root() {
+        bail!("no name servers found for the root");
+      }
+      parent = parent.base_name();
+    };
+
+    for server in parent_servers {
+      let response = self
+        .query(self.config.resolver, server.clone(), RecordType::A, true)
+        .await?;
+      let addresses = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+          Some(RData::A(addr)) => Some(IpAddr::V4(addr.0)),
+          _ => None,
+        });
+
+      for addr in addresses {
+        match self
+          .query(
+            SocketAddr::new(addr, 53),
+            origin.clone(),
+            RecordType::NS,
+            false,
+          )
+          .await
+        {
+          Ok(response) => return Ok(ns_names(&response, origin)),
+          Err(err) => debug!("Name server {} of {} failed: {:#}", server, parent, err),
+        }
+      }
+    }
+
+    bail!("none of the name servers of {} answered", parent)
+  }
+
+  /// Whether a TXT record at the challenge label holds the token.
+  async fn challenge(&self, origin: &Name, token: &str) -> anyhow::Result<bool> {
+    let name = Name::from_ascii(CHALLENGE_LABEL)?.append_domain(origin)?;
+    let response = self
+      .query(self.config.resolver, name, RecordType::TXT, true)
+      .await?;
+
+    let found = response.answers().iter().any(|record| match record.data() {
+      Some(RData::TXT(txt)) => txt.txt_data().concat() == token.as_bytes(),
+      _ => false,
+    });
+
+    Ok(found)
+  }
+
+  /// Answers other than success or a missing name do not tell anything.
+  async fn query(
+    &self,
+    server: SocketAddr,
+    name: Name,
+    record_type: RecordType,
+    recursion_desired: bool,
+  ) -> anyhow::Result<DnsResponse> {
+    let stream = UdpClientStream::<UdpSocket>::with_timeout(server, QUERY_TIMEOUT);
+    let (client, background) = AsyncClient::connect(stream).await?;
+    let background = tokio::spawn(background);
+
+    let mut query = Query::query(name.clone(), record_type);
+    query.set_query_class(DNSClass::IN);
+    let mut options = DnsRequestOptions::default();
+    options.recursion_desired = recursion_desired;
+    let result = client.lookup(query, options).first_answer().await;
+
+    background.abort();
+    let response = result?;
+
+    match response.response_code() {
+      ResponseCode::NoError | ResponseCode::NXDomain => Ok(response),
+      code => bail!(
+        "{} {} was answered by {} with {}",
+        name,
+        record_type,
+        server,
+        code
+      ),
+    }
+  }
+}
+
+/// Verified zone of the same name, as names are only unique per owner.
+pub(crate) async fn verified_namesake<C: ConnectionTrait>(
+  db: &C,
+  zone: &zone::Model,
+) -> Result<Option<zone::Model>, DbErr> {
+  let name = zone.name.trim_end_matches('.').to_lowercase();
+
+  Zone::find()
+    .filter(zone::Column::Id.ne(zone.id))
+    .filter(zone::Column::Verified.eq(true))
+    .filter(zone::Column::Deleted.is_null())
+    .filter(Expr::cust_with_values(
+      "lower(rtrim(zone.name, '.')) = $1",
+      [name],
+    ))
+    .one(db)
+    .await
+}
+
+/// Records the outcome of a check, the time of verification is kept as long
+/// as the zone stays verified.
+async fn save<C: ConnectionTrait>(
+  db: &C,
+  zone: zone::Model,
+  method: Option<Method>,
+) -> Result<zone::Model, DbErr> {
+  let now = OffsetDateTime::now_utc();
+  let verified_at = match method {
+    Some(_) if zone.verified => zone.verified_at.or(Some(now)),
+    Some(_) => Some(now),
+    None => None,
+  };
+
+  zone::ActiveModel {
+    id: ActiveValue::Unchanged(zone.id),
+    verified: ActiveValue::Set(method.is_some()),
+    verification_method: ActiveValue::Set(method.map(|method| method.as_str().to_string())),
+    verified_at: ActiveValue::Set(verified_at),
+    verification_checked: ActiveValue::Set(Some(now)),
+    ..Default::default()
+  }
+  .update(db)
+  .await
+}
+
+/// Targets of the NS records of `owner` in the answer or, for referrals, the
+/// authority section.
+fn ns_names(response: &DnsResponse, owner: &Name) -> Vec<Name> {
+  response
+    .answers()
+    .iter()
+    .chain(response.name_servers())
+    .filter(|record| record.name() == owner)
+    .filter_map(|record| match record.data() {
+      Some(RData::NS(ns)) => Some(ns.0.clone()),
+      _ => None,
+    })
+    .collect()
+}
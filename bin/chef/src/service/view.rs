@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use ipnet::IpNet;
+use sea_orm::{
+  ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DatabaseTransaction, DbErr,
+  EntityTrait, QueryFilter, SqlErr, TransactionTrait,
+};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use entity::prelude::{View, Zone};
+use entity::{view, zone};
+
+use crate::error::ApiError;
+use crate::service::{view_access, zone_access};
+
+#[derive(Clone)]
+pub(crate) struct ViewService {
+  db: Arc<DatabaseConnection>,
+}
+
+impl ViewService {
+  pub(crate) fn new(db: Arc<DatabaseConnection>) -> Self {
+    Self { db }
+  }
+
+  pub(crate) async fn list(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+  ) -> anyhow::Result<Vec<view::Model>> {
+    zone_access(self.db.as_ref(), user_id, zone_id).await?;
+
+    let views = View::find()
+      .inner_join(Zone)
+      .filter(
+        view::Column::ZoneId
+          .eq(zone_id)
+          .and(zone::Column::Owner.eq(user_id)),
+      )
+      .all(self.db.as_ref())
+      .await?;
+
+    Ok(views)
+  }
+
+  pub(crate) async fn by_id(&self, user_id: Uuid, view_id: Uuid) -> anyhow::Result<view::Model> {
+    let view = view_access(self.db.as_ref(), user_id, view_id).await?;
+
+    Ok(view)
+  }
+
+  pub(crate) async fn create(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    name: String,
+    networks: Vec<IpNet>,
+  ) -> anyhow::Result<view::Model> {
+    let result = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          zone_access(tx, user_id, zone_id).await?;
+
+          let view = view::ActiveModel {
+            id: ActiveValue::NotSet,
+            created: ActiveValue::NotSet,
+            updated: ActiveValue::NotSet,
+            zone_id: ActiveValue::Set(zone_id),
+            name: ActiveValue::Set(name),
+            networks: ActiveValue::Set(networks.iter().map(IpNet::to_string).collect()),
+          };
+
+          let view = view.insert(tx).await.map_err(view_exists)?;
+          touch_zone(tx, zone_id).await?;
+
+          Ok::<_, ApiError>(view)
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(result)
+  }
+
+  pub(crate) async fn modify(
+    &self,
+    user_id: Uuid,
+    view_id: Uuid,
+    name: String,
+    networks: Vec<IpNet>,
+  ) -> anyhow::Result<view::Model> {
+    let result = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          view_access(tx, user_id, view_id).await?;
+
+          let view = view::ActiveModel {
+            id: ActiveValue::Unchanged(view_id),
+            created: ActiveValue::NotSet,
+            updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+            zone_id: ActiveValue::NotSet,
+            name: ActiveValue::Set(name),
+            networks: ActiveValue::Set(networks.iter().map(IpNet::to_string).collect()),
+          }
+          .update(tx)
+          .await
+          .map_err(view_exists)?;
+          touch_zone(tx, view.zone_id).await?;
+
+          Ok::<_, ApiError>(view)
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(result)
+  }
+
+  /// Fails as long as records are still assigned to the view, deleting them
+  /// or making them visible to everyone would both be a surprise.
+  pub(crate) async fn delete(&self, user_id: Uuid, view_id: Uuid) -> anyhow::Result<()> {
+    self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let view = view_access(tx, user_id, view_id).await?;
+
+          View::delete_by_id(view_id)
+            .exec(tx)
+            .await
+            .map_err(|err| match err.sql_err() {
+              Some(SqlErr::ForeignKeyConstraintViolation(_)) => {
+                ApiError::conflict("view_in_use", "records are still assigned to the view")
+              }
+              _ => err.into(),
+            })?;
+          touch_zone(tx, view.zone_id).await?;
+
+          Ok::<_, ApiError>(())
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(())
+  }
+}
+
+fn view_exists(err: DbErr) -> ApiError {
+  match err.sql_err() {
+    Some(SqlErr::UniqueConstraintViolation(_)) => {
+      ApiError::conflict("view_exists", "the zone already has a view of that name")
+    }
+    _ => err.into(),
+  }
+}
+
+/// Views change answers without touching any record, so the zone has to be
+/// bumped for the serial to change.
+async fn touch_zone(tx: &DatabaseTransaction, zone_id: Uuid) -> Result<(), DbErr> {
+  zone::ActiveModel {
+    id: ActiveValue::Unchanged(zone_id),
+    updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+    ..Default::default()
+  }
+  .update(tx)
+  .await?;
+
+  Ok(())
+}
@@ -1,45 +1,215 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+use hickory_proto::rr::{rdata, Name, RData, RecordType};
+
 use sea_orm::{
-  ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+  ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+  EntityTrait, QueryFilter, QuerySelect, SqlErr, TransactionTrait,
 };
-use std::sync::Arc;
+use serde::Deserialize;
+use time::macros::datetime;
+use time::OffsetDateTime;
+use tokio::time::interval;
+use tracing::{error, info};
 use uuid::Uuid;
 
-use entity::prelude::Zone;
-use entity::zone;
+use entity::prelude::{View, Zone, ZoneTransfer};
+use entity::{parse_name, view, zone};
+
+use crate::error::{ApiError, FieldErrors};
+use crate::service::zone_file::render;
+use crate::service::{
+  delete_zone_records, normalize_name, target_name, verified_namesake, view_access, zone_access,
+  zone_name, zone_records, MAX_TTL, MIN_TTL,
+};
+
+// serials count the seconds since, the same as in maid
+const SERIAL_EPOCH: OffsetDateTime = datetime!(2023-10-12 00:00:00 UTC);
+// how often soft deleted zones past their grace period are looked for
+const PURGE_INTERVAL: Duration = Duration::from_secs(600);
+const MAX_DESCRIPTION_LENGTH: usize = 255;
+// negative answers are cached for at most a day, see RFC 2308 5
+const MAX_SOA_MINIMUM: u32 = 86400;
+
+/// Everything of a zone that can be changed, settings left empty fall back to
+/// the defaults of the name server.
+#[derive(Deserialize)]
+pub(crate) struct ZoneSettings {
+  description: Option<String>,
+  default_ttl: Option<u32>,
+  /// Primary name server, absolute or relative to the zone
+  soa_mname: Option<String>,
+  /// Mailbox of the person responsible, e.g. `hostmaster.@`
+  soa_rname: Option<String>,
+  soa_refresh: Option<u32>,
+  soa_retry: Option<u32>,
+  soa_expire: Option<u32>,
+  soa_minimum: Option<u32>,
+}
+
+impl ZoneSettings {
+  fn normalize(&mut self, zone: &str) -> Result<(), ApiError> {
+    let mut errors = FieldErrors::default();
+
+    if let Some(description) = &self.description {
+      if description.len() > MAX_DESCRIPTION_LENGTH {
+        errors.add(
+          "description",
+          "too_long",
+          format!("must not exceed {MAX_DESCRIPTION_LENGTH} bytes"),
+        );
+      }
+    }
+    if self
+      .default_ttl
+      .is_some_and(|ttl| !(MIN_TTL..=MAX_TTL).contains(&ttl))
+    {
+      errors.add(
+        "default_ttl",
+        "out_of_range",
+        format!("must be between {MIN_TTL} and {MAX_TTL} seconds"),
+      );
+    }
+    if let Some(mname) = &mut self.soa_mname {
+      normalize_name(mname, "soa_mname", zone, target_name, &mut errors);
+    }
+    if let Some(rname) = &mut self.soa_rname {
+      normalize_name(rname, "soa_rname", zone, target_name, &mut errors);
+    }
+    for (field, value) in [
+      ("soa_refresh", self.soa_refresh),
+      ("soa_retry", self.soa_retry),
+      ("soa_expire", self.soa_expire),
+    ] {
+      if value.is_some_and(|value| value == 0 || value > i32::MAX as u32) {
+        errors.add(
+          field,
+          "out_of_range",
+          "must be a positive number of seconds",
+        );
+      }
+    }
+    if self
+      .soa_minimum
+      .is_some_and(|minimum| minimum > MAX_SOA_MINIMUM)
+    {
+      errors.add(
+        "soa_minimum",
+        "out_of_range",
+        format!("must not exceed {MAX_SOA_MINIMUM} seconds"),
+      );
+    }
+
+    errors.finish()
+  }
+}
+
+/// Removes the zone along with its records, views and transferred copy.
+async fn purge<C: ConnectionTrait>(db: &C, zone_id: Uuid) -> Result<(), DbErr> {
+  delete_zone_records(db, zone_id).await?;
+  View::delete_many()
+    .filter(view::Column::ZoneId.eq(zone_id))
+    .exec(db)
+    .await?;
+  ZoneTransfer::delete_by_id(zone_id).exec(db).await?;
+  Zone::delete_by_id(zone_id).exec(db).await?;
+
+  Ok(())
+}
+
+/// Values used for everything a zone does not set itself, should be the
+/// same as the ones of maid.
+#[derive(Args, Clone)]
+pub(crate) struct ZoneDefaults {
+  /// TTL of records without one
+  #[arg(long = "default-ttl", env = "CHEF_DEFAULT_TTL", default_value_t = 300)]
+  ttl: u32,
+  /// Primary name server announced in the SOA
+  #[arg(
+    long = "soa-mname",
+    env = "CHEF_SOA_MNAME",
+    default_value = "ns.dns.dresden.zone."
+  )]
+  mname: Name,
+  /// Mailbox of the person responsible, announced in the SOA
+  #[arg(
+    long = "soa-rname",
+    env = "CHEF_SOA_RNAME",
+    default_value = "dns.dresden.zone."
+  )]
+  rname: Name,
+  #[arg(long = "soa-refresh", env = "CHEF_SOA_REFRESH", default_value_t = 7200)]
+  refresh: i32,
+  #[arg(long = "soa-retry", env = "CHEF_SOA_RETRY", default_value_t = 3600)]
+  retry: i32,
+  #[arg(
+    long = "soa-expire",
+    env = "CHEF_SOA_EXPIRE",
+    default_value_t = 1209600
+  )]
+  expire: i32,
+  /// TTL of negative answers
+  #[arg(long = "soa-minimum", env = "CHEF_SOA_MINIMUM", default_value_t = 60)]
+  minimum: u32,
+}
 
 #[derive(Clone)]
 pub(crate) struct ZoneService {
   db: Arc<DatabaseConnection>,
+  // deleted zones are kept this long to be restored, if set
+  grace_period: Option<Duration>,
+  defaults: ZoneDefaults,
 }
 
 impl ZoneService {
-  pub(crate) fn new(db: Arc<DatabaseConnection>) -> Self {
-    Self { db }
+  pub(crate) fn new(
+    db: Arc<DatabaseConnection>,
+    grace_period: Option<Duration>,
+    defaults: ZoneDefaults,
+  ) -> Self {
+    Self {
+      db,
+      grace_period,
+      defaults,
+    }
   }
 
-  pub(crate) async fn list(&self, user_id: Uuid) -> anyhow::Result<Vec<zone::Model>> {
+  /// Zones of the user, either the active or the soft deleted ones.
+  pub(crate) async fn list(
+    &self,
+    user_id: Uuid,
+    deleted: bool,
+  ) -> anyhow::Result<Vec<zone::Model>> {
     let zones = Zone::find()
       .filter(zone::Column::Owner.eq(user_id))
+      .filter(if deleted {
+        zone::Column::Deleted.is_not_null()
+      } else {
+        zone::Column::Deleted.is_null()
+      })
       .all(self.db.as_ref())
       .await?;
 
     Ok(zones)
   }
 
-  pub(crate) async fn by_id(
-    &self,
-    user_id: Uuid,
-    zone_id: Uuid,
-  ) -> anyhow::Result<Option<zone::Model>> {
-    let user = Zone::find_by_id(zone_id)
-      .filter(zone::Column::Owner.eq(user_id))
-      .one(self.db.as_ref())
-      .await?;
+  pub(crate) async fn by_id(&self, user_id: Uuid, zone_id: Uuid) -> anyhow::Result<zone::Model> {
+    let zone = zone_access(self.db.as_ref(), user_id, zone_id).await?;
 
-    Ok(user)
+    Ok(zone)
   }
 
-  pub(crate) async fn create(&self, user_id: Uuid, name: String) -> anyhow::Result<zone::Model> {
+  pub(crate) async fn create(
+    &self,
+    user_id: Uuid,
+    name: String,
+    primary_server: Option<String>,
+  ) -> anyhow::Result<zone::Model> {
+    let name = zone_name(&name).map_err(|err| ApiError::field("name", err.code, err.message))?;
+
     let zone = zone::ActiveModel {
       id: ActiveValue::NotSet,
       created: ActiveValue::NotSet,
@@ -48,19 +218,250 @@ impl ZoneService {
       owner: ActiveValue::Set(user_id),
       verified: ActiveValue::NotSet,
       serial: ActiveValue::NotSet,
+      primary_server: ActiveValue::Set(primary_server),
+      description: ActiveValue::NotSet,
+      default_ttl: ActiveValue::NotSet,
+      soa_mname: ActiveValue::NotSet,
+      soa_rname: ActiveValue::NotSet,
+      soa_refresh: ActiveValue::NotSet,
+      soa_retry: ActiveValue::NotSet,
+      soa_expire: ActiveValue::NotSet,
+      soa_minimum: ActiveValue::NotSet,
+      deleted: ActiveValue::NotSet,
+      verification_token: ActiveValue::Set(Uuid::new_v4().simple().to_string()),
+      verification_method: ActiveValue::NotSet,
+      verified_at: ActiveValue::NotSet,
+      verification_checked: ActiveValue::NotSet,
     };
 
-    let zone = zone.insert(self.db.as_ref()).await?;
+    let zone = zone
+      .insert(self.db.as_ref())
+      .await
+      .map_err(|err| match err.sql_err() {
+        Some(SqlErr::UniqueConstraintViolation(_)) => ApiError::conflict(
+          "zone_exists",
+          "you already have a zone of that name, possibly deleted",
+        ),
+        _ => err.into(),
+      })?;
 
     Ok(zone)
   }
 
-  pub(crate) async fn delete(&self, user_id: Uuid, zone_id: Uuid) -> anyhow::Result<bool> {
-    let result = Zone::delete_by_id(zone_id)
+  /// Replaces the settings of the zone, which moves its serial on.
+  pub(crate) async fn modify(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    mut settings: ZoneSettings,
+  ) -> anyhow::Result<zone::Model> {
+    let zone = self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          let zone = zone_access(tx, user_id, zone_id).await?;
+          settings.normalize(&zone.name)?;
+
+          let zone = zone::ActiveModel {
+            id: ActiveValue::Unchanged(zone_id),
+            updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+            description: ActiveValue::Set(settings.description),
+            default_ttl: ActiveValue::Set(settings.default_ttl.map(|ttl| ttl as i32)),
+            soa_mname: ActiveValue::Set(settings.soa_mname),
+            soa_rname: ActiveValue::Set(settings.soa_rname),
+            soa_refresh: ActiveValue::Set(settings.soa_refresh.map(|value| value as i32)),
+            soa_retry: ActiveValue::Set(settings.soa_retry.map(|value| value as i32)),
+            soa_expire: ActiveValue::Set(settings.soa_expire.map(|value| value as i32)),
+            soa_minimum: ActiveValue::Set(settings.soa_minimum.map(|value| value as i32)),
+            ..Default::default()
+          }
+          .update(tx)
+          .await?;
+
+          Ok::<_, ApiError>(zone)
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(zone)
+  }
+
+  /// Removes the zone with everything in it, or only marks it as deleted if
+  /// there is a grace period.
+  pub(crate) async fn delete(&self, user_id: Uuid, zone_id: Uuid) -> anyhow::Result<()> {
+    let soft = self.grace_period.is_some();
+
+    self
+      .db
+      .transaction(|tx| {
+        Box::pin(async move {
+          zone_access(tx, user_id, zone_id).await?;
+
+          if soft {
+            zone::ActiveModel {
+              id: ActiveValue::Unchanged(zone_id),
+              deleted: ActiveValue::Set(Some(OffsetDateTime::now_utc())),
+              ..Default::default()
+            }
+            .update(tx)
+            .await?;
+          } else {
+            purge(tx, zone_id).await?;
+          }
+
+          Ok::<_, ApiError>(())
+        })
+      })
+      .await
+      .map_err(ApiError::from)?;
+
+    Ok(())
+  }
+
+  /// Brings back a soft deleted zone before it is purged.
+  pub(crate) async fn restore(&self, user_id: Uuid, zone_id: Uuid) -> anyhow::Result<zone::Model> {
+    let zone = Zone::find_by_id(zone_id)
       .filter(zone::Column::Owner.eq(user_id))
-      .exec(self.db.as_ref())
+      .one(self.db.as_ref())
+      .await?
+      .ok_or_else(|| ApiError::not_found("zone_not_found", "no such zone"))?;
+
+    if zone.deleted.is_none() {
+      return Err(ApiError::conflict("zone_not_deleted", "the zone has not been deleted").into());
+    }
+
+    let mut restored = zone::ActiveModel {
+      id: ActiveValue::Unchanged(zone_id),
+      updated: ActiveValue::Set(OffsetDateTime::now_utc()),
+      deleted: ActiveValue::Set(None),
+      ..Default::default()
+    };
+
+    // someone else verified the name meanwhile, the zone has to be verified
+    // again to take it back
+    if zone.verified && verified_namesake(self.db.as_ref(), &zone).await?.is_some() {
+      restored.verified = ActiveValue::Set(false);
+      restored.verification_method = ActiveValue::Set(None);
+      restored.verified_at = ActiveValue::Set(None);
+    }
+
+    Ok(restored.update(self.db.as_ref()).await?)
+  }
+
+  /// Renders the records maid answers clients of the view with, or clients
+  /// outside of any view, as master file. Records bound to a location are
+  /// left out.
+  pub(crate) async fn export(
+    &self,
+    user_id: Uuid,
+    zone_id: Uuid,
+    view_id: Option<Uuid>,
+  ) -> anyhow::Result<(String, String)> {
+    let db = self.db.as_ref();
+    let zone = zone_access(db, user_id, zone_id).await?;
+    if let Some(view_id) = view_id {
+      let view = view_access(db, user_id, view_id).await?;
+      if view.zone_id != zone_id {
+        return Err(ApiError::not_found("view_not_found", "no such view").into());
+      }
+    }
+
+    let records = zone_records(db, zone_id).await?;
+    let total = records.len();
+
+    // serials are derived like maid does
+    let updated = records
+      .iter()
+      .map(|(record, _)| record.updated)
+      .fold(zone.updated, OffsetDateTime::max);
+    let serial = (updated - SERIAL_EPOCH).whole_seconds() as u32;
+
+    // records of the view replace the ones of the same name and type
+    let mut records: Vec<_> = records
+      .into_iter()
+      .filter(|(record, _)| record.view_id.is_none() || record.view_id == view_id)
+      .filter(|(record, _)| record.country.is_none() && record.continent.is_none())
+      .collect();
+    let replaced: HashSet<(String, RecordType)> = records
+      .iter()
+      .filter(|(record, _)| record.view_id.is_some())
+      .map(|(record, specific)| (record.name.clone(), specific.record_type()))
+      .collect();
+    records.retain(|(record, specific)| {
+      record.view_id.is_some() || !replaced.contains(&(record.name.clone(), specific.record_type()))
+    });
+
+    let mut origin = Name::from_ascii(&zone.name)?;
+    origin.set_fqdn(true);
+    let default_ttl = zone.default_ttl.map_or(self.defaults.ttl, |ttl| ttl as u32);
+
+    let soa = RData::SOA(rdata::SOA::new(
+      match &zone.soa_mname {
+        Some(mname) => parse_name(mname, &origin)?,
+        None => self.defaults.mname.clone(),
+      },
+      match &zone.soa_rname {
+        Some(rname) => parse_name(rname, &origin)?,
+        None => self.defaults.rname.clone(),
+      },
+      serial,
+      zone.soa_refresh.unwrap_or(self.defaults.refresh),
+      zone.soa_retry.unwrap_or(self.defaults.retry),
+      zone.soa_expire.unwrap_or(self.defaults.expire),
+      zone
+        .soa_minimum
+        .map_or(self.defaults.minimum, |minimum| minimum as u32),
+    ));
+
+    let left_out = total - records.len();
+    let mut entries = vec![(origin.clone(), default_ttl, soa)];
+    for (record, specific) in records {
+      let name = if record.name == "@" {
+        origin.clone()
+      } else {
+        Name::from_ascii(&record.name)?.append_domain(&origin)?
+      };
+      let ttl = record.ttl.map_or(default_ttl, |ttl| ttl as u32);
+      entries.push((name, ttl, specific.into_rdata(&origin)?));
+    }
+
+    Ok((zone.name, render(&origin, entries, left_out)))
+  }
+
+  /// Purges soft deleted zones once their grace period is over.
+  pub(crate) async fn run_purge(self: Arc<Self>) {
+    let Some(grace_period) = self.grace_period else {
+      return;
+    };
+    let mut interval = interval(PURGE_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      if let Err(err) = self.purge_expired(grace_period).await {
+        error!("Unable to purge deleted zones: {}", err);
+      }
+    }
+  }
+
+  async fn purge_expired(&self, grace_period: Duration) -> anyhow::Result<()> {
+    let expired: Vec<Uuid> = Zone::find()
+      .filter(zone::Column::Deleted.lt(OffsetDateTime::now_utc() - grace_period))
+      .select_only()
+      .column(zone::Column::Id)
+      .into_tuple()
+      .all(self.db.as_ref())
       .await?;
 
-    Ok(result.rows_affected == 1)
+    for zone_id in expired {
+      self
+        .db
+        .transaction(|tx| Box::pin(async move { purge(tx, zone_id).await }))
+        .await?;
+      info!("Purged deleted zone {}", zone_id);
+    }
+
+    Ok(())
   }
 }
@@ -0,0 +1,587 @@
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::CharIndices;
+
+use hickory_proto::rr::{Name, RData};
+use serde::Serialize;
+
+use crate::error::{ApiError, FieldErrors};
+
+/// Resource record of a master file, names are absolute.
+pub(crate) struct Entry {
+  pub(crate) line: usize,
+  pub(crate) name: String,
+  pub(crate) ttl: Option<u32>,
+  pub(crate) data: EntryData,
+}
+
+pub(crate) enum EntryData {
+  A(Ipv4Addr),
+  Aaaa(Ipv6Addr),
+  Cname(String),
+  Mx(u16, String),
+  Ns(String),
+  Txt(String),
+}
+
+/// Line left out of an import, along with the reason.
+#[derive(Serialize)]
+pub(crate) struct SkippedLine {
+  pub(crate) line: usize,
+  pub(crate) content: String,
+  pub(crate) reason: String,
+}
+
+/// Entries span several lines if they contain parentheses, see RFC 1035 5.1.
+struct RawEntry<'a> {
+  line: usize,
+  source: &'a str,
+  // starts with whitespace, so the owner of the previous entry is reused
+  blank_owner: bool,
+  tokens: Vec<String>,
+}
+
+fn tokenize(input: &str) -> Result<Vec<RawEntry<'_>>, (usize, &'static str)> {
+  let mut entries = Vec::new();
+  let mut chars = input.char_indices().peekable();
+  let mut line = 1;
+
+  while chars.peek().is_some() {
+    let (start, _) = *chars.peek().unwrap();
+    let first_line = line;
+    let mut entry = RawEntry {
+      line: first_line,
+      source: "",
+      blank_owner: matches!(chars.peek(), Some((_, ' ' | '\t'))),
+      tokens: Vec::new(),
+    };
+    let mut depth = 0;
+    let mut end = input.len();
+
+    while let Some((i, c)) = chars.next() {
+      match c {
+        '\n' => {
+          line += 1;
+          if depth == 0 {
+            end = i;
+            break;
+          }
+        }
+        ' ' | '\t' | '\r' => {}
+        ';' => while chars.next_if(|(_, c)| *c != '\n').is_some() {},
+        '(' => depth += 1,
+        ')' => {
+          if depth == 0 {
+            return Err((line, "unbalanced parenthesis"));
+          }
+          depth -= 1;
+        }
+        '"' => {
+          let quote_line = line;
+          let mut text = Vec::new();
+          loop {
+            match chars.next() {
+              Some((_, '"')) => break,
+              Some((_, '\\')) => unescape(&mut chars, &mut text).ok_or((line, "invalid escape"))?,
+              Some((_, c)) => {
+                if c == '\n' {
+                  line += 1;
+                }
+                text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+              }
+              None => return Err((quote_line, "unterminated quoted string")),
+            }
+          }
+          let text =
+            String::from_utf8(text).map_err(|_| (line, "invalid utf-8 in quoted string"))?;
+          entry.tokens.push(text);
+        }
+        c => {
+          let mut text = c.to_string();
+          while let Some((_, c)) =
+            chars.next_if(|(_, c)| !matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"'))
+          {
+            text.push(c);
+          }
+          entry.tokens.push(text);
+        }
+      }
+    }
+
+    if depth != 0 {
+      return Err((first_line, "unbalanced parenthesis"));
+    }
+    if !entry.tokens.is_empty() {
+      entry.source = input[start..end].trim();
+      entries.push(entry);
+    }
+  }
+
+  Ok(entries)
+}
+
+/// Either `\DDD` with a decimal byte or an escaped character.
+fn unescape(chars: &mut Peekable<CharIndices<'_>>, text: &mut Vec<u8>) -> Option<()> {
+  let (_, c) = chars.next()?;
+  if !c.is_ascii_digit() {
+    text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    return Some(());
+  }
+
+  let mut value = c.to_digit(10)?;
+  for _ in 0..2 {
+    value = value * 10 + chars.next()?.1.to_digit(10)?;
+  }
+  text.push(u8::try_from(value).ok()?);
+  Some(())
+}
+
+/// Seconds, optionally with BIND style units, e.g. `1h30m`.
+fn parse_ttl(text: &str) -> Option<u32> {
+  if let Ok(ttl) = text.parse() {
+    return Some(ttl);
+  }
+
+  let mut total: u32 = 0;
+  let mut value: Option<u32> = None;
+  for c in text.chars() {
+    if let Some(digit) = c.to_digit(10) {
+      value = Some(value.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+      continue;
+    }
+    let unit = match c.to_ascii_lowercase() {
+      's' => 1,
+      'm' => 60,
+      'h' => 3600,
+      'd' => 86400,
+      'w' => 604800,
+      _ => return None,
+    };
+    total = total.checked_add(value.take()?.checked_mul(unit)?)?;
+  }
+
+  match value {
+    Some(_) => None,
+    None => Some(total),
+  }
+}
+
+/// Appends the origin to relative names, `@` is the origin itself.
+fn absolute(name: &str, origin: &str) -> String {
+  if name == "@" {
+    origin.to_string()
+  } else if name.ends_with('.') {
+    name.to_string()
+  } else {
+    format!("{name}.{origin}")
+  }
+}
+
+fn is_class(text: &str) -> bool {
+  ["IN", "CH", "CS", "HS", "ANY"]
+    .iter()
+    .any(|class| text.eq_ignore_ascii_case(class))
+}
+
+/// Parses a master file as per RFC 1035 5, with `$TTL` from RFC 2308.
+/// Directives, classes and types other than the supported ones are skipped
+/// and reported, malformed lines fail the whole file.
+pub(crate) fn parse(input: &str, zone: &str) -> Result<(Vec<Entry>, Vec<SkippedLine>), ApiError> {
+  let mut errors = FieldErrors::default();
+  let mut entries = Vec::new();
+  let mut skipped = Vec::new();
+
+  let raw = tokenize(input)
+    .map_err(|(line, message)| ApiError::field(format!("line {line}"), "syntax_error", message))?;
+
+  let mut origin = format!("{zone}.");
+  let mut default_ttl: Option<u32> = None;
+  let mut last_ttl: Option<u32> = None;
+  let mut last_owner: Option<String> = None;
+
+  for entry in raw {
+    let field = format!("line {}", entry.line);
+    let skip = |skipped: &mut Vec<SkippedLine>, reason: String| {
+      skipped.push(SkippedLine {
+        line: entry.line,
+        content: entry.source.to_string(),
+        reason,
+      })
+    };
+    let mut tokens = entry.tokens.iter().map(String::as_str);
+
+    if !entry.blank_owner && entry.tokens[0].starts_with('$') {
+      let directive = tokens.next().unwrap();
+      let argument = tokens.next();
+      if directive.eq_ignore_ascii_case("$ORIGIN") {
+        match argument {
+          Some(name) => origin = absolute(name, &origin),
+          None => errors.add(field, "syntax_error", "$ORIGIN needs a name"),
+        }
+      } else if directive.eq_ignore_ascii_case("$TTL") {
+        match argument.and_then(parse_ttl) {
+          Some(ttl) => default_ttl = Some(ttl),
+          None => errors.add(field, "invalid_ttl", "$TTL needs a number of seconds"),
+        }
+      } else {
+        skip(
+          &mut skipped,
+          format!("the directive {directive} is not supported"),
+        );
+      }
+      continue;
+    }
+
+    let owner = if entry.blank_owner {
+      last_owner.clone()
+    } else {
+      tokens.next().map(|name| absolute(name, &origin))
+    };
+    let Some(owner) = owner else {
+      errors.add(field, "syntax_error", "the first record needs a name");
+      continue;
+    };
+    last_owner = Some(owner.clone());
+
+    // ttl and class may come in either order
+    let mut ttl = None;
+    let mut class = None;
+    let mut record_type = None;
+    for token in tokens.by_ref() {
+      if ttl.is_none() && token.starts_with(|c: char| c.is_ascii_digit()) {
+        ttl = parse_ttl(token);
+        if ttl.is_none() {
+          break;
+        }
+      } else if class.is_none() && is_class(token) {
+        class = Some(token);
+      } else {
+        record_type = Some(token.to_ascii_uppercase());
+        break;
+      }
+    }
+    let Some(record_type) = record_type else {
+      errors.add(field, "syntax_error", "expected a ttl, class or type");
+      continue;
+    };
+    if let Some(class) = class.filter(|class| !class.eq_ignore_ascii_case("IN")) {
+      skip(&mut skipped, format!("the class {class} is not supported"));
+      continue;
+    }
+
+    if ttl.is_some() {
+      last_ttl = ttl;
+    }
+    let ttl = ttl.or(default_ttl).or(last_ttl);
+
+    let rdata: Vec<&str> = tokens.collect();
+    let data = match (record_type.as_str(), rdata.as_slice()) {
+      ("A", [addr]) => match addr.parse() {
+        Ok(addr) => EntryData::A(addr),
+        Err(_) => {
+          errors.add(field, "invalid_address", "expected an ipv4 address");
+          continue;
+        }
+      },
+      ("AAAA", [addr]) => match addr.parse() {
+        Ok(addr) => EntryData::Aaaa(addr),
+        Err(_) => {
+          errors.add(field, "invalid_address", "expected an ipv6 address");
+          continue;
+        }
+      },
+      ("CNAME", [target]) => EntryData::Cname(absolute(target, &origin)),
+      ("NS", [target]) => EntryData::Ns(absolute(target, &origin)),
+      ("MX", [preference, exchange]) => match preference.parse() {
+        Ok(preference) => EntryData::Mx(preference, absolute(exchange, &origin)),
+        Err(_) => {
+          errors.add(
+            field,
+            "syntax_error",
+            "expected a preference between 0 and 65535",
+          );
+          continue;
+        }
+      },
+      // character strings are joined, maid splits them again when answering
+      ("TXT", [_, ..]) => EntryData::Txt(rdata.concat()),
+      ("SOA", [_, _, _, _, _, _, _]) => {
+        skip(
+          &mut skipped,
+          "the SOA is built from the zone settings".to_string(),
+        );
+        continue;
+      }
+      ("A" | "AAAA" | "CNAME" | "NS" | "MX" | "TXT" | "SOA", _) => {
+        errors.add(
+          field,
+          "syntax_error",
+          format!("wrong number of fields for {record_type}"),
+        );
+        continue;
+      }
+      _ => {
+        skip(
+          &mut skipped,
+          format!("the record type {record_type} is not supported"),
+        );
+        continue;
+      }
+    };
+
+    entries.push(Entry {
+      line: entry.line,
+      name: owner,
+      ttl,
+      data,
+    });
+  }
+
+  errors.finish()?;
+
+  Ok((entries, skipped))
+}
+
+/// Owners inside the zone are written relative to the origin.
+fn relative_owner(name: &Name, origin: &Name) -> String {
+  let name = name.to_lowercase().to_ascii();
+  let origin = origin.to_lowercase().to_ascii();
+  if name == origin {
+    return "@".to_string();
+  }
+  match name.strip_suffix(&origin) {
+    Some(prefix) if prefix.ends_with('.') => prefix.trim_end_matches('.').to_string(),
+    _ => name,
+  }
+}
+
+/// Character strings are quoted, with quotes, backslashes and anything not
+/// printable escaped.
+fn quote(text: &[u8], output: &mut String) {
+  output.push('"');
+  for chunk in text.utf8_chunks() {
+    for c in chunk.valid().chars() {
+      match c {
+        '"' | '\\' => {
+          output.push('\\');
+          output.push(c);
+        }
+        c if c.is_control() => {
+          let mut buf = [0; 4];
+          for byte in c.encode_utf8(&mut buf).bytes() {
+            let _ = write!(output, "\\{byte:03}");
+          }
+        }
+        c => output.push(c),
+      }
+    }
+    for byte in chunk.invalid() {
+      let _ = write!(output, "\\{byte:03}");
+    }
+  }
+  output.push('"');
+}
+
+/// Names are written as punycode, hickory would display them in unicode.
+fn rdata_text(rdata: &RData) -> String {
+  match rdata {
+    RData::CNAME(name) => name.0.to_ascii(),
+    RData::NS(name) => name.0.to_ascii(),
+    RData::MX(mx) => format!("{} {}", mx.preference(), mx.exchange().to_ascii()),
+    RData::SOA(soa) => format!(
+      "{} {} {} {} {} {} {}",
+      soa.mname().to_ascii(),
+      soa.rname().to_ascii(),
+      soa.serial(),
+      soa.refresh(),
+      soa.retry(),
+      soa.expire(),
+      soa.minimum()
+    ),
+    RData::TXT(txt) => {
+      let mut output = String::new();
+      for (i, text) in txt.txt_data().iter().enumerate() {
+        if i > 0 {
+          output.push(' ');
+        }
+        quote(text, &mut output);
+      }
+      output
+    }
+    rdata => rdata.to_string(),
+  }
+}
+
+/// Renders a master file, the first entry is expected to be the SOA. The
+/// others are sorted by name in canonical order, type and data so exports of
+/// the same records are identical.
+pub(crate) fn render(origin: &Name, entries: Vec<(Name, u32, RData)>, left_out: usize) -> String {
+  let mut entries: Vec<_> = entries
+    .into_iter()
+    .map(|(name, ttl, rdata)| {
+      let text = rdata_text(&rdata);
+      (name, rdata.record_type(), ttl, text)
+    })
+    .collect();
+  if entries.len() > 1 {
+    entries[1..].sort_by(|a, b| (&a.0, a.1, &a.3).cmp(&(&b.0, b.1, &b.3)));
+  }
+
+  let owners: Vec<_> = entries
+    .iter()
+    .map(|(name, ..)| relative_owner(name, origin))
+    .collect();
+  let width = owners.iter().map(String::len).max().unwrap_or(0);
+
+  let mut output = String::new();
+  let _ = writeln!(output, "; {}", origin.to_lowercase().to_ascii());
+  if left_out > 0 {
+    let _ = writeln!(
+      output,
+      "; {left_out} records answered only to some clients are left out"
+    );
+  }
+  let _ = writeln!(output, "$ORIGIN {}", origin.to_lowercase().to_ascii());
+  for ((_, record_type, ttl, text), owner) in entries.iter().zip(owners) {
+    let record_type = record_type.to_string();
+    let _ = writeln!(output, "{owner:width$} {ttl:>7} IN {record_type:<5} {text}");
+  }
+
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.name.as_str()).collect()
+  }
+
+  #[test]
+  fn multi_line_soa() {
+    let input = "@ IN SOA ns.example.org. admin.example.org. (\n  1 ; serial\n  7200\n  3600\n  1209600\n  300 )\nwww A 192.0.2.1\n";
+    let raw = tokenize(input).unwrap();
+
+    assert_eq!(raw.len(), 2);
+    assert_eq!(raw[0].line, 1);
+    assert_eq!(raw[0].tokens.len(), 10);
+    assert_eq!(raw[1].line, 7);
+
+    let (entries, skipped) = parse(input, "example.org").unwrap();
+    assert_eq!(names(&entries), ["www.example.org."]);
+    assert_eq!(entries[0].line, 7);
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].line, 1);
+  }
+
+  #[test]
+  fn origin_and_ttl_precedence() {
+    let input = "a A 192.0.2.1\n$TTL 1h\nb A 192.0.2.2\nc 60 A 192.0.2.3\nd A 192.0.2.4\n$ORIGIN sub\ne A 192.0.2.5\n$ORIGIN other.test.\nf A 192.0.2.6\ng.example.org. A 192.0.2.7\n";
+    let (entries, _) = parse(input, "example.org").unwrap();
+
+    assert_eq!(
+      names(&entries),
+      [
+        "a.example.org.",
+        "b.example.org.",
+        "c.example.org.",
+        "d.example.org.",
+        "e.sub.example.org.",
+        "f.other.test.",
+        "g.example.org.",
+      ]
+    );
+    let ttls: Vec<_> = entries.iter().map(|entry| entry.ttl).collect();
+    // an explicit ttl wins, then $TTL, then the one of the previous entry
+    assert_eq!(
+      ttls,
+      [
+        None,
+        Some(3600),
+        Some(60),
+        Some(3600),
+        Some(3600),
+        Some(3600),
+        Some(3600)
+      ]
+    );
+  }
+
+  #[test]
+  fn blank_owner_repeats_previous() {
+    let input = "www 300 IN A 192.0.2.1\n      IN AAAA 2001:db8::1\n\t MX 10 mail\n";
+    let (entries, _) = parse(input, "example.org").unwrap();
+
+    assert_eq!(names(&entries), ["www.example.org."; 3]);
+    assert_eq!(entries[1].ttl, Some(300));
+    assert!(matches!(
+      &entries[2].data,
+      EntryData::Mx(10, exchange) if exchange == "mail.example.org."
+    ));
+  }
+
+  #[test]
+  fn blank_owner_without_previous() {
+    assert!(parse("  A 192.0.2.1\n", "example.org").is_err());
+  }
+
+  #[test]
+  fn escapes_in_quoted_strings() {
+    let input = "@ TXT \"a\\\"b\" \"c\\\\d\" \"\\065\\066;\"\n";
+    let (entries, _) = parse(input, "example.org").unwrap();
+
+    assert!(matches!(&entries[0].data, EntryData::Txt(text) if text == "a\"bc\\dAB;"));
+  }
+
+  #[test]
+  fn invalid_escapes() {
+    assert_eq!(
+      tokenize("@ TXT \"\\256\"\n").err(),
+      Some((1, "invalid escape"))
+    );
+    assert_eq!(
+      tokenize("@ TXT \"\\06\"\n").err(),
+      Some((1, "invalid escape"))
+    );
+  }
+
+  #[test]
+  fn unbalanced_input() {
+    assert_eq!(
+      tokenize("@ A ( 192.0.2.1\n\nwww A 192.0.2.2\n").err(),
+      Some((1, "unbalanced parenthesis"))
+    );
+    assert_eq!(
+      tokenize("@ A 192.0.2.1 )\n").err(),
+      Some((1, "unbalanced parenthesis"))
+    );
+    assert_eq!(
+      tokenize("@ TXT \"abc\nwww A 192.0.2.1\n").err(),
+      Some((1, "unterminated quoted string"))
+    );
+  }
+
+  #[test]
+  fn comments_and_empty_lines() {
+    let raw = tokenize("; zone\n\n  ; indented\nwww A 192.0.2.1 ; trailing\n").unwrap();
+
+    assert_eq!(raw.len(), 1);
+    assert_eq!(raw[0].line, 4);
+    assert_eq!(raw[0].source, "www A 192.0.2.1 ; trailing");
+    assert_eq!(raw[0].tokens, ["www", "A", "192.0.2.1"]);
+  }
+
+  #[test]
+  fn ttl_units() {
+    assert_eq!(parse_ttl("300"), Some(300));
+    assert_eq!(parse_ttl("1h30m"), Some(5400));
+    assert_eq!(parse_ttl("1W2D"), Some(777600));
+    assert_eq!(parse_ttl("1h30"), None);
+    assert_eq!(parse_ttl("h"), None);
+    assert_eq!(parse_ttl("1y"), None);
+    assert_eq!(parse_ttl("4294967296"), None);
+    assert_eq!(parse_ttl("100000w"), None);
+  }
+}
@@ -0,0 +1,51 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use clap::Args;
+use ipnet::IpNet;
+
+#[derive(Args, Clone, PartialEq)]
+pub(crate) struct AclConfig {
+  /// Networks allowed to query, defaults to everyone
+  #[arg(long = "allow-query", env = "MAID_ALLOW_QUERY", value_delimiter = ',')]
+  pub(crate) query: Vec<IpNet>,
+  /// Networks allowed to transfer zones via AXFR or IXFR, defaults to
+  /// everyone
+  #[arg(
+    long = "allow-transfer",
+    env = "MAID_ALLOW_TRANSFER",
+    value_delimiter = ','
+  )]
+  pub(crate) transfer: Vec<IpNet>,
+}
+
+/// Decides which clients get an answer at all.
+pub(crate) struct AccessControl {
+  config: RwLock<AclConfig>,
+}
+
+impl AccessControl {
+  pub(crate) fn new(config: AclConfig) -> Arc<Self> {
+    Arc::new(Self {
+      config: RwLock::new(config),
+    })
+  }
+
+  pub(crate) fn set_config(&self, config: AclConfig) {
+    *self.config.write().unwrap() = config;
+  }
+
+  pub(crate) fn allows_query(&self, addr: IpAddr) -> bool {
+    contains(&self.config.read().unwrap().query, addr)
+  }
+
+  pub(crate) fn allows_transfer(&self, addr: IpAddr) -> bool {
+    contains(&self.config.read().unwrap().transfer, addr)
+  }
+}
+
+/// An empty list allows everyone.
+fn contains(networks: &[IpNet], addr: IpAddr) -> bool {
+  let addr = addr.to_canonical();
+  networks.is_empty() || networks.iter().any(|network| network.contains(&addr))
+}
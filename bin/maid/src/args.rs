@@ -1,13 +1,109 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use clap::Parser;
+use hickory_server::proto::rr::LowerName;
+use tracing::level_filters::LevelFilter;
 use url::Url;
 
-#[derive(Parser)]
+use crate::acl::AclConfig;
+use crate::authority::AnyResponse;
+use crate::dnstap::DnstapFormat;
+use crate::edns::EdnsConfig;
+use crate::rrl::RateLimitConfig;
+use crate::service::ZoneDefaults;
+
+#[derive(Parser, Clone)]
 #[command(author, version, about, long_about)]
 pub(super) struct MaidArgs {
-  #[arg(long, short, env = "MAID_LISTEN_ADDR", default_value = "127.0.0.1:53")]
-  pub(super) listen_addr: SocketAddr,
-  #[arg(long, short, env = "MAID_DATABASE_URL")]
-  pub(super) database_url: Url,
+  /// TOML file with settings overriding the flags, reloaded on SIGHUP
+  #[arg(long, short, env = "MAID_CONFIG")]
+  pub(super) config: Option<PathBuf>,
+  /// Address to serve dns on via udp and tcp, defaults to 127.0.0.1:53 if
+  /// no other dns listener is configured or passed by systemd
+  #[arg(long, short, env = "MAID_LISTEN_ADDR", value_delimiter = ',')]
+  pub(super) listen_addr: Vec<SocketAddr>,
+  #[arg(long, env = "MAID_UDP_LISTEN_ADDR", value_delimiter = ',')]
+  pub(super) udp_listen_addr: Vec<SocketAddr>,
+  #[arg(long, env = "MAID_TCP_LISTEN_ADDR", value_delimiter = ',')]
+  pub(super) tcp_listen_addr: Vec<SocketAddr>,
+  #[arg(
+    long,
+    short,
+    env = "MAID_DATABASE_URL",
+    required_unless_present = "config"
+  )]
+  pub(super) database_url: Option<Url>,
+  #[arg(
+    long,
+    env = "MAID_TLS_LISTEN_ADDR",
+    value_delimiter = ',',
+    requires_all = ["tls_cert_path", "tls_key_path"]
+  )]
+  pub(super) tls_listen_addr: Vec<SocketAddr>,
+  #[arg(long, env = "MAID_DOH_LISTEN_ADDR", value_delimiter = ',')]
+  pub(super) doh_listen_addr: Vec<SocketAddr>,
+  #[arg(long, env = "MAID_TLS_CERT_PATH", requires = "tls_key_path")]
+  pub(super) tls_cert_path: Option<PathBuf>,
+  #[arg(long, env = "MAID_TLS_KEY_PATH", requires = "tls_cert_path")]
+  pub(super) tls_key_path: Option<PathBuf>,
+  #[arg(long, env = "MAID_METRICS_LISTEN_ADDR")]
+  pub(super) metrics_listen_addr: Option<SocketAddr>,
+  #[arg(long, env = "MAID_LOG_LEVEL", default_value_t = LevelFilter::INFO)]
+  pub(super) log_level: LevelFilter,
+  #[command(flatten)]
+  pub(super) acl: AclConfig,
+  /// Unix socket of a dnstap receiver to log queries to
+  #[arg(long, env = "MAID_DNSTAP_SOCKET", conflicts_with = "dnstap_file")]
+  pub(super) dnstap_socket: Option<PathBuf>,
+  /// File to log queries to, json lines are appended to it. An existing
+  /// dnstap file is kept, queries are logged to a new one named after the
+  /// time instead
+  #[arg(long, env = "MAID_DNSTAP_FILE")]
+  pub(super) dnstap_file: Option<PathBuf>,
+  #[arg(long, env = "MAID_DNSTAP_FORMAT", value_enum, default_value_t = DnstapFormat::Dnstap)]
+  pub(super) dnstap_format: DnstapFormat,
+  /// Only log every n-th query
+  #[arg(
+    long,
+    env = "MAID_DNSTAP_SAMPLE_RATE",
+    default_value_t = 1,
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  pub(super) dnstap_sample_rate: u64,
+  /// Zones to log queries for, defaults to every zone
+  #[arg(long, env = "MAID_DNSTAP_ZONE", value_delimiter = ',')]
+  pub(super) dnstap_zone: Vec<LowerName>,
+  #[command(flatten)]
+  pub(super) rate_limit: RateLimitConfig,
+  #[command(flatten)]
+  pub(super) edns: EdnsConfig,
+  #[command(flatten)]
+  pub(super) zone_defaults: ZoneDefaults,
+  /// MaxMind-format database to locate clients with, for records tagged
+  /// with a country or continent
+  #[arg(long, env = "MAID_GEOIP_DATABASE")]
+  pub(super) geoip_database: Option<PathBuf>,
+  /// Probe the targets of health-checked records from this instance
+  #[arg(long, env = "MAID_HEALTH_CHECKS")]
+  pub(super) health_checks: bool,
+  /// How to answer ANY queries over udp, other transports always get every
+  /// record of the name
+  #[arg(long, env = "MAID_ANY_RESPONSE", value_enum, default_value_t = AnyResponse::Rrset)]
+  pub(super) any_response: AnyResponse,
+  /// Follow CNAME chains into the other zones served here, not just within
+  /// the zone queried
+  #[arg(long, env = "MAID_CROSS_ZONE_CNAMES")]
+  pub(super) cross_zone_cnames: bool,
+  /// Name of an RFC 9432 catalog zone listing every zone we are the primary of
+  #[arg(long, env = "MAID_CATALOG_ZONE")]
+  pub(super) catalog_zone: Option<LowerName>,
+  /// Secondaries to notify when the catalog zone changes
+  #[arg(
+    long,
+    env = "MAID_CATALOG_ZONE_NOTIFY",
+    value_delimiter = ',',
+    requires = "catalog_zone"
+  )]
+  pub(super) catalog_zone_notify: Vec<SocketAddr>,
 }
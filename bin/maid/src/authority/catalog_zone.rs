@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use hickory_client::client::{AsyncClient, ClientHandle};
+use hickory_client::udp::UdpClientStream;
+use hickory_server::authority::{
+  AuthLookup, Authority, LookupError, LookupOptions, MessageRequest, UpdateResult, ZoneType,
+};
+use hickory_server::proto::op::ResponseCode;
+use hickory_server::proto::rr::rdata::{NS, PTR, SOA, TXT};
+use hickory_server::proto::rr::{
+  DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey,
+};
+use hickory_server::server::RequestInfo;
+use hickory_server::store::in_memory::InMemoryAuthority;
+use sea_orm::prelude::Uuid;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::interval;
+use tokio::try_join;
+use tracing::{error, info};
+
+use crate::metrics::Metrics;
+use crate::service::CatalogZoneService;
+
+// how often the serial is checked to notify secondaries
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// RFC 9432 catalog zone listing every zone we are the primary of, so
+/// secondaries can provision them on their own.
+pub(crate) struct CatalogZoneAuthority {
+  catalog_zone_service: Arc<CatalogZoneService>,
+  metrics: Arc<Metrics>,
+  origin: LowerName,
+  current: RwLock<Option<(u32, Arc<InMemoryAuthority>)>>,
+}
+
+impl CatalogZoneAuthority {
+  pub(crate) fn new(
+    catalog_zone_service: Arc<CatalogZoneService>,
+    metrics: Arc<Metrics>,
+    origin: LowerName,
+  ) -> Arc<Self> {
+    Arc::new(Self {
+      catalog_zone_service,
+      metrics,
+      origin,
+      current: RwLock::new(None),
+    })
+  }
+
+  /// Rebuilds the zone whenever the set of zones changed.
+  async fn authority(&self) -> anyhow::Result<Arc<InMemoryAuthority>> {
+    let serial = self.catalog_zone_service.serial().await?;
+
+    if let Some((current, authority)) = self.current.read().await.as_ref() {
+      if *current == serial {
+        return Ok(authority.clone());
+      }
+    }
+
+    let members = self.catalog_zone_service.members().await?;
+    let authority = Arc::new(build(Name::from(&self.origin), serial, members)?);
+    *self.current.write().await = Some((serial, authority.clone()));
+
+    Ok(authority)
+  }
+
+  /// Sends a NOTIFY to the given secondaries whenever the serial changes.
+  pub(crate) async fn notify(self: Arc<Self>, secondaries: Vec<SocketAddr>) {
+    let mut interval = interval(NOTIFY_INTERVAL);
+    let mut notified = None;
+
+    loop {
+      interval.tick().await;
+
+      let serial = match self.catalog_zone_service.serial().await {
+        Ok(serial) => serial,
+        Err(err) => {
+          error!("Unable to get catalog zone serial: {}", err);
+          continue;
+        }
+      };
+
+      // no need to tell anyone about the serial we started with
+      if notified
+        .replace(serial)
+        .is_none_or(|notified| notified == serial)
+      {
+        continue;
+      }
+
+      info!(
+        "Catalog zone changed to serial {}, notifying secondaries",
+        serial
+      );
+      for secondary in &secondaries {
+        if let Err(err) = self.notify_secondary(*secondary).await {
+          error!("Unable to notify {} of catalog zone: {}", secondary, err);
+        }
+      }
+    }
+  }
+
+  async fn notify_secondary(&self, secondary: SocketAddr) -> anyhow::Result<()> {
+    let stream = UdpClientStream::<UdpSocket>::new(secondary);
+    let (mut client, background) = AsyncClient::connect(stream).await?;
+    let background = tokio::spawn(background);
+
+    let result = client
+      .notify(
+        Name::from(&self.origin),
+        DNSClass::IN,
+        RecordType::SOA,
+        None::<RecordSet>,
+      )
+      .await;
+
+    background.abort();
+    result?;
+
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl Authority for CatalogZoneAuthority {
+  type Lookup = AuthLookup;
+
+  fn zone_type(&self) -> ZoneType {
+    ZoneType::Primary
+  }
+
+  fn is_axfr_allowed(&self) -> bool {
+    true
+  }
+
+  async fn update(&self, _update: &MessageRequest) -> UpdateResult<bool> {
+    Err(ResponseCode::NotImp)
+  }
+
+  fn origin(&self) -> &LowerName {
+    &self.origin
+  }
+
+  async fn lookup(
+    &self,
+    name: &LowerName,
+    query_type: RecordType,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    let authority = self.authority().await.map_err(|err| {
+      error!("Unable to build catalog zone: {}", err);
+      LookupError::from(ResponseCode::ServFail)
+    })?;
+
+    authority.lookup(name, query_type, lookup_options).await
+  }
+
+  async fn search(
+    &self,
+    request_info: RequestInfo<'_>,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    let zone = self.origin.to_string();
+    let record_type = request_info.query.query_type();
+    let qtype = record_type.to_string();
+    let protocol = request_info.protocol.to_string();
+
+    let authority = self.authority().await.map_err(|err| {
+      error!("Unable to build catalog zone: {}", err);
+      LookupError::from(ResponseCode::ServFail)
+    })?;
+
+    let result = match record_type {
+      // we do not keep history, so an IXFR is answered with the whole zone
+      // as allowed by RFC 1995
+      RecordType::AXFR | RecordType::IXFR => {
+        let (start_soa, end_soa, records) = try_join!(
+          authority.soa_secure(lookup_options),
+          authority.soa(),
+          authority.lookup(&self.origin, RecordType::AXFR, lookup_options),
+        )?;
+
+        self
+          .metrics
+          .zone_transfers
+          .with_label_values(&[&zone, &qtype])
+          .inc();
+
+        Ok(AuthLookup::AXFR {
+          start_soa: start_soa.unwrap_records(),
+          records: records.unwrap_records(),
+          end_soa: end_soa.unwrap_records(),
+        })
+      }
+      _ => authority.search(request_info, lookup_options).await,
+    };
+
+    let rcode = match &result {
+      Ok(_) | Err(LookupError::NameExists) => ResponseCode::NoError,
+      Err(LookupError::ResponseCode(code)) => *code,
+      Err(_) => ResponseCode::ServFail,
+    };
+    self
+      .metrics
+      .queries
+      .with_label_values(&[&zone, &qtype, &format!("{:?}", rcode), &protocol])
+      .inc();
+
+    result
+  }
+
+  async fn get_nsec_records(
+    &self,
+    _name: &LowerName,
+    _lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    Ok(AuthLookup::default())
+  }
+}
+
+/// Builds the catalog zone as laid out in RFC 9432, members are named after
+/// the zone id.
+fn build(
+  origin: Name,
+  serial: u32,
+  members: Vec<(Uuid, String)>,
+) -> anyhow::Result<InMemoryAuthority> {
+  let invalid = Name::from_ascii("invalid.")?;
+  let zones = Name::from_ascii("zones")?.append_domain(&origin)?;
+
+  let mut records = vec![
+    Record::from_rdata(
+      origin.clone(),
+      0,
+      RData::SOA(SOA::new(
+        invalid.clone(),
+        invalid.clone(),
+        serial,
+        3600,
+        600,
+        2147483646,
+        0,
+      )),
+    ),
+    Record::from_rdata(origin.clone(), 0, RData::NS(NS(invalid))),
+    Record::from_rdata(
+      Name::from_ascii("version")?.append_domain(&origin)?,
+      0,
+      RData::TXT(TXT::new(vec!["2".to_string()])),
+    ),
+  ];
+
+  for (id, name) in members {
+    let mut name = Name::from_ascii(name)?;
+    name.set_fqdn(true);
+
+    records.push(Record::from_rdata(
+      Name::from_ascii(id.simple().to_string())?.append_domain(&zones)?,
+      0,
+      RData::PTR(PTR(name)),
+    ));
+  }
+
+  let mut sets: BTreeMap<RrKey, RecordSet> = BTreeMap::new();
+  for record in records {
+    sets
+      .entry(RrKey::new(record.name().into(), record.record_type()))
+      .or_insert_with(|| RecordSet::new(record.name(), record.record_type(), serial))
+      .insert(record, serial);
+  }
+
+  InMemoryAuthority::new(origin, sets, ZoneType::Primary, true).map_err(|err| anyhow!(err))
+}
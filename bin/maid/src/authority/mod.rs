@@ -1,3 +1,7 @@
+mod catalog_zone;
+mod secondary;
 mod zone;
 
-pub(crate) use zone::ZoneAuthority;
+pub(crate) use catalog_zone::CatalogZoneAuthority;
+pub(crate) use secondary::{SecondaryAuthority, SecondaryZones};
+pub(crate) use zone::{AnyResponse, PrimaryZones};
@@ -0,0 +1,745 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use hickory_client::client::{AsyncClient, ClientHandle};
+use hickory_client::tcp::TcpClientStream;
+use hickory_server::authority::{
+  AuthLookup, Authority, Catalog, LookupError, LookupOptions, MessageRequest, UpdateResult,
+  ZoneType,
+};
+use hickory_server::proto::iocompat::AsyncIoTokioAsStd;
+use hickory_server::proto::op::ResponseCode;
+use hickory_server::proto::rr::rdata::SOA;
+use hickory_server::proto::rr::{
+  DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey,
+};
+use hickory_server::server::RequestInfo;
+use hickory_server::store::in_memory::InMemoryAuthority;
+use sea_orm::prelude::Uuid;
+use time::OffsetDateTime;
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::task::AbortHandle;
+use tokio::time::{interval, sleep};
+use tracing::{error, info};
+
+use entity::zone;
+
+use crate::metrics::Metrics;
+use crate::service::{SecondaryService, ZoneCopy};
+
+const DEFAULT_PORT: u16 = 53;
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+// until there is a SOA to take the retry interval from
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+// how often zones becoming or ceasing to be secondaries are looked for
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Copy of the zone being served.
+struct ServedCopy {
+  authority: Arc<InMemoryAuthority>,
+  soa: SOA,
+  records: Vec<Record>,
+  refreshed: OffsetDateTime,
+}
+
+impl ServedCopy {
+  fn new(origin: &Name, records: Vec<Record>, refreshed: OffsetDateTime) -> anyhow::Result<Self> {
+    let soa = records
+      .iter()
+      .find_map(|record| record.data().and_then(RData::as_soa))
+      .cloned()
+      .ok_or_else(|| anyhow!("zone {} has no SOA record", origin))?;
+
+    let mut sets: BTreeMap<RrKey, RecordSet> = BTreeMap::new();
+    for record in &records {
+      sets
+        .entry(RrKey::new(record.name().into(), record.record_type()))
+        .or_insert_with(|| RecordSet::new(record.name(), record.record_type(), soa.serial()))
+        .insert(record.clone(), soa.serial());
+    }
+
+    let authority = InMemoryAuthority::new(origin.clone(), sets, ZoneType::Secondary, false)
+      .map_err(|err| anyhow!(err))?;
+
+    Ok(Self {
+      authority: Arc::new(authority),
+      soa,
+      records,
+      refreshed,
+    })
+  }
+
+  /// Whether the primary has been unreachable for longer than the zone may
+  /// be served without it.
+  fn is_expired(&self) -> bool {
+    OffsetDateTime::now_utc() - self.refreshed > Duration::from_secs(self.soa.expire() as u64)
+  }
+}
+
+/// Serves a copy of a zone mastered elsewhere, kept up to date by transfers
+/// from its primary server.
+pub(crate) struct SecondaryAuthority {
+  secondary_service: Arc<SecondaryService>,
+  metrics: Arc<Metrics>,
+  zone_id: Uuid,
+  origin: LowerName,
+  primary: SocketAddr,
+  copy: RwLock<Option<ServedCopy>>,
+  notified: Notify,
+}
+
+impl SecondaryAuthority {
+  /// Restores the stored copy of the zone, if there is one.
+  pub(crate) async fn load(
+    secondary_service: Arc<SecondaryService>,
+    metrics: Arc<Metrics>,
+    zone: zone::Model,
+  ) -> anyhow::Result<Arc<Self>> {
+    let primary = primary_of(&zone)?;
+    let origin = origin_of(&zone)?;
+
+    let copy = match secondary_service.load(zone.id).await? {
+      Some(copy) => Some(ServedCopy::new(&origin, copy.records, copy.refreshed)?),
+      None => None,
+    };
+
+    Ok(Arc::new(Self {
+      secondary_service,
+      metrics,
+      zone_id: zone.id,
+      origin: origin.into(),
+      primary,
+      copy: RwLock::new(copy),
+      notified: Notify::new(),
+    }))
+  }
+
+  /// Whether it serves the zone as it is configured right now.
+  pub(crate) fn is_of(&self, zone: &zone::Model) -> bool {
+    self.zone_id == zone.id
+      && origin_of(zone).is_ok_and(|origin| self.origin == LowerName::from(origin))
+      && primary_of(zone).is_ok_and(|primary| self.primary == primary)
+  }
+
+  pub(crate) fn primary(&self) -> SocketAddr {
+    self.primary
+  }
+
+  /// Whether there is no copy of the zone to serve, or it expired.
+  pub(crate) async fn is_expired(&self) -> bool {
+    self
+      .copy
+      .read()
+      .await
+      .as_ref()
+      .is_none_or(ServedCopy::is_expired)
+  }
+
+  /// Checks the primary for a new serial right away.
+  pub(crate) fn notify(&self) {
+    self.notified.notify_one();
+  }
+
+  /// Follows the refresh and retry timers of the zone, or a NOTIFY from the
+  /// primary, whatever comes first.
+  pub(crate) async fn refresh(self: Arc<Self>) {
+    let zone = self.origin.to_string();
+
+    loop {
+      let wait = match self.check().await {
+        Ok((result, refresh)) => {
+          self
+            .metrics
+            .secondary_refreshes
+            .with_label_values(&[&zone, result])
+            .inc();
+          refresh
+        }
+        Err(err) => {
+          error!("Unable to refresh secondary zone {}: {}", zone, err);
+          self
+            .metrics
+            .secondary_refreshes
+            .with_label_values(&[&zone, "failed"])
+            .inc();
+
+          match self.copy.read().await.as_ref() {
+            Some(copy) => Duration::from_secs(copy.soa.retry() as u64),
+            None => INITIAL_RETRY,
+          }
+        }
+      };
+
+      select! {
+        _ = sleep(wait) => {},
+        _ = self.notified.notified() => {},
+      }
+    }
+  }
+
+  /// Transfers the zone if the primary has a newer serial, returns what
+  /// happened and when to check next.
+  async fn check(&self) -> anyhow::Result<(&'static str, Duration)> {
+    let origin = Name::from(&self.origin);
+
+    let (stream, sender) =
+      TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(self.primary, TRANSFER_TIMEOUT);
+    let (mut client, background) =
+      AsyncClient::with_timeout(stream, sender, TRANSFER_TIMEOUT, None).await?;
+    let background = tokio::spawn(background);
+
+    let result = async {
+      let response = client
+        .query(origin.clone(), DNSClass::IN, RecordType::SOA)
+        .await?;
+      let primary_soa = response
+        .answers()
+        .iter()
+        .find_map(|record| record.data().and_then(RData::as_soa))
+        .cloned()
+        .ok_or_else(|| anyhow!("primary did not answer with a SOA record"))?;
+
+      let current = self
+        .copy
+        .read()
+        .await
+        .as_ref()
+        .map(|copy| (copy.soa.clone(), copy.records.clone()));
+      let now = OffsetDateTime::now_utc();
+
+      if let Some((soa, _)) = &current {
+        if !is_newer(primary_soa.serial(), soa.serial()) {
+          self.secondary_service.touch(self.zone_id, now).await?;
+          if let Some(copy) = self.copy.write().await.as_mut() {
+            copy.refreshed = now;
+          }
+          return Ok(("unchanged", Duration::from_secs(soa.refresh() as u64)));
+        }
+      }
+
+      // hickory uses the primary name server as owner of the SOA sent along
+      // with an IXFR, the primary only looks at the serial anyway
+      let last_soa = current.as_ref().map(|(soa, _)| {
+        SOA::new(
+          origin.clone(),
+          soa.rname().clone(),
+          soa.serial(),
+          soa.refresh(),
+          soa.retry(),
+          soa.expire(),
+          soa.minimum(),
+        )
+      });
+
+      let mut transferred = Vec::new();
+      let mut responses = client.zone_transfer(origin.clone(), last_soa);
+      while let Some(response) = responses.next().await {
+        let response = response?;
+        if response.response_code() != ResponseCode::NoError {
+          return Err(anyhow!(
+            "transfer failed with {:?}",
+            response.response_code()
+          ));
+        }
+        transferred.extend(response.answers().iter().cloned());
+      }
+
+      let records = current.map(|(_, records)| records).unwrap_or_default();
+      let records = apply_transfer(records, transferred)?;
+
+      let copy = ServedCopy::new(&origin, records, now)?;
+      let refresh = Duration::from_secs(copy.soa.refresh() as u64);
+
+      self
+        .secondary_service
+        .store(
+          self.zone_id,
+          &ZoneCopy {
+            serial: copy.soa.serial(),
+            records: copy.records.clone(),
+            refreshed: now,
+          },
+        )
+        .await?;
+
+      info!(
+        "Transferred secondary zone {} with serial {}",
+        self.origin,
+        copy.soa.serial()
+      );
+      *self.copy.write().await = Some(copy);
+
+      Ok(("transferred", refresh))
+    }
+    .await;
+
+    background.abort();
+    result
+  }
+
+  async fn authority(&self) -> Result<Arc<InMemoryAuthority>, LookupError> {
+    match self.copy.read().await.as_ref() {
+      Some(copy) if !copy.is_expired() => Ok(copy.authority.clone()),
+      _ => Err(LookupError::from(ResponseCode::ServFail)),
+    }
+  }
+}
+
+#[async_trait]
+impl Authority for SecondaryAuthority {
+  type Lookup = AuthLookup;
+
+  fn zone_type(&self) -> ZoneType {
+    ZoneType::Secondary
+  }
+
+  fn is_axfr_allowed(&self) -> bool {
+    false
+  }
+
+  async fn update(&self, _update: &MessageRequest) -> UpdateResult<bool> {
+    Err(ResponseCode::NotImp)
+  }
+
+  fn origin(&self) -> &LowerName {
+    &self.origin
+  }
+
+  async fn lookup(
+    &self,
+    name: &LowerName,
+    query_type: RecordType,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    self
+      .authority()
+      .await?
+      .lookup(name, query_type, lookup_options)
+      .await
+  }
+
+  async fn search(
+    &self,
+    request_info: RequestInfo<'_>,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    let zone = self.origin.to_string();
+    let qtype = request_info.query.query_type().to_string();
+    let timer = self
+      .metrics
+      .lookup_duration
+      .with_label_values(&[&zone, &qtype])
+      .start_timer();
+
+    let protocol = request_info.protocol.to_string();
+    let result = match self.authority().await {
+      Ok(authority) => authority.search(request_info, lookup_options).await,
+      Err(err) => Err(err),
+    };
+
+    timer.observe_duration();
+
+    let rcode = match &result {
+      Ok(_) | Err(LookupError::NameExists) => ResponseCode::NoError,
+      Err(LookupError::ResponseCode(code)) => *code,
+      Err(_) => ResponseCode::ServFail,
+    };
+    self
+      .metrics
+      .queries
+      .with_label_values(&[&zone, &qtype, &format!("{:?}", rcode), &protocol])
+      .inc();
+
+    result
+  }
+
+  async fn get_nsec_records(
+    &self,
+    name: &LowerName,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    self
+      .authority()
+      .await?
+      .get_nsec_records(name, lookup_options)
+      .await
+  }
+}
+
+/// Secondary zones being served, following the zones in the database as
+/// they become secondaries or stop being ones.
+pub(crate) struct SecondaryZones {
+  secondary_service: Arc<SecondaryService>,
+  metrics: Arc<Metrics>,
+  catalog: Arc<RwLock<Catalog>>,
+  zones: RwLock<HashMap<LowerName, (Arc<SecondaryAuthority>, AbortHandle)>>,
+  // the periodic reload and the one on SIGHUP must not interleave
+  reloading: Mutex<()>,
+}
+
+impl SecondaryZones {
+  pub(crate) fn new(
+    secondary_service: Arc<SecondaryService>,
+    metrics: Arc<Metrics>,
+    catalog: Arc<RwLock<Catalog>>,
+  ) -> Arc<Self> {
+    Arc::new(Self {
+      secondary_service,
+      metrics,
+      catalog,
+      zones: RwLock::new(HashMap::new()),
+      reloading: Mutex::new(()),
+    })
+  }
+
+  pub(crate) async fn get(&self, origin: &LowerName) -> Option<Arc<SecondaryAuthority>> {
+    let zones = self.zones.read().await;
+    zones.get(origin).map(|(authority, _)| authority.clone())
+  }
+
+  /// Finds the secondary zone a name belongs to.
+  pub(crate) async fn find(&self, name: &LowerName) -> Option<Arc<SecondaryAuthority>> {
+    let zones = self.zones.read().await;
+    let mut name = name.clone();
+    loop {
+      if let Some((authority, _)) = zones.get(&name) {
+        return Some(authority.clone());
+      }
+      if name.is_root() {
+        return None;
+      }
+      name = name.base_name();
+    }
+  }
+
+  /// Reloads periodically and, like the config file, on SIGHUP.
+  pub(crate) async fn run(self: Arc<Self>) {
+    let mut interval = interval(RELOAD_INTERVAL);
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+      .expect("failed to install signal handler");
+
+    loop {
+      #[cfg(unix)]
+      select! {
+        _ = interval.tick() => {},
+        _ = hangup.recv() => {},
+      }
+      #[cfg(not(unix))]
+      interval.tick().await;
+
+      if let Err(err) = self.reload().await {
+        error!("Unable to reload secondary zones: {}", err);
+      }
+    }
+  }
+
+  /// Starts serving new secondary zones and stops serving the ones that are
+  /// gone, zones with a changed name or primary are loaded anew.
+  pub(crate) async fn reload(&self) -> anyhow::Result<()> {
+    let _reloading = self.reloading.lock().await;
+    let zones = self.secondary_service.zones().await?;
+
+    let mut kept = HashSet::new();
+    let mut changed = Vec::new();
+    {
+      let served = self.zones.read().await;
+      for zone in zones {
+        match served
+          .values()
+          .find(|(authority, _)| authority.is_of(&zone))
+        {
+          Some((authority, _)) => {
+            kept.insert(authority.origin().clone());
+          }
+          None => changed.push(zone),
+        }
+      }
+    }
+
+    // loaded before taking the lock, queries keep being answered meanwhile
+    let mut loaded = Vec::new();
+    for zone in changed {
+      let name = zone.name.clone();
+      match SecondaryAuthority::load(self.secondary_service.clone(), self.metrics.clone(), zone)
+        .await
+      {
+        Ok(authority) => loaded.push(authority),
+        Err(err) => error!("Unable to load secondary zone {}: {}", name, err),
+      }
+    }
+
+    let mut served = self.zones.write().await;
+    let mut catalog = self.catalog.write().await;
+
+    let gone: Vec<_> = served
+      .keys()
+      .filter(|origin| !kept.contains(*origin))
+      .cloned()
+      .collect();
+    for origin in gone {
+      if let Some((_, refresh)) = served.remove(&origin) {
+        refresh.abort();
+      }
+      catalog.remove(&origin);
+      info!("Stopped serving secondary zone {}", origin);
+    }
+
+    for authority in loaded {
+      let origin = authority.origin().clone();
+      catalog.upsert(origin.clone(), Box::new(authority.clone()));
+      let refresh = tokio::spawn(authority.clone().refresh()).abort_handle();
+      served.insert(origin.clone(), (authority, refresh));
+      info!("Serving secondary zone {}", origin);
+    }
+
+    Ok(())
+  }
+}
+
+fn origin_of(zone: &zone::Model) -> anyhow::Result<Name> {
+  let mut origin = Name::from_ascii(&zone.name)?;
+  origin.set_fqdn(true);
+  Ok(origin)
+}
+
+fn primary_of(zone: &zone::Model) -> anyhow::Result<SocketAddr> {
+  let primary = zone
+    .primary_server
+    .as_deref()
+    .ok_or_else(|| anyhow!("zone {} has no primary server", zone.name))?;
+
+  Ok(match primary.parse::<SocketAddr>() {
+    Ok(primary) => primary,
+    Err(_) => SocketAddr::new(primary.parse::<IpAddr>()?, DEFAULT_PORT),
+  })
+}
+
+/// RFC 1982 serial number arithmetic.
+fn is_newer(serial: u32, than: u32) -> bool {
+  serial != than && (serial.wrapping_sub(than) as i32) > 0
+}
+
+/// Applies the answers of a zone transfer to the current records, either a
+/// full zone (AXFR, or IXFR falling back to it) or a sequence of differences
+/// as described in RFC 1995.
+fn apply_transfer(
+  mut records: Vec<Record>,
+  transferred: Vec<Record>,
+) -> anyhow::Result<Vec<Record>> {
+  let is_soa = |record: &Record| record.record_type() == RecordType::SOA;
+
+  let first = match (transferred.first(), transferred.last()) {
+    (Some(first), Some(last)) if is_soa(first) && is_soa(last) => first.clone(),
+    _ => return Err(anyhow!("transfer has to start and end with a SOA record")),
+  };
+
+  // the primary only answered with its SOA, which is not newer than ours
+  if transferred.len() == 1 {
+    return Ok(records);
+  }
+
+  // a full transfer has the SOA at both ends only
+  if !is_soa(&transferred[1]) {
+    let mut records = transferred;
+    records.pop();
+    return Ok(records);
+  }
+
+  // SOA of the old version, its deletions, SOA of the new version, its
+  // additions, and so on
+  let mut deleting = false;
+  for record in &transferred[1..transferred.len() - 1] {
+    if is_soa(record) {
+      deleting = !deleting;
+      continue;
+    }
+
+    if deleting {
+      records.retain(|existing| {
+        existing.name() != record.name()
+          || existing.record_type() != record.record_type()
+          || existing.data() != record.data()
+      });
+    } else {
+      records.push(record.clone());
+    }
+  }
+
+  records.retain(|record| !is_soa(record));
+  records.insert(0, first);
+
+  Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+
+  use hickory_server::proto::rr::rdata::A;
+
+  use super::*;
+
+  fn soa(serial: u32) -> Record {
+    Record::from_rdata(
+      Name::from_str("sec.test.").unwrap(),
+      300,
+      RData::SOA(SOA::new(
+        Name::from_str("ns.sec.test.").unwrap(),
+        Name::from_str("admin.sec.test.").unwrap(),
+        serial,
+        7200,
+        3600,
+        1209600,
+        300,
+      )),
+    )
+  }
+
+  fn a(name: &str, last: u8) -> Record {
+    Record::from_rdata(
+      Name::from_str(name).unwrap(),
+      300,
+      RData::A(A::new(192, 0, 2, last)),
+    )
+  }
+
+  fn serial(records: &[Record]) -> Option<u32> {
+    records
+      .first()
+      .and_then(Record::data)
+      .and_then(RData::as_soa)
+      .map(SOA::serial)
+  }
+
+  fn others(records: &[Record]) -> Vec<String> {
+    records[1..]
+      .iter()
+      .map(|record| format!("{} {}", record.name(), record.data().unwrap()))
+      .collect()
+  }
+
+  fn current() -> Vec<Record> {
+    vec![
+      soa(1),
+      a("a.sec.test.", 1),
+      a("a.sec.test.", 2),
+      a("b.sec.test.", 1),
+    ]
+  }
+
+  #[test]
+  fn malformed() {
+    assert!(apply_transfer(current(), vec![]).is_err());
+    assert!(apply_transfer(current(), vec![a("a.sec.test.", 1), soa(2)]).is_err());
+    assert!(apply_transfer(current(), vec![soa(2), a("a.sec.test.", 1)]).is_err());
+  }
+
+  #[test]
+  fn up_to_date() {
+    let records = apply_transfer(current(), vec![soa(1)]).unwrap();
+
+    assert_eq!(records, current());
+  }
+
+  #[test]
+  fn full() {
+    let records = apply_transfer(
+      current(),
+      vec![soa(2), a("c.sec.test.", 1), a("a.sec.test.", 1), soa(2)],
+    )
+    .unwrap();
+
+    assert_eq!(serial(&records), Some(2));
+    assert_eq!(
+      others(&records),
+      ["c.sec.test. 192.0.2.1", "a.sec.test. 192.0.2.1"]
+    );
+  }
+
+  #[test]
+  fn incremental() {
+    let records = apply_transfer(
+      current(),
+      vec![
+        soa(2),
+        soa(1),
+        a("a.sec.test.", 2),
+        soa(2),
+        a("c.sec.test.", 1),
+        soa(2),
+      ],
+    )
+    .unwrap();
+
+    assert_eq!(serial(&records), Some(2));
+    // only the record with the same data is gone
+    assert_eq!(
+      others(&records),
+      [
+        "a.sec.test. 192.0.2.1",
+        "b.sec.test. 192.0.2.1",
+        "c.sec.test. 192.0.2.1",
+      ]
+    );
+  }
+
+  #[test]
+  fn incremental_sequence() {
+    let records = apply_transfer(
+      current(),
+      vec![
+        soa(3),
+        soa(1),
+        a("b.sec.test.", 1),
+        soa(2),
+        a("c.sec.test.", 1),
+        soa(2),
+        a("c.sec.test.", 1),
+        a("a.sec.test.", 1),
+        soa(3),
+        a("b.sec.test.", 2),
+        soa(3),
+      ],
+    )
+    .unwrap();
+
+    assert_eq!(serial(&records), Some(3));
+    assert_eq!(
+      others(&records),
+      ["a.sec.test. 192.0.2.2", "b.sec.test. 192.0.2.2"]
+    );
+  }
+
+  #[test]
+  fn deleting_unknown_records() {
+    let records = apply_transfer(
+      current(),
+      vec![soa(2), soa(1), a("d.sec.test.", 1), soa(2), soa(2)],
+    )
+    .unwrap();
+
+    assert_eq!(serial(&records), Some(2));
+    assert_eq!(others(&records), others(&current()));
+  }
+
+  #[test]
+  fn serial_arithmetic() {
+    assert!(is_newer(2, 1));
+    assert!(!is_newer(1, 1));
+    assert!(!is_newer(1, 2));
+    // see RFC 1982 3.2
+    assert!(is_newer(0, u32::MAX));
+    assert!(is_newer(5, u32::MAX - 5));
+    assert!(!is_newer(u32::MAX, 0));
+    assert!(!is_newer(1 << 31, 0));
+    assert!(is_newer((1 << 31) - 1, 0));
+  }
+}
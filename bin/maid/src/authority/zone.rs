@@ -1,64 +1,101 @@
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::iter;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use clap::ValueEnum;
 use hickory_server::authority::{
-  AnyRecords, AuthLookup, Authority, LookupError, LookupOptions, LookupRecords, LookupResult,
-  MessageRequest, UpdateResult, ZoneType,
+  AnyRecords, AuthLookup, Authority, Catalog, LookupError, LookupOptions, LookupRecords,
+  LookupResult, MessageRequest, UpdateResult, ZoneType,
 };
 use hickory_server::proto::op::ResponseCode;
 use hickory_server::proto::rr::domain::Label;
+use hickory_server::proto::rr::rdata::HINFO;
 use hickory_server::proto::rr::{LowerName, Name, RData, Record, RecordSet, RecordType};
-use hickory_server::server::RequestInfo;
+use hickory_server::server::{Protocol, RequestInfo};
 use sea_orm::prelude::Uuid;
+use tokio::select;
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::interval;
 use tokio::try_join;
+use tracing::{error, info};
 
-use crate::service::ZoneService;
+use crate::dnstap::{Dnstap, QueryLog};
+use crate::geo::{GeoIp, Location};
+use crate::metrics::Metrics;
+use crate::service::{CatalogZoneService, Client, ZoneService};
+use crate::subnet;
+
+// ttl of the synthesized HINFO record, as suggested by RFC 8482
+const HINFO_TTL: u32 = 3600;
+// how often the served zones are brought in line with the catalog zone
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How ANY queries are answered over udp, see RFC 8482. Over other
+/// transports every RRset of the name is returned.
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum AnyResponse {
+  /// a synthesized HINFO record
+  Hinfo,
+  /// a single RRset of the name
+  Rrset,
+  /// every RRset of the name
+  Full,
+}
 
 pub(crate) struct ZoneAuthority {
   zone_service: Arc<ZoneService>,
+  metrics: Arc<Metrics>,
+  dnstap: Option<Arc<Dnstap>>,
+  geo_ip: Option<Arc<GeoIp>>,
+  any_response: AnyResponse,
   zone_id: Uuid,
   origin: LowerName,
   labels: usize,
 }
 
 impl ZoneAuthority {
-  pub(crate) fn new(zone_service: Arc<ZoneService>, zone_id: Uuid, origin: LowerName) -> Self {
+  pub(crate) fn new(
+    zone_service: Arc<ZoneService>,
+    metrics: Arc<Metrics>,
+    dnstap: Option<Arc<Dnstap>>,
+    geo_ip: Option<Arc<GeoIp>>,
+    any_response: AnyResponse,
+    zone_id: Uuid,
+    origin: LowerName,
+  ) -> Self {
     Self {
       zone_service,
+      metrics,
+      dnstap,
+      geo_ip,
+      any_response,
       zone_id,
       labels: Name::from(origin.clone()).iter().len(),
       origin,
     }
   }
-}
-
-#[async_trait]
-impl Authority for ZoneAuthority {
-  type Lookup = AuthLookup;
-
-  fn zone_type(&self) -> ZoneType {
-    ZoneType::Primary
-  }
-
-  fn is_axfr_allowed(&self) -> bool {
-    todo!()
-  }
-
-  async fn update(&self, _update: &MessageRequest) -> UpdateResult<bool> {
-    Err(ResponseCode::NotImp)
-  }
 
-  fn origin(&self) -> &LowerName {
-    &self.origin
+  /// Locates the client by the subnet its resolver told us about, falling
+  /// back to the address the query came from.
+  fn locate(&self, src: IpAddr) -> Location {
+    match &self.geo_ip {
+      Some(geo_ip) => geo_ip.locate(subnet::client_address().unwrap_or(src)),
+      None => Location::default(),
+    }
   }
 
-  async fn lookup(
+  /// Looks up records as seen by the given client.
+  async fn lookup_in(
     &self,
     name: &LowerName,
     query_type: RecordType,
     lookup_options: LookupOptions,
-  ) -> Result<Self::Lookup, LookupError> {
+    client: &Client,
+  ) -> Result<AuthLookup, LookupError> {
     let host = {
       let mut host = String::new();
 
@@ -85,12 +122,72 @@ impl Authority for ZoneAuthority {
     // Collect the records from each rr_set
     let (result, additional): (LookupResult<LookupRecords>, Option<LookupRecords>) =
       match query_type {
-        RecordType::AXFR | RecordType::ANY => {
+        RecordType::ANY => {
+          let any_response = if client.udp {
+            self.any_response
+          } else {
+            AnyResponse::Full
+          };
+
+          let answer = match any_response {
+            AnyResponse::Hinfo => {
+              if self
+                .zone_service
+                .name_exists(self.zone_id, &host, client)
+                .await
+                .map_err(|err| {
+                  error!("Unable to look up {}: {}", name, err);
+                  LookupError::from(ResponseCode::ServFail)
+                })?
+              {
+                let mut set = RecordSet::new(&Name::from(name), RecordType::HINFO, 0);
+                set.insert(
+                  Record::from_rdata(
+                    Name::from(name),
+                    HINFO_TTL,
+                    RData::HINFO(HINFO::new("RFC8482".to_string(), String::new())),
+                  ),
+                  0,
+                );
+                vec![set]
+              } else {
+                Vec::new()
+              }
+            }
+            any_response => self
+              .zone_service
+              .lookup_name_any(
+                self.zone_id,
+                &Name::from(&self.origin),
+                name,
+                &host,
+                matches!(any_response, AnyResponse::Full),
+                client,
+              )
+              .await
+              .map_err(|err| {
+                error!("Unable to look up {}: {}", name, err);
+                LookupError::from(ResponseCode::ServFail)
+              })?,
+          };
+
+          let answer = if answer.is_empty() {
+            Err(LookupError::from(ResponseCode::NXDomain))
+          } else {
+            Ok(LookupRecords::many(
+              lookup_options,
+              answer.into_iter().map(Arc::new).collect(),
+            ))
+          };
+
+          (answer, None)
+        }
+        RecordType::AXFR => {
           let result = AnyRecords::new(
             lookup_options,
             self
               .zone_service
-              .lookup_any(self.zone_id)
+              .lookup_any(self.zone_id, client)
               .await
               .unwrap()
               .into_iter()
@@ -111,11 +208,40 @@ impl Authority for ZoneAuthority {
               name,
               &host,
               query_type,
+              client,
             )
             .await
             .unwrap();
 
-          let additional = match answer.as_ref().and_then(|a| maybe_next_name(a, query_type)) {
+          let chain = match answer
+            .as_ref()
+            .filter(|answer| answer.record_type() == RecordType::CNAME)
+          {
+            Some(cname) if query_type != RecordType::CNAME => self
+              .zone_service
+              .cname_chain(
+                self.zone_id,
+                &Name::from(&self.origin),
+                name,
+                query_type,
+                cname,
+                client,
+              )
+              .await
+              .map_err(|err| {
+                error!("Unable to follow the CNAME chain of {}: {}", name, err);
+                LookupError::from(ResponseCode::ServFail)
+              })?,
+            _ => Vec::new(),
+          };
+
+          // a chain that did not resolve is not chased any further
+          let additional = match chain
+            .last()
+            .or(answer.as_ref())
+            .filter(|last| last.record_type() != RecordType::CNAME || chain.is_empty())
+            .and_then(|last| maybe_next_name(last, query_type))
+          {
             Some(search_name) => {
               if !self.origin.zone_of(&search_name) {
                 None
@@ -128,6 +254,7 @@ impl Authority for ZoneAuthority {
                     name,
                     query_type,
                     search_name,
+                    client,
                   )
                   .await
               }
@@ -136,7 +263,14 @@ impl Authority for ZoneAuthority {
           };
 
           let answer = answer.map_or(Err(LookupError::from(ResponseCode::NXDomain)), |rr_set| {
-            Ok(LookupRecords::new(lookup_options, Arc::new(rr_set)))
+            if chain.is_empty() {
+              Ok(LookupRecords::new(lookup_options, Arc::new(rr_set)))
+            } else {
+              Ok(LookupRecords::many(
+                lookup_options,
+                iter::once(rr_set).chain(chain).map(Arc::new).collect(),
+              ))
+            }
           });
 
           let additionals = additional.map(|a| LookupRecords::many(lookup_options, a));
@@ -150,7 +284,7 @@ impl Authority for ZoneAuthority {
         // TODO: evaluate this query, should this be done?
         return if self
           .zone_service
-          .name_exists(self.zone_id, &host)
+          .name_exists(self.zone_id, &host, client)
           .await
           .unwrap()
         {
@@ -178,6 +312,151 @@ impl Authority for ZoneAuthority {
       AuthLookup::answers(answers, additional)
     })
   }
+}
+
+/// Zones we are the primary of, served as long as they are listed in the
+/// catalog zone, so secondaries provisioned from it can transfer them.
+pub(crate) struct PrimaryZones {
+  zone_service: Arc<ZoneService>,
+  catalog_zone_service: Arc<CatalogZoneService>,
+  metrics: Arc<Metrics>,
+  dnstap: Option<Arc<Dnstap>>,
+  geo_ip: Option<Arc<GeoIp>>,
+  any_response: AnyResponse,
+  catalog: Arc<RwLock<Catalog>>,
+  // held while reloading, so the periodic reload and the one on SIGHUP do
+  // not interleave
+  zones: Mutex<HashMap<LowerName, Uuid>>,
+}
+
+impl PrimaryZones {
+  pub(crate) fn new(
+    zone_service: Arc<ZoneService>,
+    catalog_zone_service: Arc<CatalogZoneService>,
+    metrics: Arc<Metrics>,
+    dnstap: Option<Arc<Dnstap>>,
+    geo_ip: Option<Arc<GeoIp>>,
+    any_response: AnyResponse,
+    catalog: Arc<RwLock<Catalog>>,
+  ) -> Arc<Self> {
+    Arc::new(Self {
+      zone_service,
+      catalog_zone_service,
+      metrics,
+      dnstap,
+      geo_ip,
+      any_response,
+      catalog,
+      zones: Mutex::new(HashMap::new()),
+    })
+  }
+
+  /// Reloads periodically and, like the config file, on SIGHUP.
+  pub(crate) async fn run(self: Arc<Self>) {
+    let mut interval = interval(RELOAD_INTERVAL);
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+      .expect("failed to install signal handler");
+
+    loop {
+      #[cfg(unix)]
+      select! {
+        _ = interval.tick() => {},
+        _ = hangup.recv() => {},
+      }
+      #[cfg(not(unix))]
+      interval.tick().await;
+
+      if let Err(err) = self.reload().await {
+        error!("Unable to reload zones: {}", err);
+      }
+    }
+  }
+
+  /// Starts serving zones that became members of the catalog and stops
+  /// serving the ones that are gone.
+  pub(crate) async fn reload(&self) -> anyhow::Result<()> {
+    let mut members = HashMap::new();
+    for (zone_id, name) in self.catalog_zone_service.members().await? {
+      match Name::from_ascii(&name) {
+        Ok(mut origin) => {
+          origin.set_fqdn(true);
+          members.insert(LowerName::from(origin), zone_id);
+        }
+        Err(err) => error!("Unable to serve zone {}: {}", name, err),
+      }
+    }
+
+    let mut served = self.zones.lock().await;
+    let mut catalog = self.catalog.write().await;
+
+    for (origin, zone_id) in served.iter() {
+      if members.get(origin) != Some(zone_id) {
+        catalog.remove(origin);
+        info!("Stopped serving zone {}", origin);
+      }
+    }
+
+    for (origin, zone_id) in &members {
+      if served.get(origin) == Some(zone_id) {
+        continue;
+      }
+
+      let dnstap = self
+        .dnstap
+        .clone()
+        .filter(|dnstap| dnstap.logs_zone(origin));
+      catalog.upsert(
+        origin.clone(),
+        Box::new(Arc::new(ZoneAuthority::new(
+          self.zone_service.clone(),
+          self.metrics.clone(),
+          dnstap,
+          self.geo_ip.clone(),
+          self.any_response,
+          *zone_id,
+          origin.clone(),
+        ))),
+      );
+      info!("Serving zone {}", origin);
+    }
+
+    *served = members;
+
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl Authority for ZoneAuthority {
+  type Lookup = AuthLookup;
+
+  fn zone_type(&self) -> ZoneType {
+    ZoneType::Primary
+  }
+
+  fn is_axfr_allowed(&self) -> bool {
+    todo!()
+  }
+
+  async fn update(&self, _update: &MessageRequest) -> UpdateResult<bool> {
+    Err(ResponseCode::NotImp)
+  }
+
+  fn origin(&self) -> &LowerName {
+    &self.origin
+  }
+
+  async fn lookup(
+    &self,
+    name: &LowerName,
+    query_type: RecordType,
+    lookup_options: LookupOptions,
+  ) -> Result<Self::Lookup, LookupError> {
+    self
+      .lookup_in(name, query_type, lookup_options, &Client::default())
+      .await
+  }
 
   async fn search(
     &self,
@@ -187,32 +466,95 @@ impl Authority for ZoneAuthority {
     let lookup_name = request_info.query.name();
     let record_type: RecordType = request_info.query.query_type();
 
+    let query_time = SystemTime::now();
+    let zone = self.origin.to_string();
+    let qtype = record_type.to_string();
+    let timer = self
+      .metrics
+      .lookup_duration
+      .with_label_values(&[&zone, &qtype])
+      .start_timer();
+
     // perform the actual lookup
-    match record_type {
-      RecordType::AXFR => {
-        let (start_soa, end_soa, records) = try_join!(
-          self.soa_secure(lookup_options),
-          self.soa(),
-          self.lookup(lookup_name, record_type, lookup_options),
-        )?;
-        Ok(match start_soa {
-          l @ AuthLookup::Empty => l,
-          start_soa => AuthLookup::AXFR {
-            start_soa: match start_soa {
-              AuthLookup::SOA(soa) => soa,
-              _ => panic!("abc"),
-            },
-            records: records.unwrap_records(),
-            end_soa: match end_soa {
-              AuthLookup::SOA(soa) => soa,
-              _ => panic!("abc"),
+    let result = async {
+      let view = self
+        .zone_service
+        .view(self.zone_id, request_info.src.ip())
+        .await
+        .map_err(|err| {
+          error!("Unable to select view: {}", err);
+          LookupError::from(ResponseCode::ServFail)
+        })?;
+      let client = Client::new(
+        view,
+        self.locate(request_info.src.ip()),
+        matches!(request_info.protocol, Protocol::Udp),
+      );
+
+      let result = match record_type {
+        RecordType::AXFR => {
+          let (start_soa, end_soa, records) = try_join!(
+            self.soa_secure(lookup_options),
+            self.soa(),
+            self.lookup_in(lookup_name, record_type, lookup_options, &client),
+          )?;
+
+          self
+            .metrics
+            .zone_transfers
+            .with_label_values(&[&zone, &qtype])
+            .inc();
+
+          Ok(match start_soa {
+            l @ AuthLookup::Empty => l,
+            start_soa => AuthLookup::AXFR {
+              start_soa: match start_soa {
+                AuthLookup::SOA(soa) => soa,
+                _ => panic!("abc"),
+              },
+              records: records.unwrap_records(),
+              end_soa: match end_soa {
+                AuthLookup::SOA(soa) => soa,
+                _ => panic!("abc"),
+              },
             },
-          },
-        })
+          })
+        }
+        // A standard Lookup path
+        _ => {
+          self
+            .lookup_in(lookup_name, record_type, lookup_options, &client)
+            .await
+        }
+      };
+
+      if client.is_tailored() {
+        subnet::mark_tailored();
       }
-      // A standard Lookup path
-      _ => self.lookup(lookup_name, record_type, lookup_options).await,
+
+      result
     }
+    .await;
+
+    timer.observe_duration();
+
+    let rcode = match &result {
+      Ok(_) | Err(LookupError::NameExists) => ResponseCode::NoError,
+      Err(LookupError::ResponseCode(code)) => *code,
+      Err(_) => ResponseCode::ServFail,
+    };
+    if let Some(dnstap) = self.dnstap.as_ref().filter(|dnstap| dnstap.sample()) {
+      dnstap.log(QueryLog {
+        zone: &self.origin,
+        request_info: &request_info,
+        query_time,
+        response_time: SystemTime::now(),
+        response: &result,
+        response_code: rcode,
+      });
+    }
+
+    result
   }
 
   async fn get_nsec_records(
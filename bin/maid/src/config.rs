@@ -0,0 +1,264 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hickory_server::proto::rr::Name;
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, warn};
+use tracing_subscriber::{reload, Registry};
+use url::Url;
+
+use crate::acl::AccessControl;
+use crate::args::MaidArgs;
+use crate::rrl::RateLimiter;
+use crate::service::ZoneService;
+
+/// Contents of the `--config` file, every setting in it overrides the flag
+/// of the same meaning.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+  listen: ListenConfig,
+  database: DatabaseConfig,
+  logging: LoggingConfig,
+  acl: AclConfig,
+  rate_limit: RateLimitConfig,
+  zone_defaults: ZoneDefaultsConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ListenConfig {
+  addr: Option<Vec<SocketAddr>>,
+  udp: Option<Vec<SocketAddr>>,
+  tcp: Option<Vec<SocketAddr>>,
+  tls: Option<Vec<SocketAddr>>,
+  doh: Option<Vec<SocketAddr>>,
+  metrics: Option<SocketAddr>,
+  tls_cert_path: Option<PathBuf>,
+  tls_key_path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseConfig {
+  url: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingConfig {
+  level: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AclConfig {
+  query: Option<Vec<IpNet>>,
+  transfer: Option<Vec<IpNet>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RateLimitConfig {
+  responses_per_second: Option<u32>,
+  nxdomains_per_second: Option<u32>,
+  errors_per_second: Option<u32>,
+  window: Option<u32>,
+  slip: Option<u32>,
+  ipv4_prefix_length: Option<u8>,
+  ipv6_prefix_length: Option<u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ZoneDefaultsConfig {
+  ttl: Option<u32>,
+  mname: Option<String>,
+  rname: Option<String>,
+  refresh: Option<i32>,
+  retry: Option<i32>,
+  expire: Option<i32>,
+  minimum: Option<u32>,
+}
+
+impl ConfigFile {
+  pub(crate) async fn load(path: &Path) -> anyhow::Result<Self> {
+    let config = tokio::fs::read_to_string(path).await?;
+    Ok(toml::from_str(&config)?)
+  }
+
+  /// Overrides the flags with the settings of the file.
+  pub(crate) fn apply(self, args: &mut MaidArgs) -> anyhow::Result<()> {
+    let listen = self.listen;
+    set(&mut args.listen_addr, listen.addr);
+    set(&mut args.udp_listen_addr, listen.udp);
+    set(&mut args.tcp_listen_addr, listen.tcp);
+    set(&mut args.tls_listen_addr, listen.tls);
+    set(&mut args.doh_listen_addr, listen.doh);
+    set(&mut args.metrics_listen_addr, listen.metrics.map(Some));
+    set(&mut args.tls_cert_path, listen.tls_cert_path.map(Some));
+    set(&mut args.tls_key_path, listen.tls_key_path.map(Some));
+
+    if let Some(url) = self.database.url {
+      args.database_url = Some(Url::parse(&url)?);
+    }
+
+    if let Some(level) = self.logging.level {
+      args.log_level = LevelFilter::from_str(&level)?;
+    }
+
+    set(&mut args.acl.query, self.acl.query);
+    set(&mut args.acl.transfer, self.acl.transfer);
+
+    let rate_limit = self.rate_limit;
+    let limits = &mut args.rate_limit;
+    set(
+      &mut limits.responses_per_second,
+      rate_limit.responses_per_second,
+    );
+    set(
+      &mut limits.nxdomains_per_second,
+      rate_limit.nxdomains_per_second.map(Some),
+    );
+    set(
+      &mut limits.errors_per_second,
+      rate_limit.errors_per_second.map(Some),
+    );
+    set(&mut limits.window, rate_limit.window);
+    set(&mut limits.slip, rate_limit.slip);
+    set(
+      &mut limits.ipv4_prefix_length,
+      rate_limit.ipv4_prefix_length,
+    );
+    set(
+      &mut limits.ipv6_prefix_length,
+      rate_limit.ipv6_prefix_length,
+    );
+
+    let zone_defaults = self.zone_defaults;
+    let defaults = &mut args.zone_defaults;
+    set(&mut defaults.ttl, zone_defaults.ttl);
+    if let Some(mname) = zone_defaults.mname {
+      defaults.mname = Name::from_ascii(mname)?;
+    }
+    if let Some(rname) = zone_defaults.rname {
+      defaults.rname = Name::from_ascii(rname)?;
+    }
+    set(&mut defaults.refresh, zone_defaults.refresh);
+    set(&mut defaults.retry, zone_defaults.retry);
+    set(&mut defaults.expire, zone_defaults.expire);
+    set(&mut defaults.minimum, zone_defaults.minimum);
+
+    Ok(())
+  }
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+  if let Some(value) = value {
+    *target = value;
+  }
+}
+
+/// Names the settings that differ, but only take effect on restart.
+fn restart_required(running: &MaidArgs, args: &MaidArgs) -> Vec<&'static str> {
+  let mut changed = Vec::new();
+
+  if running.listen_addr != args.listen_addr {
+    changed.push("listen.addr");
+  }
+  if running.udp_listen_addr != args.udp_listen_addr {
+    changed.push("listen.udp");
+  }
+  if running.tcp_listen_addr != args.tcp_listen_addr {
+    changed.push("listen.tcp");
+  }
+  if running.tls_listen_addr != args.tls_listen_addr {
+    changed.push("listen.tls");
+  }
+  if running.doh_listen_addr != args.doh_listen_addr {
+    changed.push("listen.doh");
+  }
+  if running.metrics_listen_addr != args.metrics_listen_addr {
+    changed.push("listen.metrics");
+  }
+  if running.tls_cert_path != args.tls_cert_path {
+    changed.push("listen.tls_cert_path");
+  }
+  if running.tls_key_path != args.tls_key_path {
+    changed.push("listen.tls_key_path");
+  }
+  if running.database_url != args.database_url {
+    changed.push("database.url");
+  }
+
+  changed
+}
+
+/// Re-reads the config file on SIGHUP and applies whatever can be changed
+/// while running.
+pub(crate) struct Reloader {
+  path: PathBuf,
+  // as given on the command line, without the file applied
+  flags: MaidArgs,
+  running: MaidArgs,
+  log_level: reload::Handle<LevelFilter, Registry>,
+  acl: Arc<AccessControl>,
+  rate_limiter: Arc<RateLimiter>,
+  zone_service: Arc<ZoneService>,
+}
+
+impl Reloader {
+  pub(crate) fn new(
+    path: PathBuf,
+    flags: MaidArgs,
+    running: MaidArgs,
+    log_level: reload::Handle<LevelFilter, Registry>,
+    acl: Arc<AccessControl>,
+    rate_limiter: Arc<RateLimiter>,
+    zone_service: Arc<ZoneService>,
+  ) -> Self {
+    Self {
+      path,
+      flags,
+      running,
+      log_level,
+      acl,
+      rate_limiter,
+      zone_service,
+    }
+  }
+
+  #[cfg(unix)]
+  pub(crate) async fn run(self) {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+      .expect("failed to install signal handler");
+
+    while hangup.recv().await.is_some() {
+      info!("Reloading {}...", self.path.display());
+      if let Err(err) = self.reload().await {
+        error!("Unable to reload config: {}", err);
+      }
+    }
+  }
+
+  async fn reload(&self) -> anyhow::Result<()> {
+    let mut args = self.flags.clone();
+    ConfigFile::load(&self.path).await?.apply(&mut args)?;
+
+    for setting in restart_required(&self.running, &args) {
+      warn!("Changed setting {} requires a restart", setting);
+    }
+
+    self.log_level.reload(args.log_level)?;
+    self.acl.set_config(args.acl);
+    self.rate_limiter.set_config(args.rate_limit);
+    self.zone_service.set_defaults(args.zone_defaults);
+
+    info!("Config reloaded");
+    Ok(())
+  }
+}
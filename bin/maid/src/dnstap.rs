@@ -0,0 +1,413 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use hickory_server::authority::{AuthLookup, LookupError};
+use hickory_server::proto::op::{Header, Message, MessageType, ResponseCode};
+use hickory_server::proto::rr::{LowerName, Name};
+use hickory_server::proto::serialize::binary::BinEncodable;
+use hickory_server::server::{Protocol, RequestInfo};
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use crate::metrics::Metrics;
+
+// frames are dropped instead of slowing down queries once this many are pending
+const QUEUE_SIZE: usize = 4096;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+// frame streams control frames, see https://farsightsec.github.io/fstrm/
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+// dnstap.proto
+const DNSTAP_MESSAGE: u64 = 1;
+const MESSAGE_AUTH_QUERY: u64 = 1;
+const MESSAGE_AUTH_RESPONSE: u64 = 2;
+const SOCKET_FAMILY_INET: u64 = 1;
+const SOCKET_FAMILY_INET6: u64 = 2;
+const SOCKET_PROTOCOL_UDP: u64 = 1;
+const SOCKET_PROTOCOL_TCP: u64 = 2;
+const SOCKET_PROTOCOL_DOT: u64 = 3;
+const SOCKET_PROTOCOL_DOH: u64 = 4;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum DnstapFormat {
+  /// dnstap protobuf messages wrapped in frame streams
+  Dnstap,
+  /// one json object per line and query
+  Json,
+}
+
+pub(crate) enum DnstapOutput {
+  Socket(PathBuf),
+  File(PathBuf),
+}
+
+/// Logs queries answered by our authorities, see https://dnstap.info.
+pub(crate) struct Dnstap {
+  sender: Sender<Vec<u8>>,
+  format: DnstapFormat,
+  sample_rate: u64,
+  counter: AtomicU64,
+  zones: Vec<LowerName>,
+  metrics: Arc<Metrics>,
+}
+
+/// A query as seen by `ZoneAuthority::search`.
+pub(crate) struct QueryLog<'a> {
+  pub(crate) zone: &'a LowerName,
+  pub(crate) request_info: &'a RequestInfo<'a>,
+  pub(crate) query_time: SystemTime,
+  pub(crate) response_time: SystemTime,
+  pub(crate) response: &'a Result<AuthLookup, LookupError>,
+  pub(crate) response_code: ResponseCode,
+}
+
+impl Dnstap {
+  pub(crate) fn new(
+    output: DnstapOutput,
+    format: DnstapFormat,
+    sample_rate: u64,
+    zones: Vec<LowerName>,
+    metrics: Arc<Metrics>,
+  ) -> Arc<Self> {
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+
+    tokio::spawn(write(output, format, receiver));
+
+    Arc::new(Self {
+      sender,
+      format,
+      sample_rate: sample_rate.max(1),
+      counter: AtomicU64::new(0),
+      zones,
+      metrics,
+    })
+  }
+
+  /// Whether queries for the given zone should be logged at all, an empty
+  /// list of zones enables logging for every zone.
+  pub(crate) fn logs_zone(&self, origin: &LowerName) -> bool {
+    self.zones.is_empty() || self.zones.contains(origin)
+  }
+
+  /// Picks every `sample_rate`-th query.
+  pub(crate) fn sample(&self) -> bool {
+    self
+      .counter
+      .fetch_add(1, Ordering::Relaxed)
+      .is_multiple_of(self.sample_rate)
+  }
+
+  pub(crate) fn log(&self, query: QueryLog<'_>) {
+    let frames = match self.format {
+      DnstapFormat::Dnstap => query.dnstap(),
+      DnstapFormat::Json => vec![query.json()],
+    };
+
+    for frame in frames {
+      if self.sender.try_send(frame).is_err() {
+        self.metrics.dnstap_dropped.inc();
+      }
+    }
+  }
+}
+
+impl QueryLog<'_> {
+  fn dnstap(&self) -> Vec<Vec<u8>> {
+    let mut query = Message::new();
+    query.set_header(*self.request_info.header);
+    query.add_query(self.request_info.query.original().clone());
+
+    let mut response = Message::new();
+    let mut header = Header::response_from_request(self.request_info.header);
+    header.set_message_type(MessageType::Response);
+    header.set_authoritative(true);
+    header.set_response_code(self.response_code);
+    response.set_header(header);
+    response.add_query(self.request_info.query.original().clone());
+    if let Ok(lookup) = self.response {
+      response.add_answers(lookup.iter().cloned());
+    }
+
+    let mut query_message = self.message(MESSAGE_AUTH_QUERY);
+    if let Ok(query) = query.to_vec() {
+      field_bytes(&mut query_message, 10, &query);
+    }
+
+    let mut response_message = self.message(MESSAGE_AUTH_RESPONSE);
+    let (seconds, nanos) = timestamp(self.response_time);
+    field_varint(&mut response_message, 12, seconds);
+    field_fixed32(&mut response_message, 13, nanos);
+    if let Ok(response) = response.to_vec() {
+      field_bytes(&mut response_message, 14, &response);
+    }
+
+    vec![envelope(&query_message), envelope(&response_message)]
+  }
+
+  /// Fields shared by the query and response message.
+  fn message(&self, kind: u64) -> Vec<u8> {
+    let src = self.request_info.src;
+    let mut message = Vec::with_capacity(256);
+
+    field_varint(&mut message, 1, kind);
+    let address = match src.ip() {
+      IpAddr::V4(ip) => {
+        field_varint(&mut message, 2, SOCKET_FAMILY_INET);
+        ip.octets().to_vec()
+      }
+      IpAddr::V6(ip) => {
+        field_varint(&mut message, 2, SOCKET_FAMILY_INET6);
+        ip.octets().to_vec()
+      }
+    };
+    if let Some(protocol) = socket_protocol(self.request_info.protocol) {
+      field_varint(&mut message, 3, protocol);
+    }
+    field_bytes(&mut message, 4, &address);
+    field_varint(&mut message, 6, src.port() as u64);
+    let (seconds, nanos) = timestamp(self.query_time);
+    field_varint(&mut message, 8, seconds);
+    field_fixed32(&mut message, 9, nanos);
+    if let Ok(zone) = Name::from(self.zone).to_bytes() {
+      field_bytes(&mut message, 11, &zone);
+    }
+
+    message
+  }
+
+  fn json(&self) -> Vec<u8> {
+    let query = self.request_info.query;
+
+    let mut line = json!({
+      "time": OffsetDateTime::from(self.query_time).format(&Rfc3339).ok(),
+      "client": self.request_info.src.ip(),
+      "port": self.request_info.src.port(),
+      "transport": self.request_info.protocol.to_string(),
+      "zone": self.zone.to_string(),
+      "name": query.name().to_string(),
+      "type": query.query_type().to_string(),
+      "class": query.query_class().to_string(),
+      "id": self.request_info.header.id(),
+      "rcode": format!("{:?}", self.response_code),
+      "answers": self.response.as_ref().map_or(0, |lookup| lookup.iter().count()),
+      "duration": self
+        .response_time
+        .duration_since(self.query_time)
+        .unwrap_or_default()
+        .as_secs_f64(),
+    })
+    .to_string()
+    .into_bytes();
+    line.push(b'\n');
+
+    line
+  }
+}
+
+async fn write(output: DnstapOutput, format: DnstapFormat, mut receiver: Receiver<Vec<u8>>) {
+  match output {
+    DnstapOutput::File(path) => {
+      // a frame stream has to start with a START frame, so an existing log
+      // can not be continued and is kept instead
+      let path = match format {
+        DnstapFormat::Dnstap => unused_path(path).await,
+        DnstapFormat::Json => path,
+      };
+
+      let result = async {
+        let mut options = OpenOptions::new();
+        match format {
+          DnstapFormat::Dnstap => options.write(true).create_new(true),
+          DnstapFormat::Json => options.append(true).create(true),
+        };
+        let file: File = options.open(&path).await?;
+
+        let mut writer = BufWriter::new(file);
+        if let DnstapFormat::Dnstap = format {
+          writer.write_all(&control_frame(CONTROL_START)).await?;
+        }
+
+        info!("Logging queries to {}", path.display());
+        forward(&mut writer, format, &mut receiver).await
+      }
+      .await;
+
+      if let Err(err) = result {
+        error!("Unable to write query log to {}: {}", path.display(), err);
+      }
+    }
+    DnstapOutput::Socket(path) => loop {
+      let result = async {
+        let mut stream = UnixStream::connect(&path).await?;
+
+        if let DnstapFormat::Dnstap = format {
+          stream.write_all(&control_frame(CONTROL_READY)).await?;
+          if read_control_frame(&mut stream).await? != CONTROL_ACCEPT {
+            return Err(anyhow!("dnstap receiver did not accept our content type"));
+          }
+          stream.write_all(&control_frame(CONTROL_START)).await?;
+        }
+
+        info!("Logging queries to unix://{}", path.display());
+        forward(&mut BufWriter::new(stream), format, &mut receiver).await
+      }
+      .await;
+
+      match result {
+        Ok(()) => return,
+        Err(err) => warn!(
+          "Unable to write query log to unix://{}: {}",
+          path.display(),
+          err
+        ),
+      }
+
+      sleep(RECONNECT_INTERVAL).await;
+    },
+  }
+}
+
+/// The path itself if nothing is there yet, otherwise the path with the
+/// current time added before the extension, e.g. `queries.1792384062.dnstap`.
+async fn unused_path(path: PathBuf) -> PathBuf {
+  if !tokio::fs::try_exists(&path).await.unwrap_or(true) {
+    return path;
+  }
+
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+  let name = match path.extension() {
+    Some(extension) => format!("{}.{}.{}", stem, timestamp, extension.to_string_lossy()),
+    None => format!("{}.{}", stem, timestamp),
+  };
+  let unused = path.with_file_name(name);
+
+  warn!(
+    "Query log {} exists, logging to {} instead",
+    path.display(),
+    unused.display()
+  );
+  unused
+}
+
+/// Writes queued frames until all senders are gone, flushing whenever the
+/// queue runs empty.
+async fn forward<W: AsyncWrite + Unpin>(
+  writer: &mut W,
+  format: DnstapFormat,
+  receiver: &mut Receiver<Vec<u8>>,
+) -> anyhow::Result<()> {
+  while let Some(frame) = receiver.recv().await {
+    if let DnstapFormat::Dnstap = format {
+      writer
+        .write_all(&(frame.len() as u32).to_be_bytes())
+        .await?;
+    }
+    writer.write_all(&frame).await?;
+
+    if receiver.is_empty() {
+      writer.flush().await?;
+    }
+  }
+
+  writer.flush().await?;
+
+  Ok(())
+}
+
+fn control_frame(kind: u32) -> Vec<u8> {
+  let mut payload = kind.to_be_bytes().to_vec();
+  payload.extend(CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+  payload.extend((CONTENT_TYPE.len() as u32).to_be_bytes());
+  payload.extend(CONTENT_TYPE);
+
+  // an empty data frame escapes the control frame
+  let mut frame = 0u32.to_be_bytes().to_vec();
+  frame.extend((payload.len() as u32).to_be_bytes());
+  frame.extend(payload);
+  frame
+}
+
+async fn read_control_frame<R: AsyncRead + Unpin>(reader: &mut R) -> anyhow::Result<u32> {
+  if reader.read_u32().await? != 0 {
+    return Err(anyhow!("expected a control frame"));
+  }
+
+  let length = reader.read_u32().await? as usize;
+  let mut payload = vec![0; length];
+  reader.read_exact(&mut payload).await?;
+
+  payload
+    .get(..4)
+    .map(|kind| u32::from_be_bytes(kind.try_into().unwrap()))
+    .ok_or_else(|| anyhow!("empty control frame"))
+}
+
+fn envelope(message: &[u8]) -> Vec<u8> {
+  let mut dnstap = Vec::with_capacity(message.len() + 32);
+  field_bytes(
+    &mut dnstap,
+    2,
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).as_bytes(),
+  );
+  field_bytes(&mut dnstap, 14, message);
+  field_varint(&mut dnstap, 15, DNSTAP_MESSAGE);
+  dnstap
+}
+
+fn socket_protocol(protocol: Protocol) -> Option<u64> {
+  match protocol {
+    Protocol::Udp => Some(SOCKET_PROTOCOL_UDP),
+    Protocol::Tcp => Some(SOCKET_PROTOCOL_TCP),
+    Protocol::Tls => Some(SOCKET_PROTOCOL_DOT),
+    Protocol::Https => Some(SOCKET_PROTOCOL_DOH),
+    _ => None,
+  }
+}
+
+fn timestamp(time: SystemTime) -> (u64, u32) {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  (since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    buf.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  buf.push(value as u8);
+}
+
+fn field_varint(buf: &mut Vec<u8>, field: u64, value: u64) {
+  varint(buf, field << 3);
+  varint(buf, value);
+}
+
+fn field_fixed32(buf: &mut Vec<u8>, field: u64, value: u32) {
+  varint(buf, field << 3 | 5);
+  buf.extend(value.to_le_bytes());
+}
+
+fn field_bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+  varint(buf, field << 3 | 2);
+  varint(buf, value.len() as u64);
+  buf.extend(value);
+}
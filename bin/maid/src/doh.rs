@@ -0,0 +1,210 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hickory_server::authority::{MessageRequest, MessageResponse};
+use hickory_server::proto::op::Message;
+use hickory_server::proto::rr::Record;
+use hickory_server::proto::serialize::binary::{BinDecodable, BinEncoder};
+use hickory_server::server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo};
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use rustls::ServerConfig;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+use tower_service::Service;
+use tracing::{debug, error};
+
+use crate::handler::Handler;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+// clients not done with the handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// accepting fails e.g. while out of file descriptors, retrying right away
+// would only spin
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Deserialize)]
+struct DohQuery {
+  dns: String,
+}
+
+pub(crate) fn router(handler: Handler) -> Router {
+  Router::new()
+    .route("/dns-query", get(query_get).post(query_post))
+    .with_state(handler)
+}
+
+/// Serves the DoH router on the given listener, terminating tls if a config
+/// is provided and speaking plain http otherwise (e.g. behind a reverse proxy).
+pub(crate) async fn serve(listener: TcpListener, router: Router, tls: Option<Arc<ServerConfig>>) {
+  let acceptor = tls.map(TlsAcceptor::from);
+
+  loop {
+    let (stream, remote_addr) = match listener.accept().await {
+      Ok(accepted) => accepted,
+      Err(err) => {
+        error!("Unable to accept https connection: {}", err);
+        sleep(ACCEPT_BACKOFF).await;
+        continue;
+      }
+    };
+
+    let router = router.clone();
+    let acceptor = acceptor.clone();
+
+    tokio::spawn(async move {
+      let service = service_fn(move |mut request: axum::http::Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(remote_addr));
+        router.clone().call(request)
+      });
+
+      let builder = Builder::new(TokioExecutor::new());
+
+      let result = match acceptor {
+        Some(acceptor) => match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+          Ok(Ok(stream)) => {
+            builder
+              .serve_connection(TokioIo::new(stream), service)
+              .await
+          }
+          Ok(Err(err)) => {
+            debug!("https handshake with {} failed: {}", remote_addr, err);
+            return;
+          }
+          Err(_) => {
+            debug!("https handshake with {} timed out", remote_addr);
+            return;
+          }
+        },
+        None => {
+          builder
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+        }
+      };
+
+      if let Err(err) = result {
+        debug!("https connection with {} failed: {}", remote_addr, err);
+      }
+    });
+  }
+}
+
+async fn query_get(
+  State(handler): State<Handler>,
+  ConnectInfo(src): ConnectInfo<SocketAddr>,
+  Query(query): Query<DohQuery>,
+) -> Result<Response, StatusCode> {
+  let message = URL_SAFE_NO_PAD
+    .decode(query.dns.trim_end_matches('='))
+    .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+  resolve(&handler, src, &message).await
+}
+
+async fn query_post(
+  State(handler): State<Handler>,
+  ConnectInfo(src): ConnectInfo<SocketAddr>,
+  headers: HeaderMap,
+  body: Bytes,
+) -> Result<Response, StatusCode> {
+  if headers.get(CONTENT_TYPE) != Some(&HeaderValue::from_static(DNS_MESSAGE)) {
+    return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+  }
+
+  resolve(&handler, src, &body).await
+}
+
+async fn resolve(
+  handler: &Handler,
+  src: SocketAddr,
+  message: &[u8],
+) -> Result<Response, StatusCode> {
+  let message = MessageRequest::from_bytes(message).map_err(|err| {
+    debug!("Unable to decode DoH request from {}: {}", src, err);
+    StatusCode::BAD_REQUEST
+  })?;
+
+  let response_handle = BufferResponseHandle::default();
+  handler
+    .handle_request(
+      &Request::new(message, src, Protocol::Https),
+      response_handle.clone(),
+    )
+    .await;
+
+  let buffer = std::mem::take(&mut *response_handle.buffer.lock().unwrap());
+  if buffer.is_empty() {
+    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+  }
+
+  let mut headers = HeaderMap::new();
+  headers.insert(CONTENT_TYPE, HeaderValue::from_static(DNS_MESSAGE));
+
+  // RFC 8484 4.2.1: the freshness lifetime must not exceed the smallest ttl
+  let min_ttl = Message::from_vec(&buffer).ok().and_then(|response| {
+    response
+      .answers()
+      .iter()
+      .chain(response.name_servers())
+      .map(Record::ttl)
+      .min()
+  });
+  if let Some(min_ttl) = min_ttl {
+    headers.insert(
+      CACHE_CONTROL,
+      HeaderValue::from_str(&format!("max-age={}", min_ttl)).unwrap(),
+    );
+  }
+
+  Ok((headers, buffer).into_response())
+}
+
+/// Collects the serialized response instead of writing it to a socket.
+#[derive(Clone, Default)]
+struct BufferResponseHandle {
+  buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+#[async_trait]
+impl ResponseHandler for BufferResponseHandle {
+  async fn send_response<'a>(
+    &mut self,
+    response: MessageResponse<
+      '_,
+      'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+    >,
+  ) -> io::Result<ResponseInfo> {
+    let mut buffer = Vec::with_capacity(512);
+    let info = {
+      let mut encoder = BinEncoder::new(&mut buffer);
+      response
+        .destructive_emit(&mut encoder)
+        .map_err(io::Error::other)?
+    };
+
+    *self.buffer.lock().unwrap() = buffer;
+
+    Ok(info)
+  }
+}
@@ -0,0 +1,425 @@
+use std::hash::Hasher;
+use std::io;
+use std::iter;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use clap::Args;
+use hickory_server::authority::{MessageRequest, MessageResponse, MessageResponseBuilder};
+use hickory_server::proto::op::Message;
+use hickory_server::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_server::proto::rr::Record;
+use hickory_server::proto::serialize::binary::{BinDecodable, BinEncoder};
+use hickory_server::server::{Protocol, Request, ResponseHandler, ResponseInfo};
+use rand::Rng;
+use siphasher::sip::SipHasher24;
+
+use crate::metrics::Metrics;
+use crate::rrl::encode_question;
+
+// see RFC 7873 4
+const CLIENT_COOKIE_LENGTH: usize = 8;
+const MIN_COOKIE_LENGTH: usize = 16;
+const MAX_COOKIE_LENGTH: usize = 40;
+// see RFC 9018 4
+const SERVER_COOKIE_VERSION: u8 = 1;
+const SERVER_COOKIE_LENGTH: usize = 16;
+// server cookies are accepted for an hour and up to five minutes ahead, as
+// suggested by RFC 9018 4.3
+const COOKIE_MAX_AGE: i64 = 3600;
+const COOKIE_MAX_SKEW: i64 = 300;
+// see RFC 1035 4.2.1
+const MAX_PLAIN_UDP_SIZE: u16 = 512;
+
+#[derive(Args, Clone)]
+pub(crate) struct EdnsConfig {
+  /// Largest udp response to send, advertised to clients via EDNS
+  #[arg(
+    long = "edns-buffer-size",
+    env = "MAID_EDNS_BUFFER_SIZE",
+    default_value_t = 1232,
+    value_parser = clap::value_parser!(u16).range(512..)
+  )]
+  pub(crate) buffer_size: u16,
+  /// Hex encoded 128 bit secret for server cookies, instances sharing it
+  /// accept each other's cookies; random if not set
+  #[arg(long = "cookie-secret", env = "MAID_COOKIE_SECRET", value_parser = parse_secret)]
+  pub(crate) cookie_secret: Option<u128>,
+  /// Seconds after which server cookies are issued with a newly derived
+  /// secret
+  #[arg(
+    long = "cookie-secret-rotation",
+    env = "MAID_COOKIE_SECRET_ROTATION",
+    default_value_t = 3600,
+    value_parser = clap::value_parser!(u32).range(1..)
+  )]
+  pub(crate) cookie_secret_rotation: u32,
+  /// Truncate udp responses larger than this unless the client presented a
+  /// valid server cookie, so it retries with one or over tcp
+  #[arg(
+    long = "cookie-required-above",
+    env = "MAID_COOKIE_REQUIRED_ABOVE",
+    value_parser = clap::value_parser!(u16).range(512..)
+  )]
+  pub(crate) cookie_required_above: Option<u16>,
+}
+
+fn parse_secret(secret: &str) -> Result<u128, String> {
+  if secret.len() != 32 {
+    return Err("expected 32 hex digits".to_string());
+  }
+  u128::from_str_radix(secret, 16).map_err(|err| err.to_string())
+}
+
+/// Validates and issues DNS cookies, see RFC 7873 and RFC 9018.
+pub(crate) struct Cookies {
+  config: EdnsConfig,
+  secret: u128,
+  metrics: Arc<Metrics>,
+}
+
+impl Cookies {
+  pub(crate) fn new(config: EdnsConfig, metrics: Arc<Metrics>) -> Arc<Self> {
+    Arc::new(Self {
+      secret: config
+        .cookie_secret
+        .unwrap_or_else(|| rand::thread_rng().gen()),
+      config,
+      metrics,
+    })
+  }
+
+  /// Wraps the response handle to answer with a cookie and within the udp
+  /// size limit, hands it back if the request carried a malformed cookie.
+  pub(crate) fn response_handle<R>(
+    &self,
+    request: &Request,
+    inner: R,
+  ) -> Result<CookieResponseHandle<R>, R> {
+    let edns = request.edns();
+    let src = request.src().ip();
+
+    let cookie = match edns.and_then(|edns| edns.option(EdnsCode::Cookie)) {
+      Some(EdnsOption::Unknown(_, cookie)) => Some(cookie.as_slice()),
+      _ => None,
+    };
+
+    let (client_cookie, valid, result) = match cookie {
+      None => (None, false, "none"),
+      Some(cookie) if cookie.len() == CLIENT_COOKIE_LENGTH => (Some(cookie), false, "client"),
+      Some(cookie) if (MIN_COOKIE_LENGTH..=MAX_COOKIE_LENGTH).contains(&cookie.len()) => {
+        let (client_cookie, server_cookie) = cookie.split_at(CLIENT_COOKIE_LENGTH);
+        let valid = self.verify(client_cookie, server_cookie, src);
+        let result = if valid { "valid" } else { "invalid" };
+        (Some(client_cookie), valid, result)
+      }
+      Some(_) => {
+        self.metrics.cookies.with_label_values(&["malformed"]).inc();
+        return Err(inner);
+      }
+    };
+    self.metrics.cookies.with_label_values(&[result]).inc();
+
+    // every answer carries a fresh server cookie
+    let cookie = client_cookie.map(|client_cookie| {
+      let mut cookie = client_cookie.to_vec();
+      cookie.extend_from_slice(&self.server_cookie(client_cookie, now(), src));
+      EdnsOption::Unknown(u16::from(EdnsCode::Cookie), cookie)
+    });
+
+    let max_size = matches!(request.protocol(), Protocol::Udp).then(|| {
+      let max_size = edns.map_or(MAX_PLAIN_UDP_SIZE, |edns| {
+        edns
+          .max_payload()
+          .clamp(MAX_PLAIN_UDP_SIZE, self.config.buffer_size)
+      });
+
+      match self.config.cookie_required_above {
+        Some(required_above) if !valid => max_size.min(required_above),
+        _ => max_size,
+      }
+    });
+
+    Ok(CookieResponseHandle {
+      inner,
+      cookie,
+      buffer_size: self.config.buffer_size,
+      max_size,
+      question: encode_question(request),
+    })
+  }
+
+  fn verify(&self, client_cookie: &[u8], server_cookie: &[u8], src: IpAddr) -> bool {
+    if server_cookie.len() != SERVER_COOKIE_LENGTH || server_cookie[0] != SERVER_COOKIE_VERSION {
+      return false;
+    }
+
+    let timestamp = u32::from_be_bytes([
+      server_cookie[4],
+      server_cookie[5],
+      server_cookie[6],
+      server_cookie[7],
+    ]);
+    let age = i64::from(now()) - i64::from(timestamp);
+    if !(-COOKIE_MAX_SKEW..=COOKIE_MAX_AGE).contains(&age) {
+      return false;
+    }
+
+    self.server_cookie(client_cookie, timestamp, src) == server_cookie
+  }
+
+  /// Builds a server cookie as laid out in RFC 9018 4, hashed with the
+  /// secret of the rotation period the timestamp falls in.
+  fn server_cookie(
+    &self,
+    client_cookie: &[u8],
+    timestamp: u32,
+    src: IpAddr,
+  ) -> [u8; SERVER_COOKIE_LENGTH] {
+    let mut cookie = [0; SERVER_COOKIE_LENGTH];
+    cookie[0] = SERVER_COOKIE_VERSION;
+    cookie[4..8].copy_from_slice(&timestamp.to_be_bytes());
+
+    let period = timestamp / self.config.cookie_secret_rotation;
+    let mut hasher = SipHasher24::new_with_key(&self.period_secret(period));
+    hasher.write(client_cookie);
+    hasher.write(&cookie[..8]);
+    match src {
+      IpAddr::V4(src) => hasher.write(&src.octets()),
+      IpAddr::V6(src) => hasher.write(&src.octets()),
+    }
+    cookie[8..].copy_from_slice(&hasher.finish().to_le_bytes());
+
+    cookie
+  }
+
+  fn period_secret(&self, period: u32) -> [u8; 16] {
+    let mut secret = [0; 16];
+    for (half, part) in secret.chunks_mut(8).zip(0u8..) {
+      let mut hasher = SipHasher24::new_with_key(&self.secret.to_le_bytes());
+      hasher.write(&period.to_be_bytes());
+      hasher.write(&[part]);
+      half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    secret
+  }
+}
+
+fn now() -> u32 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |now| now.as_secs() as u32)
+}
+
+/// Answers with a server cookie and truncates udp responses that do not fit
+/// the client's buffer.
+#[derive(Clone)]
+pub(crate) struct CookieResponseHandle<R> {
+  inner: R,
+  cookie: Option<EdnsOption>,
+  buffer_size: u16,
+  // none for transports without a size limit
+  max_size: Option<u16>,
+  // header and question of the request, needed to rebuild the response
+  question: Arc<Vec<u8>>,
+}
+
+#[async_trait]
+impl<R: ResponseHandler> ResponseHandler for CookieResponseHandle<R> {
+  async fn send_response<'a>(
+    &mut self,
+    mut response: MessageResponse<
+      '_,
+      'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+    >,
+  ) -> io::Result<ResponseInfo> {
+    if let Some(edns) = response.get_edns() {
+      let mut edns = edns.clone();
+      edns.set_max_payload(self.buffer_size);
+      if let Some(cookie) = &self.cookie {
+        edns.options_mut().insert(cookie.clone());
+      }
+      response.set_edns(edns);
+    }
+
+    let Some(max_size) = self.max_size else {
+      return self.inner.send_response(response).await;
+    };
+
+    // hickory sends whatever records fit, dropping the OPT record if it does
+    // not, so encode the response first to find out whether all of it fits
+    let header = *response.header();
+    let edns = response.get_edns().clone();
+    let mut buffer = Vec::with_capacity(512);
+    let info = {
+      let mut encoder = BinEncoder::new(&mut buffer);
+      encoder.set_max_size(max_size);
+      response
+        .destructive_emit(&mut encoder)
+        .map_err(io::Error::other)?
+    };
+
+    let request = MessageRequest::from_bytes(&self.question).map_err(io::Error::other)?;
+    let mut builder = MessageResponseBuilder::from_message_request(&request);
+    if let Some(edns) = edns {
+      builder.edns(edns);
+    }
+
+    if info.truncated() {
+      let mut header = header;
+      header.set_truncated(true);
+
+      return self
+        .inner
+        .send_response(builder.build_no_records(header))
+        .await;
+    }
+
+    let message = Message::from_vec(&buffer).map_err(io::Error::other)?;
+    self
+      .inner
+      .send_response(builder.build(
+        header,
+        message.answers(),
+        message.name_servers(),
+        iter::empty(),
+        message.additionals(),
+      ))
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CLIENT: &[u8] = b"\x01\x02\x03\x04\x05\x06\x07\x08";
+
+  fn cookies(secret: u128, rotation: u32) -> Arc<Cookies> {
+    let config = EdnsConfig {
+      buffer_size: 1232,
+      cookie_secret: Some(secret),
+      cookie_secret_rotation: rotation,
+      cookie_required_above: None,
+    };
+
+    Cookies::new(config, Arc::new(Metrics::new().unwrap()))
+  }
+
+  fn src() -> IpAddr {
+    "192.0.2.1".parse().unwrap()
+  }
+
+  fn issued(cookies: &Cookies, age: i64) -> [u8; SERVER_COOKIE_LENGTH] {
+    cookies.server_cookie(CLIENT, (i64::from(now()) - age) as u32, src())
+  }
+
+  #[test]
+  fn layout() {
+    let cookies = cookies(1, 3600);
+    let cookie = cookies.server_cookie(CLIENT, 0x01020304, src());
+
+    assert_eq!(cookie[0], SERVER_COOKIE_VERSION);
+    assert_eq!(cookie[1..4], [0, 0, 0]);
+    assert_eq!(cookie[4..8], [1, 2, 3, 4]);
+    assert_eq!(cookie, cookies.server_cookie(CLIENT, 0x01020304, src()));
+  }
+
+  #[test]
+  fn bound_to_client_and_secret() {
+    let cookies = cookies(1, 3600);
+    let cookie = cookies.server_cookie(CLIENT, 1000, src());
+
+    assert_ne!(
+      cookie,
+      cookies.server_cookie(b"\x00\x02\x03\x04\x05\x06\x07\x08", 1000, src())
+    );
+    assert_ne!(
+      cookie,
+      cookies.server_cookie(CLIENT, 1000, "192.0.2.2".parse().unwrap())
+    );
+    assert_ne!(
+      cookie,
+      cookies.server_cookie(CLIENT, 1000, "2001:db8::1".parse().unwrap())
+    );
+    assert_ne!(
+      cookie,
+      self::cookies(2, 3600).server_cookie(CLIENT, 1000, src())
+    );
+  }
+
+  #[test]
+  fn shared_secret() {
+    let cookie = issued(&cookies(7, 3600), 10);
+
+    assert!(cookies(7, 3600).verify(CLIENT, &cookie, src()));
+    assert!(!cookies(8, 3600).verify(CLIENT, &cookie, src()));
+  }
+
+  #[test]
+  fn rotation() {
+    let cookies = cookies(1, 10);
+
+    // timestamps of two periods are hashed with different secrets
+    assert_ne!(
+      cookies.server_cookie(CLIENT, 19, src())[8..],
+      cookies.server_cookie(CLIENT, 20, src())[8..]
+    );
+    // cookies of past periods are still accepted
+    assert!(cookies.verify(CLIENT, &issued(&cookies, 100), src()));
+  }
+
+  #[test]
+  fn timestamp_window() {
+    let cookies = cookies(1, 3600);
+
+    assert!(cookies.verify(CLIENT, &issued(&cookies, 0), src()));
+    assert!(cookies.verify(CLIENT, &issued(&cookies, COOKIE_MAX_AGE - 5), src()));
+    assert!(!cookies.verify(CLIENT, &issued(&cookies, COOKIE_MAX_AGE + 5), src()));
+    assert!(cookies.verify(CLIENT, &issued(&cookies, 5 - COOKIE_MAX_SKEW), src()));
+    assert!(!cookies.verify(CLIENT, &issued(&cookies, -COOKIE_MAX_SKEW - 5), src()));
+  }
+
+  #[test]
+  fn invalid() {
+    let cookies = cookies(1, 3600);
+    let cookie = issued(&cookies, 0);
+
+    assert!(!cookies.verify(b"\x00\x00\x00\x00\x00\x00\x00\x00", &cookie, src()));
+    assert!(!cookies.verify(CLIENT, &cookie, "192.0.2.2".parse().unwrap()));
+    assert!(!cookies.verify(CLIENT, &cookie[..8], src()));
+
+    let mut longer = cookie.to_vec();
+    longer.push(0);
+    assert!(!cookies.verify(CLIENT, &longer, src()));
+
+    let mut version = cookie;
+    version[0] = 2;
+    assert!(!cookies.verify(CLIENT, &version, src()));
+
+    // the timestamp is covered by the hash
+    let mut timestamp = cookie;
+    timestamp[7] ^= 1;
+    assert!(!cookies.verify(CLIENT, &timestamp, src()));
+
+    let mut hash = cookie;
+    hash[15] ^= 1;
+    assert!(!cookies.verify(CLIENT, &hash, src()));
+  }
+
+  #[test]
+  fn secrets() {
+    assert_eq!(
+      parse_secret("000102030405060708090a0b0c0d0e0f"),
+      Ok(0x000102030405060708090a0b0c0d0e0f)
+    );
+    assert!(parse_secret("0102").is_err());
+    assert!(parse_secret("x00102030405060708090a0b0c0d0e0f").is_err());
+  }
+}
@@ -0,0 +1,41 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, Reader};
+
+/// Location of a client, as far as records can be tagged with it.
+#[derive(Default)]
+pub(crate) struct Location {
+  pub(crate) country: Option<String>,
+  pub(crate) continent: Option<String>,
+}
+
+/// A local MaxMind-format database, both country and city databases work.
+pub(crate) struct GeoIp {
+  reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+  pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+    Ok(Self {
+      reader: Reader::open_readfile(path)?,
+    })
+  }
+
+  /// Addresses missing from the database (e.g. private ones) have no location.
+  pub(crate) fn locate(&self, addr: IpAddr) -> Location {
+    match self.reader.lookup::<geoip2::Country>(addr) {
+      Ok(country) => Location {
+        country: country
+          .country
+          .and_then(|country| country.iso_code)
+          .map(str::to_string),
+        continent: country
+          .continent
+          .and_then(|continent| continent.code)
+          .map(str::to_string),
+      },
+      Err(_) => Location::default(),
+    }
+  }
+}
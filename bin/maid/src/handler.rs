@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use hickory_server::authority::{Catalog, MessageResponseBuilder};
+use hickory_server::proto::op::{Header, OpCode, ResponseCode};
+use hickory_server::proto::rr::RecordType;
+use hickory_server::server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::acl::AccessControl;
+use crate::authority::{SecondaryAuthority, SecondaryZones};
+use crate::edns::Cookies;
+use crate::metrics::Metrics;
+use crate::rrl::{RateLimitedResponseHandle, RateLimiter};
+use crate::subnet::{Subnet, SubnetResponseHandle};
+
+/// Cheaply cloneable request handler, so every transport (udp, tcp, tls,
+/// https) answers from the very same `Catalog`.
+#[derive(Clone)]
+pub(crate) struct Handler {
+  catalog: Arc<RwLock<Catalog>>,
+  secondaries: Arc<SecondaryZones>,
+  metrics: Arc<Metrics>,
+  rate_limiter: Arc<RateLimiter>,
+  cookies: Arc<Cookies>,
+  acl: Arc<AccessControl>,
+}
+
+impl Handler {
+  pub(crate) fn new(
+    catalog: Arc<RwLock<Catalog>>,
+    secondaries: Arc<SecondaryZones>,
+    metrics: Arc<Metrics>,
+    rate_limiter: Arc<RateLimiter>,
+    cookies: Arc<Cookies>,
+    acl: Arc<AccessControl>,
+  ) -> Self {
+    Self {
+      catalog,
+      secondaries,
+      metrics,
+      rate_limiter,
+      cookies,
+      acl,
+    }
+  }
+}
+
+impl Handler {
+  /// Acknowledges a NOTIFY for a secondary zone, if it came from its primary,
+  /// and checks for a new serial right away.
+  async fn notify<R: ResponseHandler>(
+    &self,
+    request: &Request,
+    secondary: &SecondaryAuthority,
+    response_handle: R,
+  ) -> ResponseInfo {
+    let zone = request.query().name();
+
+    let response_code = if request.src().ip() == secondary.primary().ip() {
+      info!("Received NOTIFY for secondary zone {}", zone);
+      secondary.notify();
+      ResponseCode::NoError
+    } else {
+      info!(
+        "Refused NOTIFY for secondary zone {} from {}",
+        zone,
+        request.src()
+      );
+      ResponseCode::Refused
+    };
+
+    self.respond(request, response_code, response_handle).await
+  }
+
+  /// Answers without any records.
+  async fn respond<R: ResponseHandler>(
+    &self,
+    request: &Request,
+    response_code: ResponseCode,
+    mut response_handle: R,
+  ) -> ResponseInfo {
+    let mut header = Header::response_from_request(request.header());
+    header.set_authoritative(response_code != ResponseCode::ServFail);
+    header.set_response_code(response_code);
+
+    let response = MessageResponseBuilder::from_message_request(request).build_no_records(header);
+    match response_handle.send_response(response).await {
+      Ok(info) => info,
+      Err(err) => {
+        error!("Unable to send response: {}", err);
+        ResponseInfo::from(header)
+      }
+    }
+  }
+
+  /// Answers from the catalog, echoing the client subnet if there is one.
+  async fn answer<R: ResponseHandler>(
+    &self,
+    request: &Request,
+    response_handle: R,
+  ) -> ResponseInfo {
+    match Subnet::from_request(request) {
+      Some(subnet) => {
+        let response_handle = SubnetResponseHandle::new(response_handle);
+        subnet
+          .scope(async {
+            let catalog = self.catalog.read().await;
+            catalog.handle_request(request, response_handle).await
+          })
+          .await
+      }
+      None => {
+        let catalog = self.catalog.read().await;
+        catalog.handle_request(request, response_handle).await
+      }
+    }
+  }
+
+  /// Answers the request, or refuses it as early as possible.
+  async fn handle<R: ResponseHandler>(
+    &self,
+    request: &Request,
+    response_handle: R,
+  ) -> ResponseInfo {
+    let response_handle = match self.cookies.response_handle(request, response_handle) {
+      Ok(response_handle) => response_handle,
+      // see RFC 7873 5.2.2
+      Err(response_handle) => {
+        return self
+          .respond(request, ResponseCode::FormErr, response_handle)
+          .await;
+      }
+    };
+
+    // the catalog refuses NOTIFY, only secondary zones care about it
+    if request.op_code() == OpCode::Notify {
+      let zone = request.query().name();
+      let secondary = self.secondaries.get(zone).await;
+      // anyone may send NOTIFY, unknown zones must not grow the label set
+      let label = match secondary {
+        Some(_) => zone.to_string(),
+        None => "other".to_string(),
+      };
+      self.metrics.notifies.with_label_values(&[&label]).inc();
+
+      if let Some(secondary) = secondary {
+        return self.notify(request, &secondary, response_handle).await;
+      }
+    }
+
+    let allowed = match request.query().query_type() {
+      RecordType::AXFR | RecordType::IXFR => self.acl.allows_transfer(request.src().ip()),
+      _ => self.acl.allows_query(request.src().ip()),
+    };
+    if !allowed {
+      return self
+        .respond(request, ResponseCode::Refused, response_handle)
+        .await;
+    }
+
+    // the catalog would answer with an empty NOERROR instead
+    if let Some(secondary) = self.secondaries.find(request.query().name()).await {
+      if secondary.is_expired().await {
+        return self
+          .respond(request, ResponseCode::ServFail, response_handle)
+          .await;
+      }
+    }
+
+    // only udp can be spoofed and used for reflection
+    if matches!(request.protocol(), Protocol::Udp) && self.rate_limiter.is_enabled() {
+      let response_handle =
+        RateLimitedResponseHandle::new(response_handle, self.rate_limiter.clone(), request);
+      self.answer(request, response_handle).await
+    } else {
+      self.answer(request, response_handle).await
+    }
+  }
+}
+
+#[async_trait]
+impl RequestHandler for Handler {
+  async fn handle_request<R: ResponseHandler>(
+    &self,
+    request: &Request,
+    response_handle: R,
+  ) -> ResponseInfo {
+    let info = self.handle(request, response_handle).await;
+
+    // counted here, so refused, rate limited and failed requests show up too
+    let query = request.query();
+    let zone = match self.catalog.read().await.find(query.name()) {
+      Some(authority) => authority.origin().to_string(),
+      None => "other".to_string(),
+    };
+    let qtype = match query.query_type() {
+      RecordType::Unknown(_) => "other".to_string(),
+      qtype => qtype.to_string(),
+    };
+    self
+      .metrics
+      .queries
+      .with_label_values(&[
+        &zone,
+        &qtype,
+        &format!("{:?}", info.response_code()),
+        &request.protocol().to_string(),
+      ])
+      .inc();
+
+    info
+  }
+}
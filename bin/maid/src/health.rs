@@ -0,0 +1,238 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hickory_server::proto::rr::{Name, RData};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+  ColumnTrait, DatabaseConnection, EntityTrait, JoinType, QueryFilter, QuerySelect, RelationTrait,
+};
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::{interval, timeout};
+use tracing::{error, info};
+
+use entity::IntoRecord;
+use entity::{record, record_a, record_aaaa, record_cname, record_health_check, zone};
+
+use crate::metrics::Metrics;
+
+// how often due checks are looked for, every check has its own interval
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// consecutive failed probes before a record is left out of answers
+const FAILURE_THRESHOLD: i32 = 3;
+
+/// Probes the targets of health-checked records and stores whether they are
+/// healthy, so every instance can fail over to the remaining records.
+pub(crate) struct HealthChecker {
+  db: Arc<DatabaseConnection>,
+  metrics: Arc<Metrics>,
+}
+
+impl HealthChecker {
+  pub(crate) fn new(db: Arc<DatabaseConnection>, metrics: Arc<Metrics>) -> Arc<Self> {
+    Arc::new(Self { db, metrics })
+  }
+
+  pub(crate) async fn run(self: Arc<Self>) {
+    let mut interval = interval(POLL_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let checks = match self.due().await {
+        Ok(checks) => checks,
+        Err(err) => {
+          error!("Unable to fetch due health checks: {}", err);
+          continue;
+        }
+      };
+
+      let mut probes = JoinSet::new();
+      for (check, record) in checks {
+        let checker = self.clone();
+        probes.spawn(async move { checker.check(check, record).await });
+      }
+
+      while let Some(result) = probes.join_next().await {
+        if let Ok(Err(err)) = result {
+          error!("Unable to health check record: {}", err);
+        }
+      }
+    }
+  }
+
+  async fn due(&self) -> anyhow::Result<Vec<(record_health_check::Model, record::Model)>> {
+    Ok(
+      record_health_check::Entity::find()
+        .filter(Expr::cust(
+          "record_health_check.checked is null or record_health_check.checked + \
+           record_health_check.check_interval * interval '1 second' <= now()",
+        ))
+        .find_also_related(record::Entity)
+        .join(JoinType::InnerJoin, record::Relation::Zone.def())
+        .filter(zone::Column::Verified.eq(true))
+        .filter(zone::Column::Deleted.is_null())
+        .all(self.db.as_ref())
+        .await?
+        .into_iter()
+        .filter_map(|(check, record)| record.map(|record| (check, record)))
+        .collect(),
+    )
+  }
+
+  async fn check(
+    &self,
+    check: record_health_check::Model,
+    record: record::Model,
+  ) -> anyhow::Result<()> {
+    let (host, addrs) = self.target(&record, check.port as u16).await?;
+
+    let mut success = false;
+    for addr in addrs {
+      if probe(&check, &host, addr).await {
+        success = true;
+        break;
+      }
+    }
+
+    self
+      .metrics
+      .health_checks
+      .with_label_values(&[
+        check.protocol.as_str(),
+        if success { "success" } else { "failure" },
+      ])
+      .inc();
+
+    // concurrent instances may probe the same record, the failures are
+    // counted by the database so none of them get lost
+    let update = record_health_check::Entity::update_many()
+      .filter(record_health_check::Column::Id.eq(check.id))
+      .col_expr(
+        record_health_check::Column::Checked,
+        Expr::value(OffsetDateTime::now_utc()),
+      );
+    let update = if success {
+      update
+        .col_expr(record_health_check::Column::Failures, Expr::value(0))
+        .col_expr(record_health_check::Column::Healthy, Expr::value(true))
+    } else {
+      update
+        .col_expr(
+          record_health_check::Column::Failures,
+          Expr::col(record_health_check::Column::Failures).add(1),
+        )
+        .col_expr(
+          record_health_check::Column::Healthy,
+          Expr::col(record_health_check::Column::Healthy)
+            .eq(true)
+            // the failure counted right now included
+            .and(Expr::col(record_health_check::Column::Failures).lt(FAILURE_THRESHOLD - 1)),
+        )
+    };
+
+    let Some(updated) = update
+      .exec_with_returning(self.db.as_ref())
+      .await?
+      .into_iter()
+      .next()
+    else {
+      // the record was deleted in the meantime
+      return Ok(());
+    };
+
+    if updated.healthy != check.healthy {
+      if updated.healthy {
+        info!("Record {} ({}) is healthy again", record.id, host);
+      } else {
+        info!(
+          "Record {} ({}) failed {} health checks, leaving it out",
+          record.id, host, updated.failures
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Resolves the name clients would ask for and the addresses they would
+  /// end up connecting to.
+  async fn target(
+    &self,
+    record: &record::Model,
+    port: u16,
+  ) -> anyhow::Result<(Name, Vec<SocketAddr>)> {
+    let db = self.db.as_ref();
+
+    let zone = zone::Entity::find_by_id(record.zone_id)
+      .one(db)
+      .await?
+      .ok_or_else(|| anyhow!("zone of record {} not found", record.id))?;
+
+    let mut origin = Name::from_ascii(zone.name)?;
+    origin.set_fqdn(true);
+
+    let name = if record.name == "@" {
+      origin.clone()
+    } else {
+      Name::from_ascii(&record.name)?.append_domain(&origin)?
+    };
+
+    let rdata = if let Some(model) = record_a::Entity::find_by_id(record.id).one(db).await? {
+      model.into_record(&origin)?
+    } else if let Some(model) = record_aaaa::Entity::find_by_id(record.id).one(db).await? {
+      model.into_record(&origin)?
+    } else if let Some(model) = record_cname::Entity::find_by_id(record.id).one(db).await? {
+      model.into_record(&origin)?
+    } else {
+      return Err(anyhow!("record {} has no address to check", record.id));
+    };
+
+    let addrs = match rdata {
+      RData::A(a) => vec![SocketAddr::new(a.0.into(), port)],
+      RData::AAAA(aaaa) => vec![SocketAddr::new(aaaa.0.into(), port)],
+      RData::CNAME(cname) => lookup_host((cname.0.to_ascii(), port)).await?.collect(),
+      _ => Vec::new(),
+    };
+
+    Ok((name, addrs))
+  }
+}
+
+/// Connects to the target and, for http checks, expects a 2xx or 3xx status.
+async fn probe(check: &record_health_check::Model, host: &Name, addr: SocketAddr) -> bool {
+  let probe = async {
+    let mut stream = TcpStream::connect(addr).await?;
+
+    if check.protocol != "http" {
+      return Ok::<bool, io::Error>(true);
+    }
+
+    let request = format!(
+      "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: maid\r\nConnection: close\r\n\r\n",
+      check.path.as_deref().unwrap_or("/"),
+      host.to_ascii().trim_end_matches('.'),
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream.take(1024))
+      .read_line(&mut status_line)
+      .await?;
+
+    let status = status_line
+      .strip_prefix("HTTP/")
+      .and_then(|line| line.split(' ').nth(1))
+      .and_then(|status| status.parse::<u16>().ok());
+
+    Ok(matches!(status, Some(200..=399)))
+  };
+
+  matches!(timeout(PROBE_TIMEOUT, probe).await, Ok(Ok(true)))
+}
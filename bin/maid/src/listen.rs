@@ -0,0 +1,59 @@
+use std::env;
+use std::net::{TcpListener, UdpSocket};
+use std::os::fd::{FromRawFd, RawFd};
+
+use anyhow::anyhow;
+use socket2::{Socket, Type};
+
+// first file descriptor passed by systemd, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A socket handed to us by the service manager. Stream sockets are routed
+/// by their `FileDescriptorName=`, everything else is plain dns.
+pub(crate) enum InheritedSocket {
+  Udp(UdpSocket),
+  Tcp(TcpListener),
+  Tls(TcpListener),
+  Doh(TcpListener),
+}
+
+/// Takes over the sockets passed via `LISTEN_FDS`, if they are meant for us.
+pub(crate) fn systemd_sockets() -> anyhow::Result<Vec<InheritedSocket>> {
+  let pid = match env::var("LISTEN_PID") {
+    Ok(pid) => pid.parse::<u32>()?,
+    Err(_) => return Ok(Vec::new()),
+  };
+
+  if pid != std::process::id() {
+    return Ok(Vec::new());
+  }
+
+  let count = env::var("LISTEN_FDS")?.parse::<RawFd>()?;
+  let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+  let mut names = names.split(':');
+
+  // make sure child processes do not try to take over our sockets
+  env::remove_var("LISTEN_PID");
+  env::remove_var("LISTEN_FDS");
+  env::remove_var("LISTEN_FDNAMES");
+
+  let mut sockets = Vec::with_capacity(count as usize);
+
+  for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+    // SAFETY: systemd passes ownership of these descriptors to us
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    socket.set_nonblocking(true)?;
+
+    let socket = match (socket.r#type()?, names.next().unwrap_or_default()) {
+      (Type::DGRAM, _) => InheritedSocket::Udp(socket.into()),
+      (Type::STREAM, "dot") => InheritedSocket::Tls(socket.into()),
+      (Type::STREAM, "doh") => InheritedSocket::Doh(socket.into()),
+      (Type::STREAM, _) => InheritedSocket::Tcp(socket.into()),
+      (ty, name) => return Err(anyhow!("unsupported socket {} of type {:?}", name, ty)),
+    };
+
+    sockets.push(socket);
+  }
+
+  Ok(sockets)
+}
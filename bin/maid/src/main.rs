@@ -1,37 +1,69 @@
-use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::anyhow;
 use clap::Parser;
 use hickory_server::authority::Catalog;
-use hickory_server::proto::rr::LowerName;
 use hickory_server::ServerFuture;
-use sea_orm::prelude::Uuid;
 use sea_orm::{ConnectOptions, Database};
 use tokio::net::{TcpListener, UdpSocket};
 use tokio::select;
 use tokio::signal::ctrl_c;
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
 
 use migration::{Migrator, MigratorTrait};
 
+use crate::acl::AccessControl;
 use crate::args::MaidArgs;
-use crate::authority::ZoneAuthority;
-use crate::service::ZoneService;
+use crate::authority::{CatalogZoneAuthority, PrimaryZones, SecondaryZones};
+use crate::config::{ConfigFile, Reloader};
+use crate::dnstap::{Dnstap, DnstapOutput};
+use crate::edns::Cookies;
+use crate::geo::GeoIp;
+use crate::handler::Handler;
+use crate::health::HealthChecker;
+use crate::listen::{systemd_sockets, InheritedSocket};
+use crate::metrics::Metrics;
+use crate::rrl::RateLimiter;
+use crate::service::{CatalogZoneService, SecondaryService, ZoneService};
+use crate::tls::CertificateStore;
 
+mod acl;
 mod args;
 mod authority;
+mod config;
+mod dnstap;
+mod doh;
+mod edns;
+mod geo;
+mod handler;
+mod health;
+mod listen;
+mod metrics;
+mod rrl;
 mod service;
+mod subnet;
+mod tls;
+
+const DEFAULT_LISTEN_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
+const TCP_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-  let args = MaidArgs::parse();
+  let flags = MaidArgs::parse();
+  let mut args = flags.clone();
+  if let Some(path) = &flags.config {
+    ConfigFile::load(path).await?.apply(&mut args)?;
+  }
 
-  let subscriber = FmtSubscriber::builder()
-    .with_max_level(Level::INFO)
-    .compact()
-    .finish();
+  let (log_level, log_level_handle) = reload::Layer::new(args.log_level);
+  let subscriber = tracing_subscriber::registry()
+    .with(log_level)
+    .with(tracing_subscriber::fmt::layer().compact());
 
   tracing::subscriber::set_global_default(subscriber)?;
 
@@ -43,7 +75,11 @@ async fn main() -> anyhow::Result<()> {
     "..."
   ));
 
-  let mut db_options = ConnectOptions::new(args.database_url);
+  let database_url = args
+    .database_url
+    .clone()
+    .ok_or_else(|| anyhow!("no database url configured"))?;
+  let mut db_options = ConnectOptions::new(database_url);
   db_options
     .max_connections(100)
     .min_connections(5)
@@ -56,29 +92,199 @@ async fn main() -> anyhow::Result<()> {
   let db = Arc::new(Database::connect(db_options).await?);
   Migrator::up(db.as_ref(), None).await?;
 
-  let zone_service = Arc::new(ZoneService::new(db));
-
-  let mut catalog = Catalog::new();
-  let name = LowerName::from_str("dresden.zone.")?;
-  catalog.upsert(
-    name.clone(),
-    Box::new(Arc::new(ZoneAuthority::new(
-      zone_service,
-      Uuid::from_str("2067530c-c9b0-4105-8f59-692593a1095d")?,
-      name,
-    ))),
-    // Box::new(Arc::new(FileAuthority::try_from_config(Name::from(name) ,ZoneType::Primary, false, None, &FileConfig {zone_file_path: "dresden.zone.db".to_string()}).unwrap())),
+  let metrics = Arc::new(Metrics::new()?);
+  let zone_service = Arc::new(ZoneService::new(
+    db.clone(),
+    metrics.clone(),
+    args.cross_zone_cnames,
+    args.zone_defaults.clone(),
+  ));
+  let secondary_service = Arc::new(SecondaryService::new(db.clone(), metrics.clone()));
+  let catalog_zone_service = Arc::new(CatalogZoneService::new(db.clone(), metrics.clone()));
+
+  // secondary zones come and go while running
+  let catalog = Arc::new(RwLock::new(Catalog::new()));
+  let secondaries = SecondaryZones::new(secondary_service, metrics.clone(), catalog.clone());
+
+  let rate_limiter = RateLimiter::new(args.rate_limit.clone(), metrics.clone());
+  tokio::spawn(rate_limiter.clone().clean());
+
+  let cookies = Cookies::new(args.edns.clone(), metrics.clone());
+  let acl = AccessControl::new(args.acl.clone());
+
+  #[cfg(unix)]
+  if let Some(path) = flags.config.clone() {
+    let reloader = Reloader::new(
+      path,
+      flags,
+      args.clone(),
+      log_level_handle,
+      acl.clone(),
+      rate_limiter.clone(),
+      zone_service.clone(),
+    );
+    tokio::spawn(reloader.run());
+  }
+
+  if args.health_checks {
+    tokio::spawn(HealthChecker::new(db, metrics.clone()).run());
+  }
+
+  let dnstap_output = match (args.dnstap_socket, args.dnstap_file) {
+    (Some(path), _) => Some(DnstapOutput::Socket(path)),
+    (None, Some(path)) => Some(DnstapOutput::File(path)),
+    (None, None) => None,
+  };
+  let dnstap = dnstap_output.map(|output| {
+    Dnstap::new(
+      output,
+      args.dnstap_format,
+      args.dnstap_sample_rate,
+      args.dnstap_zone,
+      metrics.clone(),
+    )
+  });
+
+  let geo_ip = match &args.geoip_database {
+    Some(path) => Some(Arc::new(GeoIp::open(path)?)),
+    None => None,
+  };
+
+  let primaries = PrimaryZones::new(
+    zone_service,
+    catalog_zone_service.clone(),
+    metrics.clone(),
+    dnstap,
+    geo_ip,
+    args.any_response,
+    catalog.clone(),
   );
 
-  let mut server = ServerFuture::new(catalog);
-  server.register_socket(UdpSocket::bind(args.listen_addr).await?);
-  server.register_listener(
-    TcpListener::bind(args.listen_addr).await?,
-    Duration::from_secs(30),
+  if let Some(name) = args.catalog_zone {
+    let authority = CatalogZoneAuthority::new(catalog_zone_service, metrics.clone(), name.clone());
+
+    if !args.catalog_zone_notify.is_empty() {
+      tokio::spawn(authority.clone().notify(args.catalog_zone_notify));
+    }
+    catalog.write().await.upsert(name, Box::new(authority));
+  }
+
+  primaries.reload().await?;
+  tokio::spawn(primaries.run());
+  secondaries.reload().await?;
+  tokio::spawn(secondaries.clone().run());
+
+  let handler = Handler::new(
+    catalog,
+    secondaries,
+    metrics.clone(),
+    rate_limiter,
+    cookies,
+    acl,
   );
 
-  info!("Listening on udp://{}...", args.listen_addr);
-  info!("Listening on tcp://{}...", args.listen_addr);
+  if let Some(addr) = args.metrics_listen_addr {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on http://{}/metrics...", listener.local_addr()?);
+    let router = metrics.router();
+    tokio::spawn(async move {
+      if let Err(err) = axum::serve(listener, router).await {
+        error!("Metrics listener failed: {}", err);
+      }
+    });
+  }
+
+  let certificates = match (args.tls_cert_path, args.tls_key_path) {
+    (Some(cert_path), Some(key_path)) => {
+      let certificates = CertificateStore::load(cert_path, key_path).await?;
+      tokio::spawn(certificates.clone().watch());
+      Some(certificates)
+    }
+    _ => None,
+  };
+
+  let mut udp_sockets = Vec::new();
+  let mut tcp_listeners = Vec::new();
+  let mut tls_listeners = Vec::new();
+  let mut doh_listeners = Vec::new();
+
+  for socket in systemd_sockets()? {
+    match socket {
+      InheritedSocket::Udp(socket) => udp_sockets.push(UdpSocket::from_std(socket)?),
+      InheritedSocket::Tcp(listener) => tcp_listeners.push(TcpListener::from_std(listener)?),
+      InheritedSocket::Tls(listener) => tls_listeners.push(TcpListener::from_std(listener)?),
+      InheritedSocket::Doh(listener) => doh_listeners.push(TcpListener::from_std(listener)?),
+    }
+  }
+
+  let mut udp_listen_addrs = args.udp_listen_addr;
+  let mut tcp_listen_addrs = args.tcp_listen_addr;
+  udp_listen_addrs.extend(&args.listen_addr);
+  tcp_listen_addrs.extend(&args.listen_addr);
+
+  // neither configured nor socket activated, fall back to the default address
+  if udp_listen_addrs.is_empty()
+    && tcp_listen_addrs.is_empty()
+    && udp_sockets.is_empty()
+    && tcp_listeners.is_empty()
+  {
+    udp_listen_addrs.push(DEFAULT_LISTEN_ADDR);
+    tcp_listen_addrs.push(DEFAULT_LISTEN_ADDR);
+  }
+
+  for addr in udp_listen_addrs {
+    udp_sockets.push(UdpSocket::bind(addr).await?);
+  }
+  for addr in tcp_listen_addrs {
+    tcp_listeners.push(TcpListener::bind(addr).await?);
+  }
+  for addr in args.tls_listen_addr {
+    tls_listeners.push(TcpListener::bind(addr).await?);
+  }
+  for addr in args.doh_listen_addr {
+    doh_listeners.push(TcpListener::bind(addr).await?);
+  }
+
+  let mut server = ServerFuture::new(handler.clone());
+
+  for socket in udp_sockets {
+    info!("Listening on udp://{}...", socket.local_addr()?);
+    server.register_socket(socket);
+  }
+
+  for listener in tcp_listeners {
+    info!("Listening on tcp://{}...", listener.local_addr()?);
+    server.register_listener(listener, TCP_TIMEOUT);
+  }
+
+  if !tls_listeners.is_empty() {
+    let certificates = certificates
+      .as_ref()
+      .ok_or_else(|| anyhow!("dns-over-tls requires a certificate and key"))?;
+
+    for listener in tls_listeners {
+      info!("Listening on tls://{}...", listener.local_addr()?);
+      server.register_tls_listener_with_tls_config(
+        listener,
+        TCP_TIMEOUT,
+        certificates.server_config(&[b"dot"]),
+      )?;
+    }
+  }
+
+  for listener in doh_listeners {
+    let tls = certificates
+      .as_ref()
+      .map(|certificates| certificates.server_config(&[b"h2", b"http/1.1"]));
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    info!(
+      "Listening on {}://{}/dns-query...",
+      scheme,
+      listener.local_addr()?
+    );
+    tokio::spawn(doh::serve(listener, doh::router(handler.clone()), tls));
+  }
 
   select! {
    result = server.block_until_done() => {
@@ -0,0 +1,159 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+  HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+  DEFAULT_BUCKETS,
+};
+use tracing::error;
+
+// dns lookups are expected to finish well below the default buckets
+const LOOKUP_BUCKETS: &[f64] = &[
+  0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+pub(crate) struct Metrics {
+  registry: Registry,
+  pub(crate) queries: IntCounterVec,
+  pub(crate) lookup_duration: HistogramVec,
+  pub(crate) database_duration: HistogramVec,
+  pub(crate) database_errors: IntCounterVec,
+  pub(crate) zone_transfers: IntCounterVec,
+  pub(crate) notifies: IntCounterVec,
+  pub(crate) dnstap_dropped: IntCounter,
+  pub(crate) rate_limited: IntCounterVec,
+  pub(crate) health_checks: IntCounterVec,
+  pub(crate) secondary_refreshes: IntCounterVec,
+  pub(crate) cookies: IntCounterVec,
+}
+
+impl Metrics {
+  pub(crate) fn new() -> anyhow::Result<Self> {
+    let registry = Registry::new_custom(Some("maid".to_string()), None)?;
+
+    let queries = IntCounterVec::new(
+      Opts::new(
+        "queries_total",
+        "Responses to queries, including refused and rate limited ones",
+      ),
+      &["zone", "qtype", "rcode", "transport"],
+    )?;
+    let lookup_duration = HistogramVec::new(
+      HistogramOpts::new("lookup_duration_seconds", "Time spent answering a query")
+        .buckets(LOOKUP_BUCKETS.to_vec()),
+      &["zone", "qtype"],
+    )?;
+    let database_duration = HistogramVec::new(
+      HistogramOpts::new(
+        "database_query_duration_seconds",
+        "Time spent waiting for the database",
+      )
+      .buckets(DEFAULT_BUCKETS.to_vec()),
+      &["query"],
+    )?;
+    let database_errors = IntCounterVec::new(
+      Opts::new("database_errors_total", "Failed database queries"),
+      &["query"],
+    )?;
+    let zone_transfers = IntCounterVec::new(
+      Opts::new("zone_transfers_total", "Outgoing zone transfers"),
+      &["zone", "qtype"],
+    )?;
+    let notifies = IntCounterVec::new(
+      Opts::new("notifies_total", "Received NOTIFY messages"),
+      &["zone"],
+    )?;
+    let dnstap_dropped = IntCounter::new(
+      "dnstap_dropped_total",
+      "Query log frames dropped because the writer fell behind",
+    )?;
+    let rate_limited = IntCounterVec::new(
+      Opts::new(
+        "rate_limited_responses_total",
+        "Responses dropped or truncated by rate limiting",
+      ),
+      &["class", "action"],
+    )?;
+    let health_checks = IntCounterVec::new(
+      Opts::new("health_checks_total", "Probes of health-checked records"),
+      &["protocol", "result"],
+    )?;
+    let secondary_refreshes = IntCounterVec::new(
+      Opts::new(
+        "secondary_refreshes_total",
+        "Serial checks of secondary zones against their primary",
+      ),
+      &["zone", "result"],
+    )?;
+    let cookies = IntCounterVec::new(
+      Opts::new("cookies_total", "Requests by the DNS cookie they carried"),
+      &["result"],
+    )?;
+
+    registry.register(Box::new(queries.clone()))?;
+    registry.register(Box::new(lookup_duration.clone()))?;
+    registry.register(Box::new(database_duration.clone()))?;
+    registry.register(Box::new(database_errors.clone()))?;
+    registry.register(Box::new(zone_transfers.clone()))?;
+    registry.register(Box::new(notifies.clone()))?;
+    registry.register(Box::new(dnstap_dropped.clone()))?;
+    registry.register(Box::new(rate_limited.clone()))?;
+    registry.register(Box::new(health_checks.clone()))?;
+    registry.register(Box::new(secondary_refreshes.clone()))?;
+    registry.register(Box::new(cookies.clone()))?;
+
+    Ok(Self {
+      registry,
+      queries,
+      lookup_duration,
+      database_duration,
+      database_errors,
+      zone_transfers,
+      notifies,
+      dnstap_dropped,
+      rate_limited,
+      health_checks,
+      secondary_refreshes,
+      cookies,
+    })
+  }
+
+  /// Tracks duration and failure of a database query.
+  pub(crate) async fn observe_database<T>(
+    &self,
+    query: &str,
+    future: impl Future<Output = anyhow::Result<T>>,
+  ) -> anyhow::Result<T> {
+    let timer = self
+      .database_duration
+      .with_label_values(&[query])
+      .start_timer();
+    let result = future.await;
+    timer.observe_duration();
+
+    if result.is_err() {
+      self.database_errors.with_label_values(&[query]).inc();
+    }
+
+    result
+  }
+
+  pub(crate) fn router(self: Arc<Self>) -> Router {
+    Router::new()
+      .route("/metrics", get(metrics))
+      .with_state(self)
+  }
+}
+
+async fn metrics(State(metrics): State<Arc<Metrics>>) -> Result<String, StatusCode> {
+  TextEncoder::new()
+    .encode_to_string(&metrics.registry.gather())
+    .map_err(|err| {
+      error!("Unable to encode metrics: {}", err);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
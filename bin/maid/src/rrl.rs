@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use clap::Args;
+use hickory_server::authority::{MessageRequest, MessageResponse, MessageResponseBuilder};
+use hickory_server::proto::op::ResponseCode;
+use hickory_server::proto::rr::Record;
+use hickory_server::proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+
+use crate::metrics::Metrics;
+
+const CLEAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// BIND-style response rate limiting, see
+/// https://kb.isc.org/docs/aa-01000
+#[derive(Args, Clone, PartialEq)]
+pub(crate) struct RateLimitConfig {
+  /// Answers per second and client prefix, 0 disables rate limiting
+  #[arg(
+    long = "rrl-responses-per-second",
+    env = "MAID_RRL_RESPONSES_PER_SECOND",
+    default_value_t = 0
+  )]
+  pub(crate) responses_per_second: u32,
+  /// NXDOMAIN responses per second and client prefix, defaults to the
+  /// answer limit
+  #[arg(
+    long = "rrl-nxdomains-per-second",
+    env = "MAID_RRL_NXDOMAINS_PER_SECOND"
+  )]
+  pub(crate) nxdomains_per_second: Option<u32>,
+  /// Error responses per second and client prefix, defaults to the answer
+  /// limit
+  #[arg(long = "rrl-errors-per-second", env = "MAID_RRL_ERRORS_PER_SECOND")]
+  pub(crate) errors_per_second: Option<u32>,
+  /// Seconds a client keeps being limited after it stopped exceeding the limit
+  #[arg(long = "rrl-window", env = "MAID_RRL_WINDOW", default_value_t = 15)]
+  pub(crate) window: u32,
+  /// Every n-th limited response is sent truncated instead of dropped, so
+  /// legitimate clients retry over tcp; 0 always drops
+  #[arg(long = "rrl-slip", env = "MAID_RRL_SLIP", default_value_t = 2)]
+  pub(crate) slip: u32,
+  #[arg(
+    long = "rrl-ipv4-prefix-length",
+    env = "MAID_RRL_IPV4_PREFIX_LENGTH",
+    default_value_t = 24
+  )]
+  pub(crate) ipv4_prefix_length: u8,
+  #[arg(
+    long = "rrl-ipv6-prefix-length",
+    env = "MAID_RRL_IPV6_PREFIX_LENGTH",
+    default_value_t = 56
+  )]
+  pub(crate) ipv6_prefix_length: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ResponseClass {
+  Answer,
+  NxDomain,
+  Error,
+}
+
+impl ResponseClass {
+  fn as_str(self) -> &'static str {
+    match self {
+      Self::Answer => "answer",
+      Self::NxDomain => "nxdomain",
+      Self::Error => "error",
+    }
+  }
+}
+
+impl From<ResponseCode> for ResponseClass {
+  fn from(code: ResponseCode) -> Self {
+    match code {
+      ResponseCode::NoError => Self::Answer,
+      ResponseCode::NXDomain => Self::NxDomain,
+      _ => Self::Error,
+    }
+  }
+}
+
+enum Verdict {
+  Send,
+  Drop,
+  Slip,
+}
+
+/// Token bucket of a single client prefix and response class. The balance
+/// may go negative, which keeps a client limited for up to `window` seconds.
+struct Account {
+  balance: f64,
+  updated: Instant,
+  limited: u32,
+}
+
+pub(crate) struct RateLimiter {
+  config: RwLock<RateLimitConfig>,
+  accounts: Mutex<HashMap<(IpAddr, ResponseClass), Account>>,
+  metrics: Arc<Metrics>,
+}
+
+impl RateLimiter {
+  pub(crate) fn new(config: RateLimitConfig, metrics: Arc<Metrics>) -> Arc<Self> {
+    Arc::new(Self {
+      config: RwLock::new(config),
+      accounts: Mutex::new(HashMap::new()),
+      metrics,
+    })
+  }
+
+  /// Whether any limit is configured at all.
+  pub(crate) fn is_enabled(&self) -> bool {
+    let config = self.config.read().unwrap();
+    config.responses_per_second != 0
+      || config.nxdomains_per_second.unwrap_or_default() != 0
+      || config.errors_per_second.unwrap_or_default() != 0
+  }
+
+  /// Applies new limits, every client starts over with a full balance.
+  pub(crate) fn set_config(&self, config: RateLimitConfig) {
+    *self.config.write().unwrap() = config;
+    self.accounts.lock().unwrap().clear();
+  }
+
+  /// Forgets about clients which have been quiet for longer than the window.
+  pub(crate) async fn clean(self: Arc<Self>) {
+    let mut interval = tokio::time::interval(CLEAN_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let window = Duration::from_secs(self.config.read().unwrap().window as u64);
+      self
+        .accounts
+        .lock()
+        .unwrap()
+        .retain(|_, account| account.updated.elapsed() < window);
+    }
+  }
+
+  fn rate(config: &RateLimitConfig, class: ResponseClass) -> u32 {
+    let rate = match class {
+      ResponseClass::Answer => None,
+      ResponseClass::NxDomain => config.nxdomains_per_second,
+      ResponseClass::Error => config.errors_per_second,
+    };
+
+    rate.unwrap_or(config.responses_per_second)
+  }
+
+  fn prefix(config: &RateLimitConfig, addr: IpAddr) -> IpAddr {
+    match addr {
+      IpAddr::V4(addr) => {
+        let length = config.ipv4_prefix_length.min(32) as u32;
+        let mask = u32::MAX.checked_shl(32 - length).unwrap_or_default();
+        IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+      }
+      IpAddr::V6(addr) => {
+        let length = config.ipv6_prefix_length.min(128) as u32;
+        let mask = u128::MAX.checked_shl(128 - length).unwrap_or_default();
+        IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+      }
+    }
+  }
+
+  fn check(&self, addr: IpAddr, class: ResponseClass) -> Verdict {
+    let config = self.config.read().unwrap();
+    let rate = Self::rate(&config, class);
+    if rate == 0 {
+      return Verdict::Send;
+    }
+
+    let rate = rate as f64;
+    let now = Instant::now();
+    let mut accounts = self.accounts.lock().unwrap();
+    let account = accounts
+      .entry((Self::prefix(&config, addr), class))
+      .or_insert(Account {
+        balance: rate,
+        updated: now,
+        limited: 0,
+      });
+
+    let elapsed = now.duration_since(account.updated).as_secs_f64();
+    account.balance = (account.balance + elapsed * rate).min(rate) - 1.0;
+    account.balance = account.balance.max(-rate * config.window as f64);
+    account.updated = now;
+
+    if account.balance >= 0.0 {
+      account.limited = 0;
+      return Verdict::Send;
+    }
+
+    account.limited = account.limited.wrapping_add(1);
+    let verdict = if config.slip != 0 && account.limited.is_multiple_of(config.slip) {
+      Verdict::Slip
+    } else {
+      Verdict::Drop
+    };
+    drop(accounts);
+    drop(config);
+
+    let action = match verdict {
+      Verdict::Slip => "slip",
+      _ => "drop",
+    };
+    self
+      .metrics
+      .rate_limited
+      .with_label_values(&[class.as_str(), action])
+      .inc();
+
+    verdict
+  }
+}
+
+/// Drops or truncates responses of clients exceeding their rate limit.
+#[derive(Clone)]
+pub(crate) struct RateLimitedResponseHandle<R> {
+  inner: R,
+  limiter: Arc<RateLimiter>,
+  src: IpAddr,
+  // header and question of the request, needed to build truncated responses
+  question: Arc<Vec<u8>>,
+}
+
+impl<R> RateLimitedResponseHandle<R> {
+  pub(crate) fn new(inner: R, limiter: Arc<RateLimiter>, request: &Request) -> Self {
+    Self {
+      inner,
+      limiter,
+      src: request.src().ip(),
+      question: encode_question(request),
+    }
+  }
+}
+
+/// Encodes header and question of a request, so responses to it can be
+/// built from scratch after the request itself is gone.
+pub(crate) fn encode_question(request: &Request) -> Arc<Vec<u8>> {
+  let mut header = *request.header();
+  header.set_query_count(1);
+  header.set_answer_count(0);
+  header.set_name_server_count(0);
+  header.set_additional_count(0);
+
+  let mut question = Vec::with_capacity(64);
+  {
+    let mut encoder = BinEncoder::new(&mut question);
+    // writing to a vec does not fail
+    let _ = header.emit(&mut encoder);
+    let _ = request.query().original().emit(&mut encoder);
+  }
+
+  Arc::new(question)
+}
+
+#[async_trait]
+impl<R: ResponseHandler> ResponseHandler for RateLimitedResponseHandle<R> {
+  async fn send_response<'a>(
+    &mut self,
+    response: MessageResponse<
+      '_,
+      'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+    >,
+  ) -> io::Result<ResponseInfo> {
+    let header = *response.header();
+
+    match self.limiter.check(self.src, header.response_code().into()) {
+      Verdict::Send => self.inner.send_response(response).await,
+      Verdict::Drop => Ok(header.into()),
+      Verdict::Slip => {
+        let request = MessageRequest::from_bytes(&self.question).map_err(io::Error::other)?;
+
+        let mut header = header;
+        header.set_truncated(true);
+
+        let mut builder = MessageResponseBuilder::from_message_request(&request);
+        if let Some(edns) = response.get_edns() {
+          builder.edns(edns.clone());
+        }
+
+        self
+          .inner
+          .send_response(builder.build_no_records(header))
+          .await
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limiter(responses_per_second: u32, slip: u32) -> Arc<RateLimiter> {
+    let config = RateLimitConfig {
+      responses_per_second,
+      nxdomains_per_second: None,
+      errors_per_second: None,
+      window: 2,
+      slip,
+      ipv4_prefix_length: 24,
+      ipv6_prefix_length: 56,
+    };
+
+    RateLimiter::new(config, Arc::new(Metrics::new().unwrap()))
+  }
+
+  fn verdicts(limiter: &RateLimiter, addr: &str, class: ResponseClass, n: usize) -> String {
+    let addr = addr.parse().unwrap();
+    (0..n)
+      .map(|_| match limiter.check(addr, class) {
+        Verdict::Send => 's',
+        Verdict::Drop => 'd',
+        Verdict::Slip => 't',
+      })
+      .collect()
+  }
+
+  // moves the last update of every account back, as if `secs` had passed
+  fn wait(limiter: &RateLimiter, secs: u64) {
+    for account in limiter.accounts.lock().unwrap().values_mut() {
+      account.updated -= Duration::from_secs(secs);
+    }
+  }
+
+  #[test]
+  fn disabled() {
+    let limiter = limiter(0, 2);
+
+    assert!(!limiter.is_enabled());
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 100),
+      "s".repeat(100)
+    );
+  }
+
+  #[test]
+  fn burst_then_slip() {
+    let limiter = limiter(3, 2);
+
+    assert!(limiter.is_enabled());
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 7),
+      "sssdtdt"
+    );
+  }
+
+  #[test]
+  fn slip_zero_always_drops() {
+    let limiter = limiter(1, 0);
+
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 5),
+      "sdddd"
+    );
+  }
+
+  #[test]
+  fn buckets_per_prefix_and_class() {
+    let limiter = limiter(1, 0);
+
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 2),
+      "sd"
+    );
+    // same /24
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.200", ResponseClass::Answer, 1),
+      "d"
+    );
+    assert_eq!(
+      verdicts(&limiter, "192.0.3.1", ResponseClass::Answer, 1),
+      "s"
+    );
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::NxDomain, 1),
+      "s"
+    );
+    // same /56
+    assert_eq!(
+      verdicts(&limiter, "2001:db8:0:1::1", ResponseClass::Answer, 1),
+      "s"
+    );
+    assert_eq!(
+      verdicts(&limiter, "2001:db8:0:ff::2", ResponseClass::Answer, 1),
+      "d"
+    );
+    assert_eq!(
+      verdicts(&limiter, "2001:db8:0:100::1", ResponseClass::Answer, 1),
+      "s"
+    );
+  }
+
+  #[test]
+  fn class_rates() {
+    let mut config = limiter(2, 0).config.read().unwrap().clone();
+    config.errors_per_second = Some(5);
+
+    assert_eq!(RateLimiter::rate(&config, ResponseClass::Answer), 2);
+    assert_eq!(RateLimiter::rate(&config, ResponseClass::NxDomain), 2);
+    assert_eq!(RateLimiter::rate(&config, ResponseClass::Error), 5);
+    assert!(matches!(
+      ResponseClass::from(ResponseCode::NoError),
+      ResponseClass::Answer
+    ));
+    assert!(matches!(
+      ResponseClass::from(ResponseCode::NXDomain),
+      ResponseClass::NxDomain
+    ));
+    assert!(matches!(
+      ResponseClass::from(ResponseCode::Refused),
+      ResponseClass::Error
+    ));
+  }
+
+  #[test]
+  fn refill() {
+    let limiter = limiter(3, 0);
+
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 4),
+      "sssd"
+    );
+    wait(&limiter, 1);
+    // -1 + 3, capped at the rate
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 3),
+      "ssd"
+    );
+    wait(&limiter, 10);
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 4),
+      "sssd"
+    );
+  }
+
+  #[test]
+  fn debt_is_capped_by_window() {
+    let limiter = limiter(1, 0);
+
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 100),
+      format!("s{}", "d".repeat(99))
+    );
+    // the balance never goes below -rate * window
+    wait(&limiter, 2);
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 1),
+      "d"
+    );
+    wait(&limiter, 3);
+    assert_eq!(
+      verdicts(&limiter, "192.0.2.1", ResponseClass::Answer, 1),
+      "s"
+    );
+  }
+
+  #[test]
+  fn prefixes() {
+    let mut config = limiter(1, 0).config.read().unwrap().clone();
+    let addr: IpAddr = "192.0.2.129".parse().unwrap();
+    let addr6: IpAddr = "2001:db8::1".parse().unwrap();
+
+    assert_eq!(
+      RateLimiter::prefix(&config, addr),
+      "192.0.2.0".parse::<IpAddr>().unwrap()
+    );
+    config.ipv4_prefix_length = 25;
+    assert_eq!(
+      RateLimiter::prefix(&config, addr),
+      "192.0.2.128".parse::<IpAddr>().unwrap()
+    );
+    config.ipv4_prefix_length = 0;
+    config.ipv6_prefix_length = 0;
+    assert_eq!(
+      RateLimiter::prefix(&config, addr),
+      "0.0.0.0".parse::<IpAddr>().unwrap()
+    );
+    assert_eq!(
+      RateLimiter::prefix(&config, addr6),
+      "::".parse::<IpAddr>().unwrap()
+    );
+    config.ipv4_prefix_length = 40;
+    config.ipv6_prefix_length = 200;
+    assert_eq!(RateLimiter::prefix(&config, addr), addr);
+    assert_eq!(RateLimiter::prefix(&config, addr6), addr6);
+  }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use entity::{zone, zone_catalog};
+
+use crate::metrics::Metrics;
+use crate::service::zone::EPOCH;
+
+/// Source of the catalog zone listing every zone we serve.
+pub(crate) struct CatalogZoneService {
+  db: Arc<DatabaseConnection>,
+  metrics: Arc<Metrics>,
+}
+
+impl CatalogZoneService {
+  pub(crate) fn new(db: Arc<DatabaseConnection>, metrics: Arc<Metrics>) -> Self {
+    Self { db, metrics }
+  }
+
+  /// Changes whenever a zone is created, renamed, verified, deleted or made a
+  /// secondary.
+  pub(crate) async fn serial(&self) -> anyhow::Result<u32> {
+    self
+      .metrics
+      .observe_database("catalog_zone_serial", self.query_serial())
+      .await
+  }
+
+  async fn query_serial(&self) -> anyhow::Result<u32> {
+    let catalog = zone_catalog::Entity::find()
+      .one(self.db.as_ref())
+      .await?
+      .ok_or_else(|| anyhow!("zone catalog not found"))?;
+
+    Ok((catalog.updated - EPOCH).whole_seconds() as u32)
+  }
+
+  /// Ids and names of the zones we are the primary of, secondary zones are
+  /// not transferred from us.
+  pub(crate) async fn members(&self) -> anyhow::Result<Vec<(Uuid, String)>> {
+    self
+      .metrics
+      .observe_database("catalog_zone_members", self.query_members())
+      .await
+  }
+
+  async fn query_members(&self) -> anyhow::Result<Vec<(Uuid, String)>> {
+    let zones: Vec<(Uuid, String)> = zone::Entity::find()
+      .filter(zone::Column::Verified.eq(true))
+      .filter(zone::Column::Deleted.is_null())
+      .filter(zone::Column::PrimaryServer.is_null())
+      .order_by_asc(zone::Column::Created)
+      .select_only()
+      .column(zone::Column::Id)
+      .column(zone::Column::Name)
+      .into_tuple()
+      .all(self.db.as_ref())
+      .await?;
+
+    // zone names are stored with and without the trailing dot, a name is
+    // only listed once, for the zone created first
+    let mut names = HashSet::new();
+    Ok(
+      zones
+        .into_iter()
+        .filter(|(_, name)| names.insert(name.trim_end_matches('.').to_ascii_lowercase()))
+        .collect(),
+    )
+  }
+}
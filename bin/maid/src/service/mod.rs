@@ -1,3 +1,7 @@
+mod catalog_zone;
+mod secondary;
 mod zone;
 
-pub(crate) use zone::ZoneService;
+pub(crate) use catalog_zone::CatalogZoneService;
+pub(crate) use secondary::{SecondaryService, ZoneCopy};
+pub(crate) use zone::{Client, ZoneDefaults, ZoneService};
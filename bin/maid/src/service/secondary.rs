@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use hickory_server::proto::rr::Record;
+use hickory_server::proto::serialize::binary::{
+  BinDecodable, BinDecoder, BinEncodable, BinEncoder,
+};
+use sea_orm::prelude::Uuid;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use time::OffsetDateTime;
+
+use entity::{zone, zone_transfer};
+
+use crate::metrics::Metrics;
+
+/// Transferred copies of zones mastered elsewhere.
+pub(crate) struct SecondaryService {
+  db: Arc<DatabaseConnection>,
+  metrics: Arc<Metrics>,
+}
+
+/// A stored copy of a secondary zone.
+pub(crate) struct ZoneCopy {
+  pub(crate) serial: u32,
+  pub(crate) records: Vec<Record>,
+  pub(crate) refreshed: OffsetDateTime,
+}
+
+impl SecondaryService {
+  pub(crate) fn new(db: Arc<DatabaseConnection>, metrics: Arc<Metrics>) -> Self {
+    Self { db, metrics }
+  }
+
+  pub(crate) async fn zones(&self) -> anyhow::Result<Vec<zone::Model>> {
+    self
+      .metrics
+      .observe_database("secondary_zones", self.query_zones())
+      .await
+  }
+
+  async fn query_zones(&self) -> anyhow::Result<Vec<zone::Model>> {
+    Ok(
+      zone::Entity::find()
+        .filter(
+          zone::Column::PrimaryServer
+            .is_not_null()
+            .and(zone::Column::Verified.eq(true))
+            .and(zone::Column::Deleted.is_null()),
+        )
+        .all(self.db.as_ref())
+        .await?,
+    )
+  }
+
+  pub(crate) async fn load(&self, zone_id: Uuid) -> anyhow::Result<Option<ZoneCopy>> {
+    self
+      .metrics
+      .observe_database("load_zone_copy", self.query_load(zone_id))
+      .await
+  }
+
+  async fn query_load(&self, zone_id: Uuid) -> anyhow::Result<Option<ZoneCopy>> {
+    let transfer = match zone_transfer::Entity::find_by_id(zone_id)
+      .one(self.db.as_ref())
+      .await?
+    {
+      Some(transfer) => transfer,
+      None => return Ok(None),
+    };
+
+    let mut records = Vec::new();
+    let mut decoder = BinDecoder::new(&transfer.records);
+    while !decoder.is_empty() {
+      records.push(Record::read(&mut decoder)?);
+    }
+
+    Ok(Some(ZoneCopy {
+      serial: transfer.serial as u32,
+      records,
+      refreshed: transfer.refreshed,
+    }))
+  }
+
+  pub(crate) async fn store(&self, zone_id: Uuid, copy: &ZoneCopy) -> anyhow::Result<()> {
+    self
+      .metrics
+      .observe_database("store_zone_copy", self.query_store(zone_id, copy))
+      .await
+  }
+
+  async fn query_store(&self, zone_id: Uuid, copy: &ZoneCopy) -> anyhow::Result<()> {
+    let mut records = Vec::new();
+    {
+      let mut encoder = BinEncoder::new(&mut records);
+      for record in &copy.records {
+        record.emit(&mut encoder)?;
+      }
+    }
+
+    zone_transfer::Entity::insert(zone_transfer::ActiveModel {
+      zone_id: ActiveValue::Set(zone_id),
+      serial: ActiveValue::Set(copy.serial as i64),
+      records: ActiveValue::Set(records),
+      refreshed: ActiveValue::Set(copy.refreshed),
+    })
+    .on_conflict(
+      OnConflict::column(zone_transfer::Column::ZoneId)
+        .update_columns([
+          zone_transfer::Column::Serial,
+          zone_transfer::Column::Records,
+          zone_transfer::Column::Refreshed,
+        ])
+        .to_owned(),
+    )
+    .exec(self.db.as_ref())
+    .await?;
+
+    Ok(())
+  }
+
+  /// Remembers that the primary still serves the same serial.
+  pub(crate) async fn touch(&self, zone_id: Uuid, refreshed: OffsetDateTime) -> anyhow::Result<()> {
+    self
+      .metrics
+      .observe_database("touch_zone_copy", self.query_touch(zone_id, refreshed))
+      .await
+  }
+
+  async fn query_touch(&self, zone_id: Uuid, refreshed: OffsetDateTime) -> anyhow::Result<()> {
+    zone_transfer::Entity::update_many()
+      .col_expr(zone_transfer::Column::Refreshed, Expr::value(refreshed))
+      .filter(zone_transfer::Column::ZoneId.eq(zone_id))
+      .exec(self.db.as_ref())
+      .await?;
+
+    Ok(())
+  }
+}
@@ -1,10 +1,17 @@
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
-use std::sync::Arc;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 
 use anyhow::anyhow;
+use clap::Args;
+use ipnet::IpNet;
+use rand::Rng;
 use sea_orm::prelude::{Expr, Uuid};
+use sea_orm::sea_query::SimpleExpr;
 
 use hickory_server::proto::rr::domain::Label;
 use hickory_server::proto::rr::{rdata, LowerName, Name, RData, Record, RecordSet, RecordType};
@@ -14,20 +21,136 @@ use sea_orm::{
 use time::macros::datetime;
 use time::OffsetDateTime;
 
-use entity::IntoRecord;
-use entity::{record, record_a, record_aaaa, record_cname, record_mx, record_ns, record_txt, zone};
+use entity::{parse_name, IntoRecord};
+use entity::{
+  record, record_a, record_aaaa, record_cname, record_health_check, record_mx, record_ns,
+  record_txt, view, zone,
+};
 use migration::extension::postgres::PgExpr;
 
+use crate::geo::Location;
+use crate::metrics::Metrics;
+
 // Thu Oct 12 2023 00:00:00 GMT+0000
-const EPOCH: OffsetDateTime = datetime!(2023-10-12 00:00:00 UTC);
+pub(super) const EPOCH: OffsetDateTime = datetime!(2023-10-12 00:00:00 UTC);
+
+// longest CNAME chain followed for a single answer
+const MAX_CNAME_CHAIN: usize = 8;
+
+// record types answered for an ANY query, in order of preference when only
+// a single RRset is returned
+const ANY_RECORD_TYPES: [RecordType; 7] = [
+  RecordType::CNAME,
+  RecordType::A,
+  RecordType::AAAA,
+  RecordType::MX,
+  RecordType::TXT,
+  RecordType::NS,
+  RecordType::SOA,
+];
+
+/// Values used for everything a zone does not set itself.
+#[derive(Args, Clone, PartialEq)]
+pub(crate) struct ZoneDefaults {
+  /// TTL of records without one
+  #[arg(long = "default-ttl", env = "MAID_DEFAULT_TTL", default_value_t = 300)]
+  pub(crate) ttl: u32,
+  /// Primary name server announced in the SOA
+  #[arg(
+    long = "soa-mname",
+    env = "MAID_SOA_MNAME",
+    default_value = "ns.dns.dresden.zone."
+  )]
+  pub(crate) mname: Name,
+  /// Mailbox of the person responsible, announced in the SOA
+  #[arg(
+    long = "soa-rname",
+    env = "MAID_SOA_RNAME",
+    default_value = "dns.dresden.zone."
+  )]
+  pub(crate) rname: Name,
+  #[arg(long = "soa-refresh", env = "MAID_SOA_REFRESH", default_value_t = 7200)]
+  pub(crate) refresh: i32,
+  #[arg(long = "soa-retry", env = "MAID_SOA_RETRY", default_value_t = 3600)]
+  pub(crate) retry: i32,
+  #[arg(
+    long = "soa-expire",
+    env = "MAID_SOA_EXPIRE",
+    default_value_t = 1209600
+  )]
+  pub(crate) expire: i32,
+  /// TTL of negative answers
+  #[arg(long = "soa-minimum", env = "MAID_SOA_MINIMUM", default_value_t = 60)]
+  pub(crate) minimum: u32,
+}
+
+/// Everything besides the query itself an answer may depend on.
+#[derive(Default)]
+pub(crate) struct Client {
+  pub(crate) view: Option<Uuid>,
+  pub(crate) location: Location,
+  // asked over udp, where large answers can be abused for amplification
+  pub(crate) udp: bool,
+  // set once records have been picked by location
+  tailored: AtomicBool,
+}
+
+impl Client {
+  pub(crate) fn new(view: Option<Uuid>, location: Location, udp: bool) -> Self {
+    Self {
+      view,
+      location,
+      udp,
+      tailored: AtomicBool::new(false),
+    }
+  }
+
+  pub(crate) fn is_tailored(&self) -> bool {
+    self.tailored.load(Ordering::Relaxed)
+  }
+}
 
 pub(crate) struct ZoneService {
   db: Arc<DatabaseConnection>,
+  metrics: Arc<Metrics>,
+  // follow CNAME chains into other zones we serve
+  cross_zone_cnames: bool,
+  defaults: RwLock<ZoneDefaults>,
 }
 
 impl ZoneService {
-  pub(crate) fn new(db: Arc<DatabaseConnection>) -> Self {
-    Self { db }
+  pub(crate) fn new(
+    db: Arc<DatabaseConnection>,
+    metrics: Arc<Metrics>,
+    cross_zone_cnames: bool,
+    defaults: ZoneDefaults,
+  ) -> Self {
+    Self {
+      db,
+      metrics,
+      cross_zone_cnames,
+      defaults: RwLock::new(defaults),
+    }
+  }
+
+  pub(crate) fn set_defaults(&self, defaults: ZoneDefaults) {
+    *self.defaults.write().unwrap() = defaults;
+  }
+
+  fn defaults(&self) -> ZoneDefaults {
+    self.defaults.read().unwrap().clone()
+  }
+
+  /// TTL of records without one, unless the zone sets its own.
+  async fn default_ttl(&self, zone_id: Uuid) -> anyhow::Result<u32> {
+    let ttl: Option<Option<i32>> = zone::Entity::find_by_id(zone_id)
+      .select_only()
+      .column(zone::Column::DefaultTtl)
+      .into_tuple()
+      .one(self.db.as_ref())
+      .await?;
+
+    Ok(ttl.flatten().map_or(self.defaults().ttl, |ttl| ttl as u32))
   }
 
   async fn records_serial(&self, zone_id: Uuid) -> anyhow::Result<Option<OffsetDateTime>> {
@@ -37,7 +160,8 @@ impl ZoneService {
         .filter(
           record::Column::ZoneId
             .eq(zone_id)
-            .and(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true))),
+            .and(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true)))
+            .and(Expr::col((zone::Entity, zone::Column::Deleted)).is_null()),
         )
         .select_only()
         .expr(record::Column::Updated.max())
@@ -47,13 +171,56 @@ impl ZoneService {
     )
   }
 
+  /// Picks the view of the zone with the most specific network containing
+  /// the client address.
+  pub(crate) async fn view(&self, zone_id: Uuid, addr: IpAddr) -> anyhow::Result<Option<Uuid>> {
+    self
+      .metrics
+      .observe_database("view", self.query_view(zone_id, addr))
+      .await
+  }
+
+  async fn query_view(&self, zone_id: Uuid, addr: IpAddr) -> anyhow::Result<Option<Uuid>> {
+    let views = view::Entity::find()
+      .filter(view::Column::ZoneId.eq(zone_id))
+      .all(self.db.as_ref())
+      .await?;
+
+    let addr = addr.to_canonical();
+
+    Ok(
+      views
+        .into_iter()
+        .filter_map(|view| {
+          view
+            .networks
+            .iter()
+            .filter_map(|network| network.parse::<IpNet>().ok())
+            .filter(|network| network.contains(&addr))
+            .map(|network| network.prefix_len())
+            .max()
+            .map(|prefix_len| (prefix_len, view.id))
+        })
+        .max_by_key(|(prefix_len, _)| *prefix_len)
+        .map(|(_, view_id)| view_id),
+    )
+  }
+
   pub(crate) async fn soa(
     &self,
     zone_id: Uuid,
     original: Option<&Name>,
   ) -> anyhow::Result<RecordSet> {
+    self
+      .metrics
+      .observe_database("soa", self.query_soa(zone_id, original))
+      .await
+  }
+
+  async fn query_soa(&self, zone_id: Uuid, original: Option<&Name>) -> anyhow::Result<RecordSet> {
     let zone = zone::Entity::find_by_id(zone_id)
       .filter(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true)))
+      .filter(Expr::col((zone::Entity, zone::Column::Deleted)).is_null())
       .one(self.db.as_ref())
       .await?;
 
@@ -68,7 +235,7 @@ impl ZoneService {
     };
     let serial = (serial - EPOCH).whole_seconds() as u32;
 
-    let mut name = Name::from_ascii(zone.name)?;
+    let mut name = Name::from_ascii(&zone.name)?;
     name.set_fqdn(true);
 
     let mut set = RecordSet::new(&name, RecordType::SOA, 0);
@@ -79,18 +246,30 @@ impl ZoneService {
       }
     }
 
+    // settings of the zone take precedence over the defaults
+    let defaults = self.defaults();
+    let mname = match &zone.soa_mname {
+      Some(mname) => parse_name(mname, &name)?,
+      None => defaults.mname,
+    };
+    let rname = match &zone.soa_rname {
+      Some(rname) => parse_name(rname, &name)?,
+      None => defaults.rname,
+    };
     set.insert(
       Record::from_rdata(
         name,
-        300,
+        zone.default_ttl.map_or(defaults.ttl, |ttl| ttl as u32),
         RData::SOA(rdata::SOA::new(
-          Name::from_ascii("ns.dns.dresden.zone.")?,
-          Name::from_ascii("dns.dresden.zone")?,
+          mname,
+          rname,
           serial,
-          7200,
-          3600,
-          1209600,
-          60,
+          zone.soa_refresh.unwrap_or(defaults.refresh),
+          zone.soa_retry.unwrap_or(defaults.retry),
+          zone.soa_expire.unwrap_or(defaults.expire),
+          zone
+            .soa_minimum
+            .map_or(defaults.minimum, |minimum| minimum as u32),
         )),
       ),
       0,
@@ -99,7 +278,22 @@ impl ZoneService {
     Ok(set)
   }
 
-  pub(crate) async fn lookup_any(&self, zone_id: Uuid) -> anyhow::Result<Vec<RecordSet>> {
+  pub(crate) async fn lookup_any(
+    &self,
+    zone_id: Uuid,
+    client: &Client,
+  ) -> anyhow::Result<Vec<RecordSet>> {
+    self
+      .metrics
+      .observe_database("lookup_any", self.query_lookup_any(zone_id, client))
+      .await
+  }
+
+  async fn query_lookup_any(
+    &self,
+    zone_id: Uuid,
+    client: &Client,
+  ) -> anyhow::Result<Vec<RecordSet>> {
     let mut records = Vec::with_capacity(7);
 
     let soa = self.soa(zone_id, None).await?;
@@ -107,18 +301,14 @@ impl ZoneService {
     // records.push(soa);
 
     records.append(
-      &mut query_all_records::<record_a::Entity, _>(
-        self.db.as_ref(),
-        zone_id,
-        origin,
-        RecordType::A,
-      )
-      .await?,
+      &mut query_all_records::<record_a::Entity, _>(self, zone_id, client, origin, RecordType::A)
+        .await?,
     );
     records.append(
       &mut query_all_records::<record_aaaa::Entity, _>(
-        self.db.as_ref(),
+        self,
         zone_id,
+        client,
         origin,
         RecordType::AAAA,
       )
@@ -126,8 +316,9 @@ impl ZoneService {
     );
     records.append(
       &mut query_all_records::<record_cname::Entity, _>(
-        self.db.as_ref(),
+        self,
         zone_id,
+        client,
         origin,
         RecordType::CNAME,
       )
@@ -135,27 +326,18 @@ impl ZoneService {
     );
 
     records.append(
-      &mut query_all_records::<record_mx::Entity, _>(
-        self.db.as_ref(),
-        zone_id,
-        origin,
-        RecordType::MX,
-      )
-      .await?,
+      &mut query_all_records::<record_mx::Entity, _>(self, zone_id, client, origin, RecordType::MX)
+        .await?,
     );
     records.append(
-      &mut query_all_records::<record_ns::Entity, _>(
-        self.db.as_ref(),
-        zone_id,
-        origin,
-        RecordType::NS,
-      )
-      .await?,
+      &mut query_all_records::<record_ns::Entity, _>(self, zone_id, client, origin, RecordType::NS)
+        .await?,
     );
     records.append(
       &mut query_all_records::<record_txt::Entity, _>(
-        self.db.as_ref(),
+        self,
         zone_id,
+        client,
         origin,
         RecordType::TXT,
       )
@@ -172,28 +354,126 @@ impl ZoneService {
     original: &LowerName,
     host: &str,
     record_type: RecordType,
+    client: &Client,
+  ) -> anyhow::Result<Option<RecordSet>> {
+    self
+      .metrics
+      .observe_database(
+        "lookup",
+        self.query_lookup(zone_id, origin, original, host, record_type, client),
+      )
+      .await
+  }
+
+  async fn query_lookup(
+    &self,
+    zone_id: Uuid,
+    origin: &Name,
+    original: &LowerName,
+    host: &str,
+    record_type: RecordType,
+    client: &Client,
   ) -> anyhow::Result<Option<RecordSet>> {
     let name = original.into();
+    let set = self
+      .query_set(zone_id, origin, &name, host, record_type, client)
+      .await?;
+
+    Ok(if set.is_empty() {
+      let records = query_records::<record_cname::Entity, _>(
+        self,
+        zone_id,
+        client,
+        origin,
+        &name,
+        RecordType::CNAME,
+        host,
+      )
+      .await?;
+      if records.is_empty() {
+        None
+      } else {
+        Some(records)
+      }
+    } else {
+      Some(set)
+    })
+  }
+
+  /// Looks up the RRsets of a single name for an ANY query, stopping after
+  /// the first one unless `all` is set.
+  pub(crate) async fn lookup_name_any(
+    &self,
+    zone_id: Uuid,
+    origin: &Name,
+    original: &LowerName,
+    host: &str,
+    all: bool,
+    client: &Client,
+  ) -> anyhow::Result<Vec<RecordSet>> {
+    self
+      .metrics
+      .observe_database(
+        "lookup_name_any",
+        self.query_lookup_name_any(zone_id, origin, original, host, all, client),
+      )
+      .await
+  }
+
+  async fn query_lookup_name_any(
+    &self,
+    zone_id: Uuid,
+    origin: &Name,
+    original: &LowerName,
+    host: &str,
+    all: bool,
+    client: &Client,
+  ) -> anyhow::Result<Vec<RecordSet>> {
+    let name = original.into();
+    let mut sets = Vec::new();
+
+    for record_type in ANY_RECORD_TYPES {
+      // the SOA only lives at the apex
+      if record_type == RecordType::SOA && host != "@" {
+        continue;
+      }
 
-    let set = match record_type {
-      RecordType::SOA => self.soa(zone_id, Some(&name)).await?,
+      let set = self
+        .query_set(zone_id, origin, &name, host, record_type, client)
+        .await?;
+      if !set.is_empty() {
+        sets.push(set);
+        if !all {
+          break;
+        }
+      }
+    }
+
+    Ok(sets)
+  }
+
+  async fn query_set(
+    &self,
+    zone_id: Uuid,
+    origin: &Name,
+    name: &Name,
+    host: &str,
+    record_type: RecordType,
+    client: &Client,
+  ) -> anyhow::Result<RecordSet> {
+    Ok(match record_type {
+      RecordType::SOA => self.soa(zone_id, Some(name)).await?,
       record_type @ RecordType::A => {
-        query_records::<record_a::Entity, _>(
-          self.db.as_ref(),
-          zone_id,
-          origin,
-          &name,
-          record_type,
-          host,
-        )
-        .await?
+        query_records::<record_a::Entity, _>(self, zone_id, client, origin, name, record_type, host)
+          .await?
       }
       record_type @ RecordType::AAAA => {
         query_records::<record_aaaa::Entity, _>(
-          self.db.as_ref(),
+          self,
           zone_id,
+          client,
           origin,
-          &name,
+          name,
           record_type,
           host,
         )
@@ -201,10 +481,11 @@ impl ZoneService {
       }
       record_type @ RecordType::MX => {
         query_records::<record_mx::Entity, _>(
-          self.db.as_ref(),
+          self,
           zone_id,
+          client,
           origin,
-          &name,
+          name,
           record_type,
           host,
         )
@@ -212,10 +493,11 @@ impl ZoneService {
       }
       record_type @ RecordType::NS => {
         query_records::<record_ns::Entity, _>(
-          self.db.as_ref(),
+          self,
           zone_id,
+          client,
           origin,
-          &name,
+          name,
           record_type,
           host,
         )
@@ -223,10 +505,11 @@ impl ZoneService {
       }
       record_type @ RecordType::CNAME => {
         query_records::<record_cname::Entity, _>(
-          self.db.as_ref(),
+          self,
           zone_id,
+          client,
           origin,
-          &name,
+          name,
           record_type,
           host,
         )
@@ -234,36 +517,93 @@ impl ZoneService {
       }
       record_type @ RecordType::TXT => {
         query_records::<record_txt::Entity, _>(
-          self.db.as_ref(),
+          self,
           zone_id,
+          client,
           origin,
-          &name,
+          name,
           record_type,
           host,
         )
         .await?
       }
       _ => todo!(),
-    };
+    })
+  }
 
-    Ok(if set.is_empty() {
-      let records = query_records::<record_cname::Entity, _>(
-        self.db.as_ref(),
-        zone_id,
-        origin,
-        &name,
-        RecordType::CNAME,
-        host,
-      )
-      .await?;
-      if records.is_empty() {
-        None
+  /// Follows the CNAME chain an answer starts, so the targets end up in the
+  /// answer section as described in RFC 1034 3.6.2. Stops at loops, after
+  /// `MAX_CNAME_CHAIN` links and at targets outside the zones we serve.
+  pub(crate) async fn cname_chain(
+    &self,
+    zone_id: Uuid,
+    origin: &Name,
+    original_name: &LowerName,
+    query_type: RecordType,
+    cname: &RecordSet,
+    client: &Client,
+  ) -> anyhow::Result<Vec<RecordSet>> {
+    follow_cnames(original_name, cname, move |search| async move {
+      let (zone_id, origin) = if LowerName::from(origin).zone_of(&search) {
+        (zone_id, origin.clone())
+      } else if self.cross_zone_cnames {
+        match self.zone_of(&search).await? {
+          Some(zone) => zone,
+          None => return Ok(None),
+        }
       } else {
-        Some(records)
-      }
-    } else {
-      Some(set)
+        return Ok(None);
+      };
+
+      let host = host_of(&origin, &search);
+      self
+        .lookup(zone_id, &origin, &search, &host, query_type, client)
+        .await
     })
+    .await
+  }
+
+  /// Finds the most specific zone we serve a name belongs to.
+  async fn zone_of(&self, name: &LowerName) -> anyhow::Result<Option<(Uuid, Name)>> {
+    self
+      .metrics
+      .observe_database("zone_of", self.query_zone_of(name))
+      .await
+  }
+
+  async fn query_zone_of(&self, name: &LowerName) -> anyhow::Result<Option<(Uuid, Name)>> {
+    // zone names are stored with and without the trailing dot
+    let mut candidates = Vec::new();
+    let mut name = Name::from(name);
+    while !name.is_root() {
+      let fqdn = name.to_ascii();
+      candidates.push(fqdn.trim_end_matches('.').to_string());
+      candidates.push(fqdn);
+      name = name.base_name();
+    }
+
+    let zones: Vec<(Uuid, String)> = zone::Entity::find()
+      .filter(zone::Column::Verified.eq(true))
+      .filter(zone::Column::Deleted.is_null())
+      .filter(zone::Column::PrimaryServer.is_null())
+      .filter(zone::Column::Name.is_in(candidates))
+      .select_only()
+      .column(zone::Column::Id)
+      .column(zone::Column::Name)
+      .into_tuple()
+      .all(self.db.as_ref())
+      .await?;
+
+    let Some((zone_id, name)) = zones
+      .into_iter()
+      .max_by_key(|(_, name)| name.trim_end_matches('.').len())
+    else {
+      return Ok(None);
+    };
+
+    let mut origin = Name::from_ascii(name)?;
+    origin.set_fqdn(true);
+    Ok(Some((zone_id, origin)))
   }
 
   pub(crate) async fn additional_search(
@@ -273,6 +613,7 @@ impl ZoneService {
     original_name: &LowerName,
     original_query_type: RecordType,
     next_name: LowerName,
+    client: &Client,
   ) -> Option<Vec<Arc<RecordSet>>> {
     let mut additionals: Vec<Arc<RecordSet>> = vec![];
 
@@ -304,31 +645,10 @@ impl ZoneService {
           break;
         }
 
-        let host = {
-          let mut host = String::new();
-
-          let name = Name::from(search.clone());
-          let name = name.into_iter().rev().skip(origin.iter().len());
-          let mut first = true;
-          for label in name {
-            if first {
-              first = false;
-            } else {
-              host.write_char('.').unwrap();
-            }
-            let name = Label::from_raw_bytes(label).unwrap();
-            name.write_ascii(&mut host).unwrap();
-          }
-
-          if host.is_empty() {
-            host.write_char('@').unwrap();
-          }
-
-          host
-        };
+        let host = host_of(origin, &search);
 
         let additional = self
-          .lookup(zone_id, origin, &search, &host, *query_type)
+          .lookup(zone_id, origin, &search, &host, *query_type, client)
           .await
           .unwrap();
         names.insert(search);
@@ -352,20 +672,40 @@ impl ZoneService {
     }
   }
 
-  pub(crate) async fn name_exists(&self, zone_id: Uuid, host: &str) -> anyhow::Result<bool> {
+  pub(crate) async fn name_exists(
+    &self,
+    zone_id: Uuid,
+    host: &str,
+    client: &Client,
+  ) -> anyhow::Result<bool> {
+    self
+      .metrics
+      .observe_database("name_exists", self.query_name_exists(zone_id, host, client))
+      .await
+  }
+
+  async fn query_name_exists(
+    &self,
+    zone_id: Uuid,
+    host: &str,
+    client: &Client,
+  ) -> anyhow::Result<bool> {
     Ok(
       record::Entity::find()
         .inner_join(zone::Entity)
         .filter(
-          Expr::col((zone::Entity, zone::Column::Id)).eq(zone_id).and(
-            Expr::col((record::Entity, record::Column::Name))
-              .eq(host)
-              .or(
-                Expr::val('%')
-                  .concat(Expr::col((record::Entity, record::Column::Name)))
-                  .like(host),
-              ),
-          ),
+          Expr::col((zone::Entity, zone::Column::Id))
+            .eq(zone_id)
+            .and(
+              Expr::col((record::Entity, record::Column::Name))
+                .eq(host)
+                .or(
+                  Expr::val('%')
+                    .concat(Expr::col((record::Entity, record::Column::Name)))
+                    .like(host),
+                ),
+            )
+            .and(view_condition(client.view)),
         )
         .limit(1)
         .one(self.db.as_ref())
@@ -375,6 +715,75 @@ impl ZoneService {
   }
 }
 
+/// Looks up the targets of a CNAME chain one after another, until a target
+/// is not found, loops or the chain gets too long.
+async fn follow_cnames<F, Fut>(
+  original_name: &LowerName,
+  cname: &RecordSet,
+  mut lookup: F,
+) -> anyhow::Result<Vec<RecordSet>>
+where
+  F: FnMut(LowerName) -> Fut,
+  Fut: Future<Output = anyhow::Result<Option<RecordSet>>>,
+{
+  let mut chain = Vec::new();
+  let mut names = HashSet::from([original_name.clone()]);
+
+  let mut next_name = cname_target(cname);
+  while let Some(search) = next_name.take() {
+    if !names.insert(search.clone()) || chain.len() >= MAX_CNAME_CHAIN {
+      break;
+    }
+
+    let Some(set) = lookup(search).await? else {
+      break;
+    };
+
+    next_name = cname_target(&set);
+    chain.push(set);
+  }
+
+  Ok(chain)
+}
+
+/// Target of a CNAME record set.
+fn cname_target(record_set: &RecordSet) -> Option<LowerName> {
+  if record_set.record_type() != RecordType::CNAME {
+    return None;
+  }
+
+  record_set
+    .records_without_rrsigs()
+    .next()
+    .and_then(Record::data)
+    .and_then(RData::as_cname)
+    .map(|cname| LowerName::from(&cname.0))
+}
+
+/// Name of a record relative to the zone origin, `@` for the apex.
+fn host_of(origin: &Name, name: &LowerName) -> String {
+  let mut host = String::new();
+
+  let name = Name::from(name);
+  let name = name.into_iter().rev().skip(origin.iter().len());
+  let mut first = true;
+  for label in name {
+    if first {
+      first = false;
+    } else {
+      host.write_char('.').unwrap();
+    }
+    let name = Label::from_raw_bytes(label).unwrap();
+    name.write_ascii(&mut host).unwrap();
+  }
+
+  if host.is_empty() {
+    host.write_char('@').unwrap();
+  }
+
+  host
+}
+
 /// Gets the next search name, and returns the RecordType that it originated from
 fn maybe_next_name(
   record_set: &RecordSet,
@@ -418,8 +827,9 @@ fn maybe_next_name(
 }
 
 async fn query_records<E, M>(
-  db: &DatabaseConnection,
+  service: &ZoneService,
   zone_id: Uuid,
+  client: &Client,
   origin: &Name,
   name: &Name,
   record_type: RecordType,
@@ -432,6 +842,7 @@ where
 {
   fn call(
     zone_id: Uuid,
+    view: Option<Uuid>,
     name: &Name,
     record_type: RecordType,
     host: &str,
@@ -444,19 +855,32 @@ where
         Expr::col((zone::Entity, zone::Column::Id))
           .eq(Expr::val(zone_id))
           .and(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true)))
-          .and(Expr::col((record::Entity, record::Column::Name)).eq(host)),
+          .and(Expr::col((zone::Entity, zone::Column::Deleted)).is_null())
+          .and(Expr::col((record::Entity, record::Column::Name)).eq(host))
+          .and(view_condition(view)),
       );
 
     (set, query)
   }
 
-  let (mut set, query) = call(zone_id, name, record_type, host);
+  let (mut set, query) = call(zone_id, client.view, name, record_type, host);
+  let ttl = service.default_ttl(zone_id).await?;
 
-  let records = query
+  let mut records = query
     .inner_join(E::default())
     .select_also(E::default())
-    .all(db)
+    .all(service.db.as_ref())
     .await?;
+  prefer_view(&mut records);
+  prefer_location(&mut records, client);
+
+  if matches!(
+    record_type,
+    RecordType::A | RecordType::AAAA | RecordType::CNAME
+  ) {
+    skip_unhealthy(service.db.as_ref(), &mut records).await?;
+    pick_weighted(&mut records);
+  }
 
   for (record, model) in records {
     // we are using an inner join, so this can never be none
@@ -465,7 +889,7 @@ where
     set.insert(
       Record::from_rdata(
         name.clone(),
-        record.ttl.unwrap_or(300) as u32,
+        record.ttl.map_or(ttl, |ttl| ttl as u32),
         model.into_record(origin)?,
       ),
       0,
@@ -476,8 +900,9 @@ where
 }
 
 async fn query_all_records<E, M>(
-  db: &DatabaseConnection,
+  service: &ZoneService,
   zone_id: Uuid,
+  client: &Client,
   origin: &Name,
   record_type: RecordType,
 ) -> anyhow::Result<Vec<RecordSet>>
@@ -486,19 +911,25 @@ where
   record::Entity: Related<E>,
   M: IntoRecord,
 {
-  fn call(zone_id: Uuid) -> Select<record::Entity> {
+  fn call(zone_id: Uuid, view: Option<Uuid>) -> Select<record::Entity> {
     record::Entity::find().inner_join(zone::Entity).filter(
       Expr::col((zone::Entity, zone::Column::Id))
         .eq(Expr::val(zone_id))
-        .and(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true))),
+        .and(Expr::col((zone::Entity, zone::Column::Verified)).eq(Expr::val(true)))
+        .and(Expr::col((zone::Entity, zone::Column::Deleted)).is_null())
+        .and(view_condition(view)),
     )
   }
 
-  let records = call(zone_id)
+  let ttl = service.default_ttl(zone_id).await?;
+
+  let mut records = call(zone_id, client.view)
     .inner_join(E::default())
     .select_also(E::default())
-    .all(db)
+    .all(service.db.as_ref())
     .await?;
+  prefer_view(&mut records);
+  prefer_location(&mut records, client);
 
   let mut set = HashMap::new();
 
@@ -514,7 +945,7 @@ where
 
     let record = Record::from_rdata(
       name.clone(),
-      record.ttl.unwrap_or(300) as u32,
+      record.ttl.map_or(ttl, |ttl| ttl as u32),
       model.into_record(origin)?,
     );
 
@@ -532,3 +963,259 @@ where
 
   Ok(set.into_values().collect())
 }
+
+/// Records without a view are visible to every client, records assigned to
+/// a view only to the clients of that view.
+fn view_condition(view: Option<Uuid>) -> SimpleExpr {
+  let view_id = Expr::col((record::Entity, record::Column::ViewId));
+
+  match view {
+    Some(view) => view_id
+      .clone()
+      .is_null()
+      .or(Expr::col((record::Entity, record::Column::ViewId)).eq(view)),
+    None => view_id.is_null(),
+  }
+}
+
+/// Drops records visible to every client if the client's view defines records
+/// of the same name, as the view is supposed to replace them (all records
+/// passed in share one type).
+fn prefer_view<M>(records: &mut Vec<(record::Model, Option<M>)>) {
+  let overridden: HashSet<String> = records
+    .iter()
+    .filter(|(record, _)| record.view_id.is_some())
+    .map(|(record, _)| record.name.clone())
+    .collect();
+
+  records.retain(|(record, _)| record.view_id.is_some() || !overridden.contains(&record.name));
+}
+
+/// Picks records tagged with the client's country over records tagged with
+/// its continent over untagged ones, per name. If neither matches and there
+/// is no untagged default, all records are served rather than none.
+fn prefer_location<M>(records: &mut Vec<(record::Model, Option<M>)>, client: &Client) {
+  if records
+    .iter()
+    .all(|(record, _)| record.country.is_none() && record.continent.is_none())
+  {
+    return;
+  }
+
+  client.tailored.store(true, Ordering::Relaxed);
+
+  let matches = |tag: &Option<String>, location: &Option<String>| match (tag, location) {
+    (Some(tag), Some(location)) => tag.eq_ignore_ascii_case(location),
+    _ => false,
+  };
+  let rank = |record: &record::Model| {
+    if record.country.is_none() && record.continent.is_none() {
+      1
+    } else if matches(&record.country, &client.location.country) {
+      3
+    } else if matches(&record.continent, &client.location.continent) {
+      2
+    } else {
+      0
+    }
+  };
+
+  let mut best: HashMap<String, u8> = HashMap::new();
+  for (record, _) in records.iter() {
+    let entry = best.entry(record.name.clone()).or_default();
+    *entry = (*entry).max(rank(record));
+  }
+
+  records.retain(|(record, _)| best.get(&record.name) == Some(&rank(record)));
+}
+
+/// Leaves out records whose health check failed, unless that would leave
+/// nothing to answer with.
+async fn skip_unhealthy<M>(
+  db: &DatabaseConnection,
+  records: &mut Vec<(record::Model, Option<M>)>,
+) -> anyhow::Result<()> {
+  if records.len() < 2 {
+    return Ok(());
+  }
+
+  let unhealthy: HashSet<Uuid> = record_health_check::Entity::find()
+    .filter(
+      record_health_check::Column::Id
+        .is_in(records.iter().map(|(record, _)| record.id))
+        .and(record_health_check::Column::Healthy.eq(false)),
+    )
+    .select_only()
+    .column(record_health_check::Column::Id)
+    .into_tuple()
+    .all(db)
+    .await?
+    .into_iter()
+    .collect();
+
+  if records
+    .iter()
+    .any(|(record, _)| !unhealthy.contains(&record.id))
+  {
+    records.retain(|(record, _)| !unhealthy.contains(&record.id));
+  }
+
+  Ok(())
+}
+
+/// Answers with a single record chosen by weight, as soon as any of the
+/// records has one.
+fn pick_weighted<M>(records: &mut Vec<(record::Model, Option<M>)>) {
+  if records.iter().all(|(record, _)| record.weight.is_none()) {
+    return;
+  }
+
+  let weights: Vec<u64> = records
+    .iter()
+    .map(|(record, _)| record.weight.unwrap_or(1).max(0) as u64)
+    .collect();
+  let total: u64 = weights.iter().sum();
+
+  // only weights of zero left, treat them as equal
+  let index = if total == 0 {
+    rand::thread_rng().gen_range(0..records.len())
+  } else {
+    let mut point = rand::thread_rng().gen_range(0..total);
+    weights
+      .iter()
+      .position(|weight| {
+        if point < *weight {
+          true
+        } else {
+          point -= weight;
+          false
+        }
+      })
+      .unwrap_or_default()
+  };
+
+  let picked = records.swap_remove(index);
+  records.clear();
+  records.push(picked);
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+  use std::str::FromStr;
+
+  use super::*;
+
+  fn name(name: &str) -> LowerName {
+    LowerName::from_str(name).unwrap()
+  }
+
+  fn set(owner: &str, rdata: RData) -> RecordSet {
+    Record::from_rdata(Name::from_str(owner).unwrap(), 300, rdata).into()
+  }
+
+  fn cname(owner: &str, target: &str) -> RecordSet {
+    set(
+      owner,
+      RData::CNAME(rdata::CNAME(Name::from_str(target).unwrap())),
+    )
+  }
+
+  // follows the chain starting at `a.test.` through the given sets, returns
+  // the owners of the chain and the names looked up
+  async fn follow(sets: Vec<RecordSet>) -> (Vec<String>, Vec<String>) {
+    let start = sets[0].clone();
+    let sets: HashMap<LowerName, RecordSet> = sets
+      .into_iter()
+      .skip(1)
+      .map(|set| (LowerName::from(set.name()), set))
+      .collect();
+    let searched = RefCell::new(Vec::new());
+
+    let chain = follow_cnames(&name("a.test."), &start, |search| {
+      searched.borrow_mut().push(search.to_string());
+      let set = sets.get(&search).cloned();
+      async move { Ok(set) }
+    })
+    .await
+    .unwrap();
+
+    (
+      chain.iter().map(|set| set.name().to_string()).collect(),
+      searched.into_inner(),
+    )
+  }
+
+  #[tokio::test]
+  async fn chain_ends_at_answer() {
+    let (chain, searched) = follow(vec![
+      cname("a.test.", "b.test."),
+      cname("b.test.", "c.test."),
+      set("c.test.", RData::A(rdata::A::new(192, 0, 2, 1))),
+    ])
+    .await;
+
+    assert_eq!(chain, ["b.test.", "c.test."]);
+    assert_eq!(searched, ["b.test.", "c.test."]);
+  }
+
+  #[tokio::test]
+  async fn chain_ends_at_missing_target() {
+    let (chain, searched) = follow(vec![
+      cname("a.test.", "b.test."),
+      cname("b.test.", "gone.test."),
+    ])
+    .await;
+
+    assert_eq!(chain, ["b.test."]);
+    assert_eq!(searched, ["b.test.", "gone.test."]);
+  }
+
+  #[tokio::test]
+  async fn not_a_cname() {
+    let (chain, searched) =
+      follow(vec![set("a.test.", RData::A(rdata::A::new(192, 0, 2, 1)))]).await;
+
+    assert!(chain.is_empty());
+    assert!(searched.is_empty());
+  }
+
+  #[tokio::test]
+  async fn loops() {
+    let (chain, searched) = follow(vec![cname("a.test.", "a.test.")]).await;
+    assert!(chain.is_empty());
+    assert!(searched.is_empty());
+
+    // names compare case-insensitively
+    let (chain, searched) = follow(vec![
+      cname("a.test.", "b.test."),
+      cname("b.test.", "c.test."),
+      cname("c.test.", "B.Test."),
+    ])
+    .await;
+    assert_eq!(chain, ["b.test.", "c.test."]);
+    assert_eq!(searched, ["b.test.", "c.test."]);
+
+    let (chain, _) = follow(vec![
+      cname("a.test.", "b.test."),
+      cname("b.test.", "a.test."),
+    ])
+    .await;
+    assert_eq!(chain, ["b.test."]);
+  }
+
+  #[tokio::test]
+  async fn length_limit() {
+    let mut sets = vec![cname("a.test.", "1.test.")];
+    for n in 1..20 {
+      sets.push(cname(&format!("{n}.test."), &format!("{}.test.", n + 1)));
+    }
+    sets.push(set("20.test.", RData::A(rdata::A::new(192, 0, 2, 1))));
+
+    let (chain, searched) = follow(sets).await;
+
+    assert_eq!(chain.len(), MAX_CNAME_CHAIN);
+    assert_eq!(searched.len(), MAX_CNAME_CHAIN);
+    assert_eq!(chain.last().unwrap(), &format!("{}.test.", MAX_CNAME_CHAIN));
+  }
+}
@@ -0,0 +1,123 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use async_trait::async_trait;
+use hickory_server::authority::MessageResponse;
+use hickory_server::proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+use hickory_server::proto::rr::Record;
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+
+// see RFC 7871 6, address family numbers
+const FAMILY_IPV4: u16 = 1;
+const FAMILY_IPV6: u16 = 2;
+
+tokio::task_local! {
+  static SUBNET: Subnet;
+}
+
+/// EDNS Client Subnet of the request currently being answered, see RFC 7871.
+pub(crate) struct Subnet {
+  address: IpAddr,
+  source_prefix: u8,
+  scope_prefix: Cell<u8>,
+}
+
+impl Subnet {
+  pub(crate) fn from_request(request: &Request) -> Option<Self> {
+    let option = request.edns()?.option(EdnsCode::Subnet)?;
+    let EdnsOption::Subnet(subnet) = option else {
+      return None;
+    };
+
+    // hickory does not expose the fields, so take them from the wire format
+    let bytes = Vec::<u8>::try_from(subnet).ok()?;
+    let family = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+    let source_prefix = *bytes.get(2)?;
+    let address = bytes.get(4..).unwrap_or_default();
+
+    let address = match family {
+      FAMILY_IPV4 if source_prefix <= 32 => {
+        let mut octets = [0; 4];
+        octets[..address.len().min(4)].copy_from_slice(&address[..address.len().min(4)]);
+        IpAddr::V4(Ipv4Addr::from(octets))
+      }
+      FAMILY_IPV6 if source_prefix <= 128 => {
+        let mut octets = [0; 16];
+        octets[..address.len().min(16)].copy_from_slice(&address[..address.len().min(16)]);
+        IpAddr::V6(Ipv6Addr::from(octets))
+      }
+      _ => return None,
+    };
+
+    Some(Self {
+      address,
+      source_prefix,
+      scope_prefix: Cell::new(0),
+    })
+  }
+
+  /// Runs `future` with the subnet available to the functions below.
+  pub(crate) async fn scope<F: Future>(self, future: F) -> F::Output {
+    SUBNET.scope(self, future).await
+  }
+}
+
+/// The client address as told by the resolver. A source prefix of 0 means
+/// the resolver does not want answers tailored to its clients.
+pub(crate) fn client_address() -> Option<IpAddr> {
+  SUBNET
+    .try_with(|subnet| (subnet.source_prefix > 0).then_some(subnet.address))
+    .ok()
+    .flatten()
+}
+
+/// Marks the answer as depending on the client subnet, so resolvers only
+/// cache it for this subnet.
+pub(crate) fn mark_tailored() {
+  let _ = SUBNET.try_with(|subnet| subnet.scope_prefix.set(subnet.source_prefix));
+}
+
+/// Echoes the client subnet option with the resulting scope.
+#[derive(Clone)]
+pub(crate) struct SubnetResponseHandle<R> {
+  inner: R,
+}
+
+impl<R> SubnetResponseHandle<R> {
+  pub(crate) fn new(inner: R) -> Self {
+    Self { inner }
+  }
+}
+
+#[async_trait]
+impl<R: ResponseHandler> ResponseHandler for SubnetResponseHandle<R> {
+  async fn send_response<'a>(
+    &mut self,
+    mut response: MessageResponse<
+      '_,
+      'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+      impl Iterator<Item = &'a Record> + Send + 'a,
+    >,
+  ) -> io::Result<ResponseInfo> {
+    let option = SUBNET.try_with(|subnet| {
+      EdnsOption::Subnet(ClientSubnet::new(
+        subnet.address,
+        subnet.source_prefix,
+        subnet.scope_prefix.get(),
+      ))
+    });
+
+    if let (Ok(option), Some(edns)) = (option, response.get_edns()) {
+      let mut edns = edns.clone();
+      edns.options_mut().insert(option);
+      response.set_edns(edns);
+    }
+
+    self.inner.send_response(response).await
+  }
+}
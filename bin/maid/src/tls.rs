@@ -0,0 +1,113 @@
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+use tokio::time::interval;
+use tracing::{error, info};
+
+// how often the certificate files are checked for modifications
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Serves the certificate found at `cert_path`/`key_path` and swaps it out
+/// whenever one of the files is modified on disk.
+pub(crate) struct CertificateStore {
+  cert_path: PathBuf,
+  key_path: PathBuf,
+  current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateStore {
+  pub(crate) async fn load(cert_path: PathBuf, key_path: PathBuf) -> anyhow::Result<Arc<Self>> {
+    let current = load_certified_key(&cert_path, &key_path).await?;
+
+    Ok(Arc::new(Self {
+      cert_path,
+      key_path,
+      current: RwLock::new(Arc::new(current)),
+    }))
+  }
+
+  pub(crate) fn server_config(self: &Arc<Self>, alpn: &[&[u8]]) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+      .with_safe_defaults()
+      .with_no_client_auth()
+      .with_cert_resolver(self.clone());
+
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+    Arc::new(config)
+  }
+
+  pub(crate) async fn watch(self: Arc<Self>) {
+    let mut last_modified = self.modified().await.ok();
+    let mut interval = interval(RELOAD_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let modified = match self.modified().await {
+        Ok(modified) => modified,
+        Err(err) => {
+          error!("Unable to stat certificate files: {}", err);
+          continue;
+        }
+      };
+
+      if last_modified == Some(modified) {
+        continue;
+      }
+
+      match load_certified_key(&self.cert_path, &self.key_path).await {
+        Ok(certified_key) => {
+          *self.current.write().unwrap() = Arc::new(certified_key);
+          last_modified = Some(modified);
+          info!("Reloaded certificate from {}", self.cert_path.display());
+        }
+        Err(err) => error!("Unable to reload certificate: {}", err),
+      }
+    }
+  }
+
+  async fn modified(&self) -> anyhow::Result<(SystemTime, SystemTime)> {
+    let cert = tokio::fs::metadata(&self.cert_path).await?.modified()?;
+    let key = tokio::fs::metadata(&self.key_path).await?.modified()?;
+
+    Ok((cert, key))
+  }
+}
+
+impl ResolvesServerCert for CertificateStore {
+  fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+    Some(self.current.read().unwrap().clone())
+  }
+}
+
+async fn load_certified_key(cert_path: &Path, key_path: &Path) -> anyhow::Result<CertifiedKey> {
+  let certs = tokio::fs::read(cert_path).await?;
+  let certs = rustls_pemfile::certs(&mut BufReader::new(Cursor::new(certs)))?
+    .into_iter()
+    .map(Certificate)
+    .collect::<Vec<_>>();
+
+  if certs.is_empty() {
+    return Err(anyhow!("no certificate found in {}", cert_path.display()));
+  }
+
+  let key = tokio::fs::read(key_path).await?;
+  let mut reader = BufReader::new(Cursor::new(key));
+  let key = loop {
+    match rustls_pemfile::read_one(&mut reader)? {
+      Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) => break PrivateKey(key),
+      Some(_) => continue,
+      None => return Err(anyhow!("no private key found in {}", key_path.display())),
+    }
+  };
+
+  Ok(CertifiedKey::new(certs, any_supported_type(&key)?))
+}
@@ -3,7 +3,7 @@ use axum::http::StatusCode;
 use axum::Json;
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use axum_extra::extract::CookieJar;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use time::Duration;
 use tracing::error;
 use uuid::Uuid;
@@ -21,22 +21,12 @@ pub(super) struct RegisterRequest {
   password: String,
 }
 
-#[derive(Serialize)]
-pub(super) struct RegisterResponse {
-  user_id: String,
-}
-
 #[derive(Deserialize)]
 pub(super) struct PasswordLoginRequest {
   name: String,
   password: String,
 }
 
-#[derive(Serialize)]
-pub(super) struct PasswordLoginResponse {
-  user_id: String,
-}
-
 pub(super) async fn me(
   State(ctx): State<Context>,
   session: Session,
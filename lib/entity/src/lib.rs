@@ -9,4 +9,4 @@ mod models;
 pub use error::EntityError;
 pub use models::*;
 #[cfg(feature = "hickory-proto")]
-pub use try_from::IntoRecord;
+pub use try_from::{parse_name, IntoRecord};
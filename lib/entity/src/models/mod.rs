@@ -8,8 +8,12 @@ pub mod record;
 pub mod record_a;
 pub mod record_aaaa;
 pub mod record_cname;
+pub mod record_health_check;
 pub mod record_mx;
 pub mod record_ns;
 pub mod record_txt;
 pub mod user;
+pub mod view;
 pub mod zone;
+pub mod zone_catalog;
+pub mod zone_transfer;
@@ -6,8 +6,12 @@ pub use super::record::Entity as Record;
 pub use super::record_a::Entity as RecordA;
 pub use super::record_aaaa::Entity as RecordAaaa;
 pub use super::record_cname::Entity as RecordCname;
+pub use super::record_health_check::Entity as RecordHealthCheck;
 pub use super::record_mx::Entity as RecordMx;
 pub use super::record_ns::Entity as RecordNs;
 pub use super::record_txt::Entity as RecordTxt;
 pub use super::user::Entity as User;
+pub use super::view::Entity as View;
 pub use super::zone::Entity as Zone;
+pub use super::zone_catalog::Entity as ZoneCatalog;
+pub use super::zone_transfer::Entity as ZoneTransfer;
@@ -15,6 +15,10 @@ pub struct Model {
   pub name: String,
   pub zone_id: Uuid,
   pub ttl: Option<i32>,
+  pub view_id: Option<Uuid>,
+  pub country: Option<String>,
+  pub continent: Option<String>,
+  pub weight: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -25,12 +29,22 @@ pub enum Relation {
   RecordAaaa,
   #[sea_orm(has_many = "super::record_cname::Entity")]
   RecordCname,
+  #[sea_orm(has_one = "super::record_health_check::Entity")]
+  RecordHealthCheck,
   #[sea_orm(has_many = "super::record_mx::Entity")]
   RecordMx,
   #[sea_orm(has_many = "super::record_ns::Entity")]
   RecordNs,
   #[sea_orm(has_many = "super::record_txt::Entity")]
   RecordTxt,
+  #[sea_orm(
+    belongs_to = "super::view::Entity",
+    from = "Column::ViewId",
+    to = "super::view::Column::Id",
+    on_update = "NoAction",
+    on_delete = "NoAction"
+  )]
+  View,
   #[sea_orm(
     belongs_to = "super::zone::Entity",
     from = "Column::ZoneId",
@@ -59,6 +73,12 @@ impl Related<super::record_cname::Entity> for Entity {
   }
 }
 
+impl Related<super::record_health_check::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::RecordHealthCheck.def()
+  }
+}
+
 impl Related<super::record_mx::Entity> for Entity {
   fn to() -> RelationDef {
     Relation::RecordMx.def()
@@ -77,6 +97,12 @@ impl Related<super::record_txt::Entity> for Entity {
   }
 }
 
+impl Related<super::view::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::View.def()
+  }
+}
+
 impl Related<super::zone::Entity> for Entity {
   fn to() -> RelationDef {
     Relation::Zone.def()
@@ -16,6 +16,23 @@ pub struct Model {
   pub owner: Uuid,
   pub verified: bool,
   pub serial: i64,
+  pub primary_server: Option<String>,
+  pub description: Option<String>,
+  pub default_ttl: Option<i32>,
+  pub soa_mname: Option<String>,
+  pub soa_rname: Option<String>,
+  pub soa_refresh: Option<i32>,
+  pub soa_retry: Option<i32>,
+  pub soa_expire: Option<i32>,
+  pub soa_minimum: Option<i32>,
+  #[serde(with = "time::serde::iso8601::option")]
+  pub deleted: Option<TimeDateTimeWithTimeZone>,
+  pub verification_token: String,
+  pub verification_method: Option<String>,
+  #[serde(with = "time::serde::iso8601::option")]
+  pub verified_at: Option<TimeDateTimeWithTimeZone>,
+  #[serde(with = "time::serde::iso8601::option")]
+  pub verification_checked: Option<TimeDateTimeWithTimeZone>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -30,6 +47,10 @@ pub enum Relation {
     on_delete = "NoAction"
   )]
   User,
+  #[sea_orm(has_many = "super::view::Entity")]
+  View,
+  #[sea_orm(has_one = "super::zone_transfer::Entity")]
+  ZoneTransfer,
 }
 
 impl Related<super::record::Entity> for Entity {
@@ -44,4 +65,16 @@ impl Related<super::user::Entity> for Entity {
   }
 }
 
+impl Related<super::view::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::View.def()
+  }
+}
+
+impl Related<super::zone_transfer::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::ZoneTransfer.def()
+  }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
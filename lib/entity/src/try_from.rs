@@ -3,7 +3,9 @@ use hickory_proto::rr::{rdata, Name, RData};
 use crate::error::EntityError;
 use crate::{record_a, record_aaaa, record_cname, record_mx, record_ns, record_txt};
 
-fn parse_name(provided: &str, origin: &Name) -> Result<Name, EntityError> {
+/// Resolves names relative to the zone, e.g. `mail.@`, everything else is
+/// taken as is.
+pub fn parse_name(provided: &str, origin: &Name) -> Result<Name, EntityError> {
   let name = match provided.strip_suffix('@') {
     None => Name::from_ascii(provided)?,
     Some(stripped) => {
@@ -58,8 +60,26 @@ impl IntoRecord for record_ns::Model {
   }
 }
 
+// see RFC 1035 3.3
+const MAX_CHARACTER_STRING_LENGTH: usize = 255;
+
 impl IntoRecord for record_txt::Model {
+  /// Content longer than a single character string is split, without
+  /// breaking up characters.
   fn into_record(self, _origin: &Name) -> Result<RData, EntityError> {
-    Ok(RData::TXT(rdata::TXT::new(vec![self.content])))
+    let mut strings = Vec::new();
+    let mut rest = self.content.as_str();
+    while rest.len() > MAX_CHARACTER_STRING_LENGTH {
+      let mut end = MAX_CHARACTER_STRING_LENGTH;
+      while !rest.is_char_boundary(end) {
+        end -= 1;
+      }
+      let (string, remainder) = rest.split_at(end);
+      strings.push(string.to_string());
+      rest = remainder;
+    }
+    strings.push(rest.to_string());
+
+    Ok(RData::TXT(rdata::TXT::new(strings)))
   }
 }
@@ -1,12 +1,28 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20231010_000001_create_table;
+mod m20261019_000001_create_view;
+mod m20261019_000002_record_location;
+mod m20261019_000003_record_health_check;
+mod m20261019_000004_secondary_zone;
+mod m20261019_000005_zone_catalog;
+mod m20261019_000006_zone_settings;
+mod m20261019_000007_zone_verification;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
   fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-    vec![Box::new(m20231010_000001_create_table::Migration)]
+    vec![
+      Box::new(m20231010_000001_create_table::Migration),
+      Box::new(m20261019_000001_create_view::Migration),
+      Box::new(m20261019_000002_record_location::Migration),
+      Box::new(m20261019_000003_record_health_check::Migration),
+      Box::new(m20261019_000004_secondary_zone::Migration),
+      Box::new(m20261019_000005_zone_catalog::Migration),
+      Box::new(m20261019_000006_zone_settings::Migration),
+      Box::new(m20261019_000007_zone_verification::Migration),
+    ]
   }
 }
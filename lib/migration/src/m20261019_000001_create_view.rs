@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      create table view(
+        id       uuid          not null primary key default gen_random_uuid(),
+        created  timestamptz   not null             default now(),
+        updated  timestamptz   not null             default now(),
+        zone_id  uuid          not null references zone (id),
+        name     varchar(255)  not null,
+        networks varchar(43)[] not null,
+        unique (zone_id, name),
+        unique (id, zone_id)
+      );
+
+      -- records without a view are served to every client, the composite key
+      -- makes sure a record can only be assigned to a view of its own zone
+      alter table record
+        add column view_id uuid,
+        add foreign key (view_id, zone_id) references view (id, zone_id);
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        ALTER TABLE record DROP COLUMN view_id;
+        DROP TABLE view;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
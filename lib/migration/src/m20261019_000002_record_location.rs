@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- ISO 3166 country and GeoNames continent codes, records without
+      -- either are the default for clients elsewhere
+      alter table record
+        add column country   varchar(2),
+        add column continent varchar(2);
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        ALTER TABLE record DROP COLUMN country;
+        ALTER TABLE record DROP COLUMN continent;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
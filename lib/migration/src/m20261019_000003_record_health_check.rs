@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- records of the same name and type are answered by weight as soon as
+      -- one of them has a weight, records without one count as 1
+      alter table record
+        add column weight integer check (weight >= 0);
+
+      create table record_health_check(
+        id             uuid         not null primary key references record (id),
+        protocol       varchar(4)   not null check (protocol in ('http', 'tcp')),
+        port           integer      not null check (port between 1 and 65535),
+        path           varchar(255),
+        check_interval integer      not null default 30 check (check_interval > 0),
+        healthy        boolean      not null default true,
+        failures       integer      not null default 0,
+        checked        timestamptz
+      );
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        DROP TABLE record_health_check;
+        ALTER TABLE record DROP COLUMN weight;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- zones mastered elsewhere are transferred from their primary server
+      -- instead of being served from the record tables
+      alter table zone
+        add column primary_server varchar(255);
+
+      -- the last transferred copy of a secondary zone, records are stored in
+      -- wire format
+      create table zone_transfer(
+        zone_id   uuid        not null primary key references zone (id),
+        serial    int8        not null,
+        records   bytea       not null,
+        refreshed timestamptz not null
+      );
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        DROP TABLE zone_transfer;
+        ALTER TABLE zone DROP COLUMN primary_server;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- single row tracking when the set of zones last changed, the catalog
+      -- zone derives its serial from it, so deleted zones are noticed too
+      create table zone_catalog(
+        id      boolean     not null primary key default true check (id),
+        updated timestamptz not null             default now()
+      );
+
+      insert into zone_catalog default values;
+
+      -- every change moves the serial by at least a second
+      create function touch_zone_catalog() returns trigger as $$
+      begin
+        update zone_catalog set updated = greatest(now(), updated + interval '1 second');
+        return null;
+      end;
+      $$ language plpgsql;
+
+      create trigger zone_catalog_touch
+        after insert or delete or update of name, verified, primary_server on zone
+        for each statement execute function touch_zone_catalog();
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        DROP TRIGGER zone_catalog_touch ON zone;
+        DROP FUNCTION touch_zone_catalog;
+        DROP TABLE zone_catalog;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- settings left empty fall back to the defaults of the name server
+      alter table zone
+        add column description varchar(255),
+        add column default_ttl integer check (default_ttl > 0),
+        add column soa_mname   varchar(255),
+        add column soa_rname   varchar(255),
+        add column soa_refresh integer check (soa_refresh > 0),
+        add column soa_retry   integer check (soa_retry > 0),
+        add column soa_expire  integer check (soa_expire > 0),
+        add column soa_minimum integer check (soa_minimum >= 0),
+        -- soft deleted zones are no longer served and purged after a grace
+        -- period unless restored
+        add column deleted     timestamptz;
+
+      drop trigger zone_catalog_touch on zone;
+      create trigger zone_catalog_touch
+        after insert or delete or update of name, verified, primary_server, deleted on zone
+        for each statement execute function touch_zone_catalog();
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        DROP TRIGGER zone_catalog_touch ON zone;
+        CREATE TRIGGER zone_catalog_touch
+          AFTER INSERT OR DELETE OR UPDATE OF name, verified, primary_server ON zone
+          FOR EACH STATEMENT EXECUTE FUNCTION touch_zone_catalog();
+        ALTER TABLE zone
+          DROP COLUMN description,
+          DROP COLUMN default_ttl,
+          DROP COLUMN soa_mname,
+          DROP COLUMN soa_rname,
+          DROP COLUMN soa_refresh,
+          DROP COLUMN soa_retry,
+          DROP COLUMN soa_expire,
+          DROP COLUMN soa_minimum,
+          DROP COLUMN deleted;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
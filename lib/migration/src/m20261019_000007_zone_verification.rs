@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    db.execute_unprepared(
+      r#"
+      -- owners prove a zone is theirs by delegating it to our name servers or
+      -- publishing the token at _rabauken-challenge
+      alter table zone
+        add column verification_token   varchar(32),
+        add column verification_method  varchar(10) check (verification_method in ('delegation', 'txt')),
+        add column verified_at          timestamptz,
+        add column verification_checked timestamptz;
+
+      update zone set verification_token = md5(random()::text || id::text);
+      alter table zone alter column verification_token set not null;
+
+      -- names are only unique per owner, but a name is served for a single
+      -- one of them, the zone verified first keeps it
+      update zone set verified = false
+        where verified and deleted is null
+          and id not in (
+            select distinct on (lower(rtrim(name, '.'))) id from zone
+              where verified and deleted is null
+              order by lower(rtrim(name, '.')), created
+          );
+      create unique index zone_verified_name on zone (lower(rtrim(name, '.')))
+        where verified and deleted is null;
+    "#,
+    )
+    .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .get_connection()
+      .execute_unprepared(
+        r#"
+        DROP INDEX zone_verified_name;
+        ALTER TABLE zone
+          DROP COLUMN verification_token,
+          DROP COLUMN verification_method,
+          DROP COLUMN verified_at,
+          DROP COLUMN verification_checked;
+      "#,
+      )
+      .await?;
+
+    Ok(())
+  }
+}
@@ -41,7 +41,7 @@ impl SessionStore {
     redis::cmd("HSET")
       .arg(session_id)
       .arg(Inner { user_id, roles })
-      .query_async(conn.deref_mut())
+      .query_async::<_, ()>(conn.deref_mut())
       .await?;
 
     Ok(session_id)
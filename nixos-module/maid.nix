@@ -77,17 +77,28 @@ in
 
   config = lib.mkIf cfg.enable {
     systemd = {
+      # bound by systemd, so maid does not need CAP_NET_BIND_SERVICE
+      sockets = {
+        "maid" = {
+          wantedBy = [ "sockets.target" ];
+          listenStreams = [ "${cfg.host}:${toString cfg.port}" ];
+          listenDatagrams = [ "${cfg.host}:${toString cfg.port}" ];
+        };
+      };
+
       services = {
         "maid" = {
           enable = true;
           wantedBy = [ "multi-user.target" "network.target" ];
+          requires = [ "maid.socket" ];
 
           script = ''
-            exec ${pkgs.maid}/bin/maid --listend-addr ${cfg.host}:${toString cfg.port}&
+            exec ${pkgs.maid}/bin/maid
           '';
 
           environment = {
             "RUST_LOG" = "${cfg.log_level}";
+            "MAID_LOG_LEVEL" = "${cfg.log_level}";
             "RUST_BACKTRACE" = if (cfg.log_level == "info") then "0" else "1";
             "POSTGRES_HOST" = "${cfg.database.host}";
             "POSTGRES_PORT" = "${toString cfg.database.port}";
@@ -97,9 +108,11 @@ in
           };
 
           serviceConfig = {
-            Type = "forking";
+            Type = "simple";
             User = cfg.user;
             Restart = "always";
+            # rereads the config file, if there is one
+            ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
           };
         };
       };